@@ -0,0 +1,296 @@
+use std::f64::consts::PI;
+
+use crate::ast::{ArcDirection, Plane};
+use crate::bounds::BoundingBox;
+use crate::diagnostic::DiagnosticKind;
+use crate::point::Point;
+
+// Same epsilon as used by Grbl to detect full circles
+const ANGULAR_TRAVEL_EPSILON: f64 = 5e-7;
+
+// Grbl accepts a difference between start and end radius up to 0.005mm, or up to 0.1% of the radius as long as the
+// difference stays below 0.5mm
+const RADIUS_TOLERANCE: f64 = 0.005;
+const RADIUS_TOLERANCE_MAX: f64 = 0.5;
+const RADIUS_TOLERANCE_RELATIVE: f64 = 0.001;
+
+// Default chord tolerance in mm matching Grbl's default `$12` setting
+pub const DEFAULT_ARC_TOLERANCE: f64 = 0.002;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arc {
+    pub center: Point,
+    pub direction: ArcDirection,
+    pub plane: Plane,
+}
+
+impl Arc {
+    // Returns the indices of the two axes spanning the plane and the linear axis perpendicular to it
+    pub fn axes(plane: Plane) -> (usize, usize, usize) {
+        return match plane {
+            Plane::XY => (0, 1, 2),
+            Plane::ZX => (2, 0, 1),
+            Plane::YZ => (1, 2, 0),
+        };
+    }
+
+    // Calculates the center of an arc given in radius format. A negative radius selects the center resulting in an
+    // arc of more than 180 degrees.
+    pub fn from_radius(start: Point, end: Point, radius: f64, direction: ArcDirection, plane: Plane) -> Result<Self, DiagnosticKind> {
+        let (axis0, axis1, _) = Self::axes(plane);
+
+        let x = end[axis0] - start[axis0];
+        let y = end[axis1] - start[axis1];
+
+        // The center of a full circle is undefined by its radius
+        if x == 0.0 && y == 0.0 {
+            return Err(DiagnosticKind::ArcFullCircleRadius);
+        }
+
+        let h_x2_div_d = 4.0 * radius * radius - x * x - y * y;
+        if h_x2_div_d < 0.0 {
+            return Err(DiagnosticKind::ArcRadiusTooSmall);
+        }
+
+        // Distance of the center from the chord mid point relative to the chord length
+        let mut h = -h_x2_div_d.sqrt() / f64::hypot(x, y);
+        if direction == ArcDirection::CounterClockwise {
+            h = -h;
+        }
+        if radius < 0.0 {
+            h = -h;
+        }
+
+        let mut center = start;
+        center[axis0] = start[axis0] + 0.5 * (x - y * h);
+        center[axis1] = start[axis1] + 0.5 * (y + x * h);
+
+        return Ok(Self {
+            center,
+            direction,
+            plane,
+        });
+    }
+
+    // Checks that the end point lies on the circle described by the start point and the center
+    pub fn validate(&self, start: Point, end: Point) -> Result<(), DiagnosticKind> {
+        let start_radius = self.radius(start);
+        let end_radius = self.radius(end);
+
+        let delta = (end_radius - start_radius).abs();
+        if delta > RADIUS_TOLERANCE && (delta > RADIUS_TOLERANCE_MAX || delta > RADIUS_TOLERANCE_RELATIVE * start_radius) {
+            return Err(DiagnosticKind::ArcRadiusMismatch {
+                start: start_radius,
+                end: end_radius,
+            });
+        }
+
+        return Ok(());
+    }
+
+    pub fn radius(&self, start: Point) -> f64 {
+        let (axis0, axis1, _) = Self::axes(self.plane);
+
+        return f64::hypot(start[axis0] - self.center[axis0],
+                          start[axis1] - self.center[axis1]);
+    }
+
+    // Signed angle swept from start to end, negative for clockwise arcs. Coinciding start and end points describe a
+    // full circle.
+    pub fn angular_travel(&self, start: Point, end: Point) -> f64 {
+        let (axis0, axis1, _) = Self::axes(self.plane);
+
+        let r0 = start[axis0] - self.center[axis0];
+        let r1 = start[axis1] - self.center[axis1];
+        let t0 = end[axis0] - self.center[axis0];
+        let t1 = end[axis1] - self.center[axis1];
+
+        let angular_travel = f64::atan2(r0 * t1 - r1 * t0, r0 * t0 + r1 * t1);
+
+        return match self.direction {
+            ArcDirection::Clockwise if angular_travel >= -ANGULAR_TRAVEL_EPSILON => angular_travel - 2.0 * PI,
+            ArcDirection::CounterClockwise if angular_travel <= ANGULAR_TRAVEL_EPSILON => angular_travel + 2.0 * PI,
+            _ => angular_travel,
+        };
+    }
+
+    // Length of the helical path from start to end
+    pub fn length(&self, start: Point, end: Point) -> f64 {
+        let (_, _, linear) = Self::axes(self.plane);
+
+        return f64::hypot(self.angular_travel(start, end).abs() * self.radius(start),
+                          end[linear] - start[linear]);
+    }
+
+    // Exact bounding box of the arc including the extreme points of the circle passed on the way
+    pub fn bounds(&self, start: Point, end: Point) -> BoundingBox {
+        let (axis0, axis1, _) = Self::axes(self.plane);
+
+        let mut bounds = BoundingBox::new(start);
+        bounds.extend(end);
+
+        let radius = self.radius(start);
+        let angular_travel = self.angular_travel(start, end);
+
+        let start_angle = f64::atan2(start[axis1] - self.center[axis1],
+                                     start[axis0] - self.center[axis0]);
+
+        for quadrant in 0..4 {
+            let angle = f64::from(quadrant) * PI / 2.0;
+
+            // Angle to sweep from the start point in direction of travel to reach the extreme point
+            let delta = if angular_travel > 0.0 {
+                (angle - start_angle).rem_euclid(2.0 * PI)
+            } else {
+                (start_angle - angle).rem_euclid(2.0 * PI)
+            };
+
+            if delta <= angular_travel.abs() {
+                // The linear axis is already covered by start and end point
+                let mut point = start;
+                point[axis0] = self.center[axis0] + radius * angle.cos();
+                point[axis1] = self.center[axis1] + radius * angle.sin();
+
+                bounds.extend(point);
+            }
+        }
+
+        return bounds;
+    }
+
+    // Approximates the arc by line segments deviating at most `tolerance` from the true arc. The returned points
+    // exclude the start point and end exactly at the end point.
+    pub fn linearize(&self, start: Point, end: Point, tolerance: f64) -> Vec<Point> {
+        let (axis0, axis1, linear) = Self::axes(self.plane);
+
+        let radius = self.radius(start);
+        let angular_travel = self.angular_travel(start, end);
+
+        // Number of segments keeping the chord height below the tolerance. Other than Grbl this rounds up to guarantee
+        // the tolerance.
+        let segments = if tolerance > 0.0 && tolerance < radius {
+            ((0.5 * angular_travel * radius).abs() / (tolerance * (2.0 * radius - tolerance)).sqrt()).ceil() as usize
+        } else {
+            0
+        };
+
+        let mut points = Vec::with_capacity(segments + 1);
+
+        if segments > 1 {
+            let start_angle = f64::atan2(start[axis1] - self.center[axis1],
+                                         start[axis0] - self.center[axis0]);
+
+            let theta = angular_travel / segments as f64;
+            let linear_step = (end[linear] - start[linear]) / segments as f64;
+
+            for i in 1..segments {
+                let angle = start_angle + theta * i as f64;
+
+                let mut point = start;
+                point[axis0] = self.center[axis0] + radius * angle.cos();
+                point[axis1] = self.center[axis1] + radius * angle.sin();
+                point[linear] = start[linear] + linear_step * i as f64;
+
+                points.push(point);
+            }
+        }
+
+        points.push(end);
+
+        return points;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_angular_travel() {
+        let arc = Arc {
+            center: Point::zero(),
+            direction: ArcDirection::CounterClockwise,
+            plane: Plane::XY,
+        };
+
+        let start = Point::new(1.0, 0.0, 0.0);
+
+        assert!((arc.angular_travel(start, Point::new(0.0, 1.0, 0.0)) - PI / 2.0).abs() < 1e-9);
+        assert!((arc.angular_travel(start, start) - 2.0 * PI).abs() < 1e-9);
+
+        let arc = Arc { direction: ArcDirection::Clockwise, ..arc };
+        assert!((arc.angular_travel(start, Point::new(0.0, 1.0, 0.0)) + 3.0 * PI / 2.0).abs() < 1e-9);
+        assert!((arc.length(start, Point::new(0.0, 1.0, 0.0)) - 3.0 * PI / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_from_radius() {
+        let start = Point::new(0.0, 0.0, 0.0);
+        let end = Point::new(10.0, 0.0, 0.0);
+
+        let arc = Arc::from_radius(start, end, 5.0, ArcDirection::Clockwise, Plane::XY).unwrap();
+        assert_eq!(arc.center, Point::new(5.0, 0.0, 0.0));
+
+        let arc = Arc::from_radius(start, end, 10.0, ArcDirection::Clockwise, Plane::XY).unwrap();
+        assert!(arc.center.distance(Point::new(5.0, -75f64.sqrt(), 0.0)) < 1e-9);
+        assert!(arc.angular_travel(start, end).abs() < PI);
+
+        let arc = Arc::from_radius(start, end, -10.0, ArcDirection::Clockwise, Plane::XY).unwrap();
+        assert!(arc.center.distance(Point::new(5.0, 75f64.sqrt(), 0.0)) < 1e-9);
+        assert!(arc.angular_travel(start, end).abs() > PI);
+
+        assert_eq!(Arc::from_radius(start, end, 4.0, ArcDirection::Clockwise, Plane::XY),
+                   Err(DiagnosticKind::ArcRadiusTooSmall));
+        assert_eq!(Arc::from_radius(start, Point::new(0.0, 0.0, 5.0), 4.0, ArcDirection::Clockwise, Plane::XY),
+                   Err(DiagnosticKind::ArcFullCircleRadius));
+    }
+
+    #[test]
+    fn test_validate() {
+        let arc = Arc {
+            center: Point::zero(),
+            direction: ArcDirection::CounterClockwise,
+            plane: Plane::XY,
+        };
+
+        let start = Point::new(10.0, 0.0, 0.0);
+
+        assert!(arc.validate(start, Point::new(0.0, 10.004, 0.0)).is_ok());
+        assert!(arc.validate(start, Point::new(0.0, 10.009, 0.0)).is_ok());
+        assert!(arc.validate(start, Point::new(0.0, 10.011, 0.0)).is_err());
+
+        let start = Point::new(1000.0, 0.0, 0.0);
+        assert!(arc.validate(start, Point::new(0.0, 1000.4, 0.0)).is_ok());
+        assert!(arc.validate(start, Point::new(0.0, 1000.6, 0.0)).is_err());
+    }
+
+    #[test]
+    fn test_linearize() {
+        let arc = Arc {
+            center: Point::zero(),
+            direction: ArcDirection::Clockwise,
+            plane: Plane::XY,
+        };
+
+        let start = Point::new(10.0, 0.0, 0.0);
+        let end = Point::new(0.0, -10.0, -5.0);
+
+        let points = arc.linearize(start, end, 0.01);
+
+        assert_eq!(points.last(), Some(&end));
+        assert!(points.len() > 10);
+
+        let mut previous = start;
+        for point in points.iter() {
+            // Every chord mid point stays within tolerance of the circle
+            let mid = (previous + *point) / 2.0;
+            assert!(10.0 - f64::hypot(mid.x, mid.y) <= 0.01 + 1e-9);
+            assert!(point.y <= 0.0 && point.x >= 0.0);
+            assert!(point.z <= previous.z);
+
+            previous = *point;
+        }
+
+        assert_eq!(arc.linearize(start, end, 20.0), vec![end]);
+    }
+}
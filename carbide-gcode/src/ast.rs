@@ -0,0 +1,240 @@
+use std::fmt;
+
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModalGroup {
+    NonModal,
+    Motion,
+    Plane,
+    Distance,
+    ArcDistance,
+    FeedMode,
+    Units,
+    CutterCompensation,
+    ToolLengthOffset,
+    CoordinateSystem,
+    Stopping,
+    ToolChange,
+    Spindle,
+    Coolant,
+}
+
+impl fmt::Display for ModalGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return f.write_str(match self {
+            ModalGroup::NonModal => "non-modal",
+            ModalGroup::Motion => "motion",
+            ModalGroup::Plane => "plane selection",
+            ModalGroup::Distance => "distance mode",
+            ModalGroup::ArcDistance => "arc distance mode",
+            ModalGroup::FeedMode => "feed rate mode",
+            ModalGroup::Units => "units",
+            ModalGroup::CutterCompensation => "cutter compensation",
+            ModalGroup::ToolLengthOffset => "tool length offset",
+            ModalGroup::CoordinateSystem => "coordinate system",
+            ModalGroup::Stopping => "stopping",
+            ModalGroup::ToolChange => "tool change",
+            ModalGroup::Spindle => "spindle",
+            ModalGroup::Coolant => "coolant",
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArcDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    // G38.2 and G38.3 probe toward the workpiece, G38.4 and G38.5 away from it
+    pub toward: bool,
+
+    // G38.2 and G38.4 raise an alarm if the probe does not trigger
+    pub signal_failure: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Rapid,
+    Linear,
+    Arc(ArcDirection),
+    Probe(Probe),
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plane {
+    XY,
+    ZX,
+    YZ,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMode {
+    Absolute,
+    Incremental,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedMode {
+    InverseTime,
+    UnitsPerMinute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    Inches,
+    Millimeters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CutterCompensation {
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolLengthOffset {
+    Dynamic,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    G54,
+    G55,
+    G56,
+    G57,
+    G58,
+    G59,
+}
+
+impl CoordinateSystem {
+    pub const ALL: [CoordinateSystem; 6] = [
+        CoordinateSystem::G54,
+        CoordinateSystem::G55,
+        CoordinateSystem::G56,
+        CoordinateSystem::G57,
+        CoordinateSystem::G58,
+        CoordinateSystem::G59,
+    ];
+
+    pub fn index(&self) -> usize {
+        return *self as usize;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonModal {
+    Dwell,
+    SetCoordinateData,
+    GoHome,
+    SetHome,
+    GoSecondaryHome,
+    SetSecondaryHome,
+    MachineCoordinates,
+    SetOffset,
+    ResetOffset,
+}
+
+impl NonModal {
+    // Whether the axis words of the block belong to this command instead of a motion
+    pub fn uses_axis_words(&self) -> bool {
+        return matches!(self,
+            NonModal::SetCoordinateData | NonModal::GoHome | NonModal::GoSecondaryHome | NonModal::SetOffset);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Pause,
+    OptionalPause,
+    End,
+    EndAndRewind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spindle {
+    Clockwise,
+    CounterClockwise,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coolant {
+    Mist,
+    Flood,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Axes {
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub z: Option<f64>,
+}
+
+impl Axes {
+    pub fn is_empty(&self) -> bool {
+        return self.x.is_none() && self.y.is_none() && self.z.is_none();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ArcOffsets {
+    pub i: Option<f64>,
+    pub j: Option<f64>,
+    pub k: Option<f64>,
+}
+
+impl ArcOffsets {
+    pub fn is_empty(&self) -> bool {
+        return self.i.is_none() && self.j.is_none() && self.k.is_none();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+    // 1-based line in the source and the span of the whole line
+    pub line: usize,
+    pub span: Span,
+
+    pub block_delete: bool,
+    pub line_number: Option<u32>,
+
+    pub non_modal: Option<NonModal>,
+    pub motion: Option<Motion>,
+    pub plane: Option<Plane>,
+    pub distance: Option<DistanceMode>,
+    pub arc_distance: Option<DistanceMode>,
+    pub feed_mode: Option<FeedMode>,
+    pub units: Option<Units>,
+    pub cutter_compensation: Option<CutterCompensation>,
+    pub tool_length_offset: Option<ToolLengthOffset>,
+    pub coordinate_system: Option<CoordinateSystem>,
+
+    pub stop: Option<Stop>,
+    pub tool_change: bool,
+    pub spindle: Option<Spindle>,
+    pub coolant: Option<Coolant>,
+
+    pub feed: Option<f64>,
+    pub speed: Option<f64>,
+    pub tool: Option<u32>,
+
+    pub axes: Axes,
+    pub offsets: ArcOffsets,
+    pub radius: Option<f64>,
+    pub p: Option<f64>,
+    pub l: Option<u32>,
+}
+
+impl Block {
+    // Whether the axis words of the block are consumed by a command other than the motion
+    pub fn has_axis_command(&self) -> bool {
+        return matches!(self.non_modal, Some(non_modal) if non_modal.uses_axis_words())
+            || self.tool_length_offset == Some(ToolLengthOffset::Dynamic);
+    }
+}
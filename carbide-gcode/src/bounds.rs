@@ -0,0 +1,88 @@
+use crate::interpreter::Move;
+use crate::point::Point;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    pub fn new(point: Point) -> Self {
+        return Self {
+            min: point,
+            max: point,
+        };
+    }
+
+    // Bounding box of all the given moves or `None` if there are no moves
+    pub fn of<'a>(moves: impl IntoIterator<Item=&'a Move>) -> Option<Self> {
+        return moves.into_iter()
+            .map(Move::bounds)
+            .fold(None, |bounds: Option<Self>, other| match bounds {
+                Some(bounds) => Some(bounds.union(other)),
+                None => Some(other),
+            });
+    }
+
+    pub fn extend(&mut self, point: Point) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        return Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        };
+    }
+
+    pub fn size(&self) -> Point {
+        return self.max - self.min;
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        return (0..3).all(|axis| self.min[axis] <= other.min[axis] && other.max[axis] <= self.max[axis]);
+    }
+}
+
+impl From<BoundingBox> for (Point, Point) {
+    fn from(bounds: BoundingBox) -> Self {
+        return (bounds.min, bounds.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_bounds_of_program() {
+        let program = parse("G0 X5 Y5\nG1 Z-3 F100\nG2 X15 I5\nG0 Z10");
+        let (moves, _) = Interpreter::new().run(&program);
+
+        let bounds = BoundingBox::of(moves.iter()).unwrap();
+
+        assert_eq!(bounds.min, Point::new(0.0, 0.0, -3.0));
+        assert_eq!(bounds.max, Point::new(15.0, 10.0, 10.0));
+        assert!(bounds.contains(&BoundingBox::new(Point::new(1.0, 1.0, 1.0))));
+        assert!(!bounds.contains(&BoundingBox::new(Point::new(1.0, -1.0, 1.0))));
+    }
+
+    #[test]
+    fn test_bounds_of_arcs() {
+        let program = parse("G0 X10 Y0\nG2 X0 Y-10 I-10 F100\nG18 G3 X-10 Z10 I0 K10");
+        let (moves, _) = Interpreter::new().run(&program);
+
+        let round = |p: Point| ((p.x * 1e6).round() / 1e6, (p.y * 1e6).round() / 1e6, (p.z * 1e6).round() / 1e6);
+
+        assert_eq!(round(moves[1].bounds().min), (0.0, -10.0, 0.0));
+        assert_eq!(round(moves[1].bounds().max), (10.0, 0.0, 0.0));
+        assert_eq!(round(moves[2].bounds().min), (-10.0, -10.0, 0.0));
+        assert_eq!(round(moves[2].bounds().max), (0.0, -10.0, 10.0));
+
+        assert_eq!(BoundingBox::of(Vec::new().iter()), None);
+    }
+}
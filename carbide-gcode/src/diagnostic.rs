@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+
+use crate::ast::ModalGroup;
+use crate::lexer::{LexErrorKind, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    Lex(LexErrorKind),
+    UnsupportedGCode(f64),
+    UnsupportedMCode(f64),
+    UnsupportedWord(char),
+    InvalidValue(char),
+    DuplicateWord(char),
+    ModalGroupConflict(ModalGroup),
+    AxisCommandConflict,
+    MisplacedBlockDelete,
+    MisplacedLineNumber,
+    MisplacedChecksum,
+    ChecksumMismatch { expected: u8, actual: u8 },
+    MissingValue(char),
+    MissingFeedRate,
+    MissingAxisWords,
+    MissingArcParameters,
+    UnusedAxisWords,
+    MachineCoordinatesMotion,
+    ArcRadiusMismatch { start: f64, end: f64 },
+    ArcRadiusTooSmall,
+    ArcFullCircleRadius,
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            DiagnosticKind::Lex(kind) => write!(f, "{}", kind),
+            DiagnosticKind::UnsupportedGCode(code) => write!(f, "unsupported command G{}", code),
+            DiagnosticKind::UnsupportedMCode(code) => write!(f, "unsupported command M{}", code),
+            DiagnosticKind::UnsupportedWord(letter) => write!(f, "unsupported word '{}'", letter),
+            DiagnosticKind::InvalidValue(letter) => write!(f, "invalid value for word '{}'", letter),
+            DiagnosticKind::DuplicateWord(letter) => write!(f, "word '{}' repeated in block", letter),
+            DiagnosticKind::ModalGroupConflict(group) => write!(f, "multiple commands of {} modal group in block", group),
+            DiagnosticKind::AxisCommandConflict => write!(f, "motion and non-modal command both use axis words"),
+            DiagnosticKind::MisplacedBlockDelete => write!(f, "block delete must start the line"),
+            DiagnosticKind::MisplacedLineNumber => write!(f, "line number must precede all words"),
+            DiagnosticKind::MisplacedChecksum => write!(f, "checksum must end the line"),
+            DiagnosticKind::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch: expected {}, got {}", expected, actual),
+            DiagnosticKind::MissingValue(letter) => write!(f, "command requires word '{}'", letter),
+            DiagnosticKind::MissingFeedRate => write!(f, "feed rate undefined"),
+            DiagnosticKind::MissingAxisWords => write!(f, "command requires axis words in the selected plane"),
+            DiagnosticKind::MissingArcParameters => write!(f, "arc requires offsets or radius in the selected plane"),
+            DiagnosticKind::UnusedAxisWords => write!(f, "axis words without motion command"),
+            DiagnosticKind::MachineCoordinatesMotion => write!(f, "G53 requires G0 or G1 motion"),
+            DiagnosticKind::ArcRadiusMismatch { start, end } => write!(f, "arc radius at end point ({:.4}mm) does not match start point ({:.4}mm)", end, start),
+            DiagnosticKind::ArcRadiusTooSmall => write!(f, "arc radius too small to reach end point"),
+            DiagnosticKind::ArcFullCircleRadius => write!(f, "full circle arcs can not be defined by radius"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+
+    // 1-based line in the source and the span pointing at the offending text
+    pub line: usize,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(kind: DiagnosticKind, line: usize, span: Span) -> Self {
+        return Self { severity: Severity::Error, kind, line, span };
+    }
+
+    pub fn warning(kind: DiagnosticKind, line: usize, span: Span) -> Self {
+        return Self { severity: Severity::Warning, kind, line, span };
+    }
+
+    pub fn is_error(&self) -> bool {
+        return self.severity == Severity::Error;
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        return write!(f, "line {}: {}: {}", self.line, severity, self.kind);
+    }
+}
+
+impl error::Error for Diagnostic {}
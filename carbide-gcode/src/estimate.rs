@@ -0,0 +1,289 @@
+use crate::arc::DEFAULT_ARC_TOLERANCE;
+use crate::interpreter::{Move, MoveKind};
+use crate::point::Point;
+
+// Direction changes closer than this to a full reversal or a straight line are treated as such, same as Grbl does
+const JUNCTION_COS_EPSILON: f64 = 0.999999;
+
+// Motion limits of the machine as configured in Grbl
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MachineModel {
+    // Per axis maximum rate in mm/min (`$110` - `$112`)
+    pub max_rate: Point,
+
+    // Per axis acceleration in mm/sec^2 (`$120` - `$122`)
+    pub acceleration: Point,
+
+    // Junction deviation in mm (`$11`)
+    pub junction_deviation: f64,
+
+    // Arc tolerance in mm (`$12`)
+    pub arc_tolerance: f64,
+}
+
+impl Default for MachineModel {
+    // Grbl's default settings
+    fn default() -> Self {
+        return Self {
+            max_rate: Point::new(500.0, 500.0, 500.0),
+            acceleration: Point::new(10.0, 10.0, 10.0),
+            junction_deviation: 0.01,
+            arc_tolerance: DEFAULT_ARC_TOLERANCE,
+        };
+    }
+}
+
+// Estimated duration of a program in seconds
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Estimate {
+    // Duration of each move, in the same order as the estimated moves
+    pub durations: Vec<f64>,
+
+    // Line of each move and the time elapsed before the move starts
+    starts: Vec<(usize, f64)>,
+
+    pub total: f64,
+}
+
+impl Estimate {
+    // Time elapsed until execution reaches the given line
+    pub fn elapsed(&self, line: usize) -> f64 {
+        return self.starts.iter()
+            .find(|&&(start, _)| start >= line)
+            .map_or(self.total, |&(_, elapsed)| elapsed);
+    }
+
+    // Time remaining from the start of the given line until the end of the program
+    pub fn remaining(&self, line: usize) -> f64 {
+        return self.total - self.elapsed(line);
+    }
+}
+
+// A straight piece of motion as seen by the planner
+struct Segment {
+    // Index of the move this segment belongs to
+    index: usize,
+
+    length: f64,
+    unit: Point,
+
+    // Nominal speed in mm/sec and acceleration in mm/sec^2 along the segment
+    nominal: f64,
+    acceleration: f64,
+
+    // Maximum speed allowed at the junction entering this segment
+    max_entry: f64,
+
+    // Motion comes to a full stop at the end of this segment
+    stop: bool,
+}
+
+// Limits a vector of per axis values to the maximum possible along the given direction
+fn limit_by_axis(limits: Point, unit: Point) -> f64 {
+    return (0..3)
+        .filter(|&axis| unit[axis] != 0.0)
+        .map(|axis| (limits[axis] / unit[axis]).abs())
+        .fold(f64::INFINITY, f64::min);
+}
+
+// Time to travel the given distance starting at `entry` and ending at `exit` speed while never exceeding `nominal`
+fn trapezoid(length: f64, entry: f64, exit: f64, nominal: f64, acceleration: f64) -> f64 {
+    let accelerate = (nominal * nominal - entry * entry) / (2.0 * acceleration);
+    let decelerate = (nominal * nominal - exit * exit) / (2.0 * acceleration);
+
+    if accelerate + decelerate <= length {
+        return (nominal - entry) / acceleration
+            + (length - accelerate - decelerate) / nominal
+            + (nominal - exit) / acceleration;
+    }
+
+    // Nominal speed is never reached, so the profile degrades to a triangle
+    let peak = ((2.0 * acceleration * length + entry * entry + exit * exit) / 2.0).sqrt();
+
+    return (peak - entry) / acceleration + (peak - exit) / acceleration;
+}
+
+// Estimates the execution time of the given moves by modeling Grbl's planner: arcs are split into segments, junction
+// speeds are limited by the junction deviation and every segment follows a trapezoidal velocity profile.
+pub fn estimate(moves: &[Move], machine: &MachineModel) -> Estimate {
+    let max_rate = machine.max_rate / 60.0;
+
+    let mut durations = vec![0.0; moves.len()];
+    let mut segments: Vec<Segment> = Vec::new();
+
+    for (index, m) in moves.iter().enumerate() {
+        let feed = match m.kind {
+            MoveKind::Rapid => f64::INFINITY,
+            MoveKind::Dwell(seconds) => {
+                // Grbl waits for all motion to stop before dwelling
+                if let Some(last) = segments.last_mut() {
+                    last.stop = true;
+                }
+
+                durations[index] += seconds;
+                continue;
+            }
+            _ if m.feed > 0.0 => m.feed / 60.0,
+            _ => f64::INFINITY,
+        };
+
+        let mut start = m.start;
+        for end in m.linearize(machine.arc_tolerance) {
+            let length = start.distance(end);
+            if length == 0.0 {
+                continue;
+            }
+
+            let unit = (end - start) / length;
+
+            let nominal = f64::min(feed, limit_by_axis(max_rate, unit));
+            let acceleration = limit_by_axis(machine.acceleration, unit);
+
+            let max_entry = match segments.last() {
+                Some(previous) if !previous.stop => {
+                    let cos_theta = -(previous.unit.x * unit.x + previous.unit.y * unit.y + previous.unit.z * unit.z);
+
+                    let junction = if cos_theta > JUNCTION_COS_EPSILON {
+                        // Full reversal
+                        0.0
+                    } else if cos_theta < -JUNCTION_COS_EPSILON {
+                        // Straight continuation
+                        f64::INFINITY
+                    } else {
+                        let direction = unit - previous.unit;
+                        let acceleration = limit_by_axis(machine.acceleration, direction / direction.length());
+
+                        let sin_theta_d2 = (0.5 * (1.0 - cos_theta)).sqrt();
+                        (acceleration * machine.junction_deviation * sin_theta_d2 / (1.0 - sin_theta_d2)).sqrt()
+                    };
+
+                    f64::min(junction, f64::min(previous.nominal, nominal))
+                }
+                _ => 0.0,
+            };
+
+            segments.push(Segment {
+                index,
+                length,
+                unit,
+                nominal,
+                acceleration,
+                max_entry,
+                stop: false,
+            });
+
+            start = end;
+        }
+
+        // Probing cycles wait for the probe motion to finish
+        if let MoveKind::Probe(_) = m.kind {
+            if let Some(last) = segments.last_mut() {
+                last.stop = true;
+            }
+        }
+    }
+
+    // Backward pass: limit entry speeds so that every segment can decelerate to the entry speed of its successor
+    let mut entries = vec![0.0; segments.len()];
+    let mut exit = 0.0;
+    for (i, segment) in segments.iter().enumerate().rev() {
+        if segment.stop {
+            exit = 0.0;
+        }
+
+        entries[i] = f64::min(segment.max_entry, (exit * exit + 2.0 * segment.acceleration * segment.length).sqrt());
+        exit = entries[i];
+    }
+
+    // Forward pass: limit entry speeds to what can be reached by accelerating through the predecessor
+    for i in 1..segments.len() {
+        let previous = &segments[i - 1];
+        if !previous.stop {
+            let reachable = (entries[i - 1] * entries[i - 1] + 2.0 * previous.acceleration * previous.length).sqrt();
+            entries[i] = f64::min(entries[i], reachable);
+        }
+    }
+
+    for (i, segment) in segments.iter().enumerate() {
+        let exit = if segment.stop || i + 1 == segments.len() {
+            0.0
+        } else {
+            entries[i + 1]
+        };
+
+        durations[segment.index] += trapezoid(segment.length, entries[i], exit, segment.nominal, segment.acceleration);
+    }
+
+    let mut total = 0.0;
+    let mut starts = Vec::with_capacity(moves.len());
+    for (m, duration) in moves.iter().zip(durations.iter()) {
+        starts.push((m.line, total));
+        total += duration;
+    }
+
+    return Estimate {
+        durations,
+        starts,
+        total,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::parser::parse;
+
+    fn run(source: &str, machine: &MachineModel) -> Estimate {
+        let (moves, diagnostics) = Interpreter::new().run(&parse(source));
+        assert!(diagnostics.is_empty());
+
+        return estimate(&moves, machine);
+    }
+
+    #[test]
+    fn test_trapezoid() {
+        let machine = MachineModel {
+            max_rate: Point::new(6000.0, 6000.0, 6000.0),
+            acceleration: Point::new(100.0, 100.0, 100.0),
+            ..MachineModel::default()
+        };
+
+        // Accelerates to 100mm/sec within exactly 50mm and brakes again
+        assert!((run("G1 X100 F6000", &machine).total - 2.0).abs() < 1e-9);
+
+        // Cruises at 100mm/sec for another 100mm
+        assert!((run("G1 X200 F6000", &machine).total - 3.0).abs() < 1e-9);
+
+        // Never reaches nominal speed
+        assert!((run("G1 X25 F6000", &machine).total - 1.0).abs() < 1e-9);
+
+        // Rapids are limited by the max rate of the axes
+        assert!((run("G0 X200", &machine).total - 3.0).abs() < 1e-9);
+
+        // Straight continuation does not slow down
+        assert!((run("G1 X100 F6000\nX200", &machine).total - 3.0).abs() < 1e-9);
+
+        // A corner requires slowing down and a reversal a full stop
+        let corner = run("G1 X100 F6000\nY100", &machine).total;
+        assert!(corner > 3.0 && corner < 4.0);
+        assert!((run("G1 X100 F6000\nX0", &machine).total - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_remaining() {
+        let machine = MachineModel {
+            max_rate: Point::new(6000.0, 6000.0, 6000.0),
+            acceleration: Point::new(100.0, 100.0, 100.0),
+            ..MachineModel::default()
+        };
+
+        let estimate = run("G1 X100 F6000\nG4 P1.5\nG1 X0", &machine);
+
+        assert_eq!(estimate.durations.len(), 3);
+        assert!((estimate.total - 5.5).abs() < 1e-9);
+        assert!((estimate.remaining(2) - 3.5).abs() < 1e-9);
+        assert!((estimate.remaining(3) - 2.0).abs() < 1e-9);
+        assert_eq!(estimate.remaining(4), 0.0);
+    }
+}
@@ -0,0 +1,700 @@
+use crate::arc::Arc;
+use crate::ast::*;
+use crate::bounds::BoundingBox;
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::parser::Program;
+use crate::point::Point;
+
+pub const MILLIMETERS_PER_INCH: f64 = 25.4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModalState {
+    pub motion: Motion,
+    pub plane: Plane,
+    pub distance: DistanceMode,
+    pub arc_distance: DistanceMode,
+    pub feed_mode: FeedMode,
+    pub units: Units,
+    pub coordinate_system: CoordinateSystem,
+    pub tool_length_offset: ToolLengthOffset,
+
+    pub spindle: Spindle,
+    pub flood_coolant: bool,
+    pub mist_coolant: bool,
+
+    // Feed rate and spindle speed as programmed, i.e. in the active units and feed mode
+    pub tool: u32,
+    pub feed: f64,
+    pub speed: f64,
+}
+
+impl Default for ModalState {
+    fn default() -> Self {
+        return Self {
+            motion: Motion::Rapid,
+            plane: Plane::XY,
+            distance: DistanceMode::Absolute,
+            arc_distance: DistanceMode::Incremental,
+            feed_mode: FeedMode::UnitsPerMinute,
+            units: Units::Millimeters,
+            coordinate_system: CoordinateSystem::G54,
+            tool_length_offset: ToolLengthOffset::Cancel,
+            spindle: Spindle::Off,
+            flood_coolant: false,
+            mist_coolant: false,
+            tool: 0,
+            feed: 0.0,
+            speed: 0.0,
+        };
+    }
+}
+
+impl ModalState {
+    // Factor converting values in the active units to millimeters
+    pub fn unit_factor(&self) -> f64 {
+        return match self.units {
+            Units::Millimeters => 1.0,
+            Units::Inches => MILLIMETERS_PER_INCH,
+        };
+    }
+}
+
+// Coordinate offsets in machine coordinates and millimeters
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Offsets {
+    pub coordinate_systems: [Point; 6],
+    pub home: Point,
+    pub secondary_home: Point,
+    pub offset: Point,
+    pub tool_length: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveKind {
+    Rapid,
+    Linear,
+    Arc(Arc),
+    Probe(Probe),
+    Dwell(f64),
+}
+
+// A fully resolved move in absolute machine coordinates and millimeters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub line: usize,
+    pub kind: MoveKind,
+
+    pub start: Point,
+    pub end: Point,
+
+    // Feed rate in mm/min with inverse time already resolved, zero for rapids and dwells
+    pub feed: f64,
+}
+
+impl Move {
+    pub fn length(&self) -> f64 {
+        return match self.kind {
+            MoveKind::Arc(arc) => arc.length(self.start, self.end),
+            MoveKind::Dwell(_) => 0.0,
+            _ => self.start.distance(self.end),
+        };
+    }
+
+    pub fn bounds(&self) -> BoundingBox {
+        return match self.kind {
+            MoveKind::Arc(arc) => arc.bounds(self.start, self.end),
+            _ => {
+                let mut bounds = BoundingBox::new(self.start);
+                bounds.extend(self.end);
+                bounds
+            }
+        };
+    }
+
+    // Approximates the move by line segments, see `Arc::linearize`
+    pub fn linearize(&self, tolerance: f64) -> Vec<Point> {
+        return match self.kind {
+            MoveKind::Arc(arc) => arc.linearize(self.start, self.end, tolerance),
+            _ => vec![self.end],
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Interpreter {
+    state: ModalState,
+    offsets: Offsets,
+
+    // Current position in machine coordinates
+    position: Point,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn with_offsets(offsets: Offsets) -> Self {
+        return Self {
+            offsets,
+            ..Self::default()
+        };
+    }
+
+    pub fn state(&self) -> &ModalState {
+        return &self.state;
+    }
+
+    pub fn set_state(&mut self, state: ModalState) {
+        self.state = state;
+    }
+
+    pub fn offsets(&self) -> &Offsets {
+        return &self.offsets;
+    }
+
+    pub fn position(&self) -> Point {
+        return self.position;
+    }
+
+    pub fn set_position(&mut self, position: Point) {
+        self.position = position;
+    }
+
+    pub fn work_position(&self) -> Point {
+        return self.position - self.work_offset();
+    }
+
+    // Executes all blocks of the program collecting the resulting moves. Blocks failing to execute are reported and
+    // leave the state untouched, just like Grbl rejecting a line.
+    pub fn run(&mut self, program: &Program) -> (Vec<Move>, Vec<Diagnostic>) {
+        let mut moves = Vec::new();
+        let mut diagnostics = Vec::new();
+
+        for block in program.blocks.iter() {
+            if let Err(diagnostic) = self.execute(block, &mut moves) {
+                diagnostics.push(diagnostic);
+            }
+        }
+
+        return (moves, diagnostics);
+    }
+
+    pub fn execute(&mut self, block: &Block, moves: &mut Vec<Move>) -> Result<(), Diagnostic> {
+        let mut next = self.clone();
+        let len = moves.len();
+
+        return match next.apply(block, moves) {
+            Ok(()) => {
+                *self = next;
+                Ok(())
+            }
+            Err(kind) => {
+                moves.truncate(len);
+                Err(Diagnostic::error(kind, block.line, block.span))
+            }
+        };
+    }
+
+    fn work_offset(&self) -> Point {
+        return self.offsets.coordinate_systems[self.state.coordinate_system.index()]
+            + self.offsets.offset
+            + Point::new(0.0, 0.0, self.offsets.tool_length);
+    }
+
+    // Applies the block in the order of execution defined by RS274/NGC
+    fn apply(&mut self, block: &Block, moves: &mut Vec<Move>) -> Result<(), DiagnosticKind> {
+        // Units apply to all values of the block they are given in
+        if let Some(units) = block.units {
+            self.state.units = units;
+        }
+
+        if let Some(feed_mode) = block.feed_mode {
+            self.state.feed_mode = feed_mode;
+        }
+
+        // Inverse time feed rates are not modal and must be given with each motion
+        if self.state.feed_mode == FeedMode::InverseTime {
+            self.state.feed = 0.0;
+        }
+
+        if let Some(feed) = block.feed {
+            self.state.feed = feed;
+        }
+
+        if let Some(speed) = block.speed {
+            self.state.speed = speed;
+        }
+
+        if let Some(tool) = block.tool {
+            self.state.tool = tool;
+        }
+
+        if let Some(spindle) = block.spindle {
+            self.state.spindle = spindle;
+        }
+
+        match block.coolant {
+            Some(Coolant::Mist) => self.state.mist_coolant = true,
+            Some(Coolant::Flood) => self.state.flood_coolant = true,
+            Some(Coolant::Off) => {
+                self.state.mist_coolant = false;
+                self.state.flood_coolant = false;
+            }
+            None => {}
+        }
+
+        if let Some(NonModal::Dwell) = block.non_modal {
+            let seconds = block.p.ok_or(DiagnosticKind::MissingValue('P'))?;
+            if seconds < 0.0 {
+                return Err(DiagnosticKind::InvalidValue('P'));
+            }
+
+            moves.push(Move {
+                line: block.line,
+                kind: MoveKind::Dwell(seconds),
+                start: self.position,
+                end: self.position,
+                feed: 0.0,
+            });
+        }
+
+        if let Some(plane) = block.plane {
+            self.state.plane = plane;
+        }
+
+        if let Some(tool_length_offset) = block.tool_length_offset {
+            self.offsets.tool_length = match tool_length_offset {
+                ToolLengthOffset::Dynamic => block.axes.z.ok_or(DiagnosticKind::MissingValue('Z'))? * self.state.unit_factor(),
+                ToolLengthOffset::Cancel => 0.0,
+            };
+
+            self.state.tool_length_offset = tool_length_offset;
+        }
+
+        if let Some(coordinate_system) = block.coordinate_system {
+            self.state.coordinate_system = coordinate_system;
+        }
+
+        if let Some(distance) = block.distance {
+            self.state.distance = distance;
+        }
+
+        if let Some(arc_distance) = block.arc_distance {
+            self.state.arc_distance = arc_distance;
+        }
+
+        match block.non_modal {
+            Some(NonModal::SetCoordinateData) => self.set_coordinate_data(block)?,
+            Some(NonModal::GoHome) => self.go_home(block, self.offsets.home, moves),
+            Some(NonModal::SetHome) => self.offsets.home = self.position,
+            Some(NonModal::GoSecondaryHome) => self.go_home(block, self.offsets.secondary_home, moves),
+            Some(NonModal::SetSecondaryHome) => self.offsets.secondary_home = self.position,
+            Some(NonModal::SetOffset) => self.set_offset(block)?,
+            Some(NonModal::ResetOffset) => self.offsets.offset = Point::zero(),
+            _ => {}
+        }
+
+        if let Some(motion) = block.motion {
+            self.state.motion = motion;
+        }
+
+        let machine = block.non_modal == Some(NonModal::MachineCoordinates);
+        if machine && !matches!(self.state.motion, Motion::Rapid | Motion::Linear) {
+            return Err(DiagnosticKind::MachineCoordinatesMotion);
+        }
+
+        if !block.has_axis_command() && !block.axes.is_empty() {
+            self.motion(block, machine, moves)?;
+        }
+
+        // Program end resets the modal state the same way Grbl does
+        if let Some(Stop::End) | Some(Stop::EndAndRewind) = block.stop {
+            self.state = ModalState {
+                motion: Motion::Linear,
+                plane: Plane::XY,
+                distance: DistanceMode::Absolute,
+                feed_mode: FeedMode::UnitsPerMinute,
+                coordinate_system: CoordinateSystem::G54,
+                spindle: Spindle::Off,
+                flood_coolant: false,
+                mist_coolant: false,
+                ..self.state.clone()
+            };
+        }
+
+        return Ok(());
+    }
+
+    // Resolves the axis words of the block to a target in machine coordinates
+    fn target(&self, axes: &Axes, machine: bool) -> Point {
+        let factor = self.state.unit_factor();
+        let offset = self.work_offset();
+
+        let mut target = self.position;
+        for (axis, value) in [axes.x, axes.y, axes.z].iter().enumerate() {
+            if let Some(value) = value {
+                target[axis] = if machine {
+                    value * factor
+                } else {
+                    match self.state.distance {
+                        DistanceMode::Absolute => value * factor + offset[axis],
+                        DistanceMode::Incremental => self.position[axis] + value * factor,
+                    }
+                };
+            }
+        }
+
+        return target;
+    }
+
+    fn motion(&mut self, block: &Block, machine: bool, moves: &mut Vec<Move>) -> Result<(), DiagnosticKind> {
+        let start = self.position;
+        let end = self.target(&block.axes, machine);
+
+        let kind = match self.state.motion {
+            Motion::Rapid => MoveKind::Rapid,
+            Motion::Linear => MoveKind::Linear,
+            Motion::Arc(direction) => MoveKind::Arc(self.arc(block, direction, end)?),
+            Motion::Probe(probe) => MoveKind::Probe(probe),
+            Motion::Cancel => return Err(DiagnosticKind::UnusedAxisWords),
+        };
+
+        let mut motion = Move {
+            line: block.line,
+            kind,
+            start,
+            end,
+            feed: 0.0,
+        };
+
+        if kind != MoveKind::Rapid {
+            if self.state.feed <= 0.0 {
+                return Err(DiagnosticKind::MissingFeedRate);
+            }
+
+            motion.feed = match self.state.feed_mode {
+                FeedMode::UnitsPerMinute => self.state.feed * self.state.unit_factor(),
+                FeedMode::InverseTime => self.state.feed * motion.length(),
+            };
+        }
+
+        moves.push(motion);
+        self.position = end;
+
+        return Ok(());
+    }
+
+    fn arc(&self, block: &Block, direction: ArcDirection, end: Point) -> Result<Arc, DiagnosticKind> {
+        let (axis0, axis1, _) = Arc::axes(self.state.plane);
+
+        let axes = [block.axes.x, block.axes.y, block.axes.z];
+        if axes[axis0].is_none() && axes[axis1].is_none() {
+            return Err(DiagnosticKind::MissingAxisWords);
+        }
+
+        let factor = self.state.unit_factor();
+        let start = self.position;
+
+        if let Some(radius) = block.radius {
+            return Arc::from_radius(start, end, radius * factor, direction, self.state.plane);
+        }
+
+        let offsets = [block.offsets.i, block.offsets.j, block.offsets.k];
+        if offsets[axis0].is_none() && offsets[axis1].is_none() {
+            return Err(DiagnosticKind::MissingArcParameters);
+        }
+
+        let work_offset = self.work_offset();
+
+        let mut center = start;
+        for &axis in [axis0, axis1].iter() {
+            let offset = offsets[axis].unwrap_or(0.0) * factor;
+            center[axis] = match self.state.arc_distance {
+                DistanceMode::Incremental => start[axis] + offset,
+                DistanceMode::Absolute => work_offset[axis] + offset,
+            };
+        }
+
+        let arc = Arc {
+            center,
+            direction,
+            plane: self.state.plane,
+        };
+
+        arc.validate(start, end)?;
+
+        return Ok(arc);
+    }
+
+    fn set_coordinate_data(&mut self, block: &Block) -> Result<(), DiagnosticKind> {
+        let factor = self.state.unit_factor();
+
+        // P0 selects the active coordinate system, P1 to P6 select G54 to G59
+        let p = block.p.ok_or(DiagnosticKind::MissingValue('P'))?;
+        let system = if p == 0.0 {
+            self.state.coordinate_system.index()
+        } else if p.fract() == 0.0 && (1.0..=6.0).contains(&p) {
+            p as usize - 1
+        } else {
+            return Err(DiagnosticKind::InvalidValue('P'));
+        };
+
+        let mut data = self.offsets.coordinate_systems[system];
+        for (axis, value) in [block.axes.x, block.axes.y, block.axes.z].iter().enumerate() {
+            if let Some(value) = value {
+                data[axis] = match block.l.ok_or(DiagnosticKind::MissingValue('L'))? {
+                    2 => value * factor,
+                    20 => self.position[axis] - self.offsets.offset[axis] - value * factor
+                        - if axis == 2 { self.offsets.tool_length } else { 0.0 },
+                    _ => return Err(DiagnosticKind::InvalidValue('L')),
+                };
+            }
+        }
+
+        self.offsets.coordinate_systems[system] = data;
+
+        return Ok(());
+    }
+
+    fn set_offset(&mut self, block: &Block) -> Result<(), DiagnosticKind> {
+        if block.axes.is_empty() {
+            return Err(DiagnosticKind::MissingAxisWords);
+        }
+
+        let factor = self.state.unit_factor();
+        let system = self.offsets.coordinate_systems[self.state.coordinate_system.index()];
+
+        for (axis, value) in [block.axes.x, block.axes.y, block.axes.z].iter().enumerate() {
+            if let Some(value) = value {
+                self.offsets.offset[axis] = self.position[axis] - system[axis] - value * factor
+                    - if axis == 2 { self.offsets.tool_length } else { 0.0 };
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Rapid to the optional intermediate point and from there to the stored position. If axis words are given, only
+    // these axes move to the stored position.
+    fn go_home(&mut self, block: &Block, home: Point, moves: &mut Vec<Move>) {
+        let mut target = home;
+
+        if !block.axes.is_empty() {
+            let intermediate = self.target(&block.axes, block.non_modal == Some(NonModal::MachineCoordinates));
+
+            moves.push(Move {
+                line: block.line,
+                kind: MoveKind::Rapid,
+                start: self.position,
+                end: intermediate,
+                feed: 0.0,
+            });
+
+            self.position = intermediate;
+
+            for (axis, value) in [block.axes.x, block.axes.y, block.axes.z].iter().enumerate() {
+                if value.is_none() {
+                    target[axis] = self.position[axis];
+                }
+            }
+        }
+
+        moves.push(Move {
+            line: block.line,
+            kind: MoveKind::Rapid,
+            start: self.position,
+            end: target,
+            feed: 0.0,
+        });
+
+        self.position = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn run(source: &str) -> (Interpreter, Vec<Move>) {
+        let program = parse(source);
+        assert!(program.is_valid(), "{:?}", program.diagnostics);
+
+        let mut interpreter = Interpreter::new();
+        let (moves, diagnostics) = interpreter.run(&program);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        return (interpreter, moves);
+    }
+
+    fn ends(moves: &[Move]) -> Vec<(f64, f64, f64)> {
+        let round = |v: f64| (v * 1e6).round() / 1e6;
+
+        return moves.iter()
+            .map(|m| (round(m.end.x), round(m.end.y), round(m.end.z)))
+            .collect();
+    }
+
+    #[test]
+    fn test_units_and_distance_modes() {
+        let (interpreter, moves) = run("G20 G0 X1 Y2\nG21 G91 G1 X10 F100\nY-5 Z-1\nG90 G0 Z5");
+
+        assert_eq!(ends(&moves), vec![
+            (25.4, 50.8, 0.0),
+            (35.4, 50.8, 0.0),
+            (35.4, 45.8, -1.0),
+            (35.4, 45.8, 5.0),
+        ]);
+        assert_eq!(moves[1].feed, 100.0);
+        assert_eq!(interpreter.state().distance, DistanceMode::Absolute);
+        assert_eq!(interpreter.state().units, Units::Millimeters);
+    }
+
+    #[test]
+    fn test_inch_feed_rate() {
+        let (_, moves) = run("G20 G1 X1 F10");
+
+        assert_eq!(moves[0].feed, 254.0);
+    }
+
+    #[test]
+    fn test_inverse_time_feed_rate() {
+        let (_, moves) = run("G93 G1 X10 F2\nG1 X20 F0.5");
+
+        assert_eq!(moves[0].feed, 20.0);
+        assert_eq!(moves[1].feed, 5.0);
+
+        let program = parse("G93 G1 X10 F2\nX20");
+        let (_, diagnostics) = Interpreter::new().run(&program);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::MissingFeedRate);
+        assert_eq!(diagnostics[0].line, 2);
+    }
+
+    #[test]
+    fn test_coordinate_systems() {
+        let (interpreter, moves) = run("G10 L2 P2 X10 Y20\nG55 G0 X0 Y0\nG54 X0 Y0\nG55 G92 X5\nX0\nG92.1 X0\nG53 X1");
+
+        assert_eq!(ends(&moves), vec![
+            (10.0, 20.0, 0.0),
+            (0.0, 0.0, 0.0),
+            (-5.0, 0.0, 0.0),
+            (10.0, 0.0, 0.0),
+            (1.0, 0.0, 0.0),
+        ]);
+        assert_eq!(interpreter.state().coordinate_system, CoordinateSystem::G55);
+        assert_eq!(interpreter.work_position(), Point::new(-9.0, -20.0, 0.0));
+    }
+
+    #[test]
+    fn test_set_coordinate_data_relative() {
+        let (interpreter, _) = run("G0 X3 Y4\nG10 L20 P1 X1 Y1");
+
+        assert_eq!(interpreter.offsets().coordinate_systems[0], Point::new(2.0, 3.0, 0.0));
+        assert_eq!(interpreter.work_position(), Point::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_tool_length_offset() {
+        let (interpreter, moves) = run("G43.1 Z-2\nG0 Z0\nG49\nG0 Z0");
+
+        assert_eq!(ends(&moves), vec![
+            (0.0, 0.0, -2.0),
+            (0.0, 0.0, 0.0),
+        ]);
+        assert_eq!(interpreter.offsets().tool_length, 0.0);
+    }
+
+    #[test]
+    fn test_go_home() {
+        let (_, moves) = run("G0 X5 Y5 Z5\nG28.1\nG0 X0 Y0 Z0\nG28 Z10\nG28");
+
+        assert_eq!(ends(&moves), vec![
+            (5.0, 5.0, 5.0),
+            (0.0, 0.0, 0.0),
+            (0.0, 0.0, 10.0),
+            (0.0, 0.0, 5.0),
+            (5.0, 5.0, 5.0),
+        ]);
+    }
+
+    #[test]
+    fn test_arc_center() {
+        let (_, moves) = run("G0 X10 Y0\nG3 X0 Y10 I-10 J0 F100\nG90.1 G2 X10 Y0 I0 J0\nG18 G2 X20 Z0 R5");
+
+        let centers: Vec<(f64, f64, f64)> = moves.iter()
+            .filter_map(|m| match m.kind {
+                MoveKind::Arc(arc) => Some(arc.center.into()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(centers, vec![
+            (0.0, 0.0, 0.0),
+            (0.0, 0.0, 0.0),
+            (15.0, 0.0, 0.0),
+        ]);
+        assert!((moves[1].length() - 10.0 * std::f64::consts::PI / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_arc_validation() {
+        let program = parse("G0 X10 F100\nG2 X0 Y-9 I-10\nG2 X10 R5\nG2 X-10 R5\nG2 X-10 R10\nG20 G3 X0 R0.2");
+        let (moves, diagnostics) = Interpreter::new().run(&program);
+
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![
+            (2, DiagnosticKind::ArcRadiusMismatch { start: 10.0, end: 9.0 }),
+            (3, DiagnosticKind::ArcFullCircleRadius),
+            (4, DiagnosticKind::ArcRadiusTooSmall),
+        ]);
+        assert_eq!(ends(&moves), vec![
+            (10.0, 0.0, 0.0),
+            (-10.0, 0.0, 0.0),
+            (0.0, 0.0, 0.0),
+        ]);
+    }
+
+    #[test]
+    fn test_modal_state() {
+        let (interpreter, _) = run("G18 G91.1 G56 T3 S1000 M4 M7 F200");
+
+        assert_eq!(interpreter.state(), &ModalState {
+            plane: Plane::ZX,
+            arc_distance: DistanceMode::Incremental,
+            coordinate_system: CoordinateSystem::G56,
+            tool: 3,
+            speed: 1000.0,
+            spindle: Spindle::CounterClockwise,
+            mist_coolant: true,
+            flood_coolant: false,
+            feed: 200.0,
+            ..ModalState::default()
+        });
+
+        let (interpreter, _) = run("G91 G56 M3 M8\nM30");
+        assert_eq!(interpreter.state().distance, DistanceMode::Absolute);
+        assert_eq!(interpreter.state().coordinate_system, CoordinateSystem::G54);
+        assert_eq!(interpreter.state().spindle, Spindle::Off);
+        assert_eq!(interpreter.state().motion, Motion::Linear);
+    }
+
+    #[test]
+    fn test_errors_leave_state_untouched() {
+        let program = parse("G1 X10\nG91 G0 X1\nG2 X5 Y5\nG80 X1\nG2 G53 X1\nG4\nG0 X1");
+        let mut interpreter = Interpreter::new();
+        let (moves, diagnostics) = interpreter.run(&program);
+
+        assert_eq!(diagnostics.iter().map(|d| (d.line, d.kind)).collect::<Vec<_>>(), vec![
+            (1, DiagnosticKind::MissingFeedRate),
+            (3, DiagnosticKind::MissingArcParameters),
+            (4, DiagnosticKind::UnusedAxisWords),
+            (5, DiagnosticKind::MachineCoordinatesMotion),
+            (6, DiagnosticKind::MissingValue('P')),
+        ]);
+        assert_eq!(ends(&moves), vec![
+            (1.0, 0.0, 0.0),
+            (2.0, 0.0, 0.0),
+        ]);
+        assert_eq!(interpreter.state().motion, Motion::Rapid);
+    }
+}
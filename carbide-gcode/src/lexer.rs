@@ -0,0 +1,366 @@
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        return Self { start, end };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.end - self.start;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.start == self.end;
+    }
+
+    pub fn join(self, other: Span) -> Self {
+        return Self {
+            start: usize::min(self.start, other.start),
+            end: usize::max(self.end, other.end),
+        };
+    }
+
+    pub fn slice<'a>(&self, source: &'a str) -> &'a str {
+        return &source[self.start..self.end];
+    }
+
+    // Returns the 1-based line and column of the span start in the given source
+    pub fn location(&self, source: &str) -> (usize, usize) {
+        let before = &source.as_bytes()[..self.start];
+
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let column = match before.iter().rposition(|&b| b == b'\n') {
+            Some(i) => self.start - i,
+            None => self.start + 1,
+        };
+
+        return (line, column);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentKind {
+    Parenthesized,
+    Semicolon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind<'a> {
+    Word { letter: char, value: f64 },
+    LineNumber(u32),
+    Checksum(u8),
+    Comment { kind: CommentKind, text: &'a str },
+    BlockDelete,
+    ProgramDelimiter,
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    MissingValue(char),
+    InvalidNumber,
+    UnterminatedComment,
+    NestedComment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{} at {}..{}", self.kind, self.span.start, self.span.end);
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c.escape_default()),
+            LexErrorKind::MissingValue(letter) => write!(f, "missing value for word '{}'", letter),
+            LexErrorKind::InvalidNumber => write!(f, "invalid number"),
+            LexErrorKind::UnterminatedComment => write!(f, "unterminated comment"),
+            LexErrorKind::NestedComment => write!(f, "nested comment"),
+        };
+    }
+}
+
+impl error::Error for LexError {}
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        return Self {
+            source,
+            pos: 0,
+        };
+    }
+
+    pub fn source(&self) -> &'a str {
+        return self.source;
+    }
+
+    fn peek(&self) -> Option<u8> {
+        return self.source.as_bytes().get(self.pos).cloned();
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_while(&mut self, f: impl Fn(u8) -> bool) {
+        while let Some(b) = self.peek() {
+            if !f(b) {
+                break;
+            }
+
+            self.pos += 1;
+        }
+    }
+
+    // Returns the position of the end of the current line, excluding the line terminator
+    fn line_end(&self) -> usize {
+        let rest = &self.source[self.pos..];
+        let end = rest.find('\n').unwrap_or(rest.len());
+        let end = if rest[..end].ends_with('\r') { end - 1 } else { end };
+
+        return self.pos + end;
+    }
+
+    fn error(&self, kind: LexErrorKind, start: usize) -> Option<Result<Token<'a>, LexError>> {
+        return Some(Err(LexError { kind, span: Span::new(start, self.pos) }));
+    }
+
+    fn token(&self, kind: TokenKind<'a>, start: usize) -> Option<Result<Token<'a>, LexError>> {
+        return Some(Ok(Token { kind, span: Span::new(start, self.pos) }));
+    }
+
+    fn lex_parenthesized_comment(&mut self, start: usize) -> Option<Result<Token<'a>, LexError>> {
+        let end = self.line_end();
+        let body = &self.source[start + 1..end];
+
+        return match (body.find(')'), body.find('(')) {
+            (Some(close), Some(open)) if open < close => {
+                // Skip up to the last closing parenthesis to resynchronize after the nested comment
+                self.pos = start + 1 + body.rfind(')').unwrap() + 1;
+                self.error(LexErrorKind::NestedComment, start)
+            }
+            (Some(close), _) => {
+                self.pos = start + 1 + close + 1;
+                self.token(TokenKind::Comment {
+                    kind: CommentKind::Parenthesized,
+                    text: &body[..close],
+                }, start)
+            }
+            (None, _) => {
+                self.pos = end;
+                self.error(LexErrorKind::UnterminatedComment, start)
+            }
+        };
+    }
+
+    fn lex_semicolon_comment(&mut self, start: usize) -> Option<Result<Token<'a>, LexError>> {
+        self.pos = self.line_end();
+
+        return self.token(TokenKind::Comment {
+            kind: CommentKind::Semicolon,
+            text: &self.source[start + 1..self.pos],
+        }, start);
+    }
+
+    fn lex_integer<T>(&mut self, start: usize, f: impl FnOnce(T) -> TokenKind<'a>) -> Option<Result<Token<'a>, LexError>>
+        where T: std::str::FromStr {
+        let letter = self.source[start..].chars().next().unwrap().to_ascii_uppercase();
+
+        self.pos += 1;
+        self.skip_whitespace();
+
+        let number = self.pos;
+        self.skip_while(|b| b.is_ascii_digit());
+
+        if number == self.pos {
+            return self.error(LexErrorKind::MissingValue(letter), start);
+        }
+
+        // Reject fractions instead of silently truncating them
+        if let Some(b'.') = self.peek() {
+            self.skip_while(|b| b == b'.' || b.is_ascii_digit());
+            return self.error(LexErrorKind::InvalidNumber, start);
+        }
+
+        return match self.source[number..self.pos].parse() {
+            Ok(value) => self.token(f(value), start),
+            Err(_) => self.error(LexErrorKind::InvalidNumber, start),
+        };
+    }
+
+    fn lex_word(&mut self, start: usize, letter: char) -> Option<Result<Token<'a>, LexError>> {
+        self.pos += 1;
+        self.skip_whitespace();
+
+        let number = self.pos;
+
+        if let Some(b'+') | Some(b'-') = self.peek() {
+            self.pos += 1;
+        }
+
+        let digits = self.pos;
+        self.skip_while(|b| b.is_ascii_digit() || b == b'.');
+
+        if digits == self.pos {
+            self.pos = start + 1;
+            return self.error(LexErrorKind::MissingValue(letter), start);
+        }
+
+        return match self.source[number..self.pos].parse() {
+            Ok(value) => self.token(TokenKind::Word { letter, value }, start),
+            Err(_) => self.error(LexErrorKind::InvalidNumber, start),
+        };
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        let c = self.source[start..].chars().next()?;
+
+        return match c {
+            '\n' => {
+                self.pos += 1;
+                self.token(TokenKind::Newline, start)
+            }
+            '(' => self.lex_parenthesized_comment(start),
+            ';' => self.lex_semicolon_comment(start),
+            '/' => {
+                self.pos += 1;
+                self.token(TokenKind::BlockDelete, start)
+            }
+            '%' => {
+                self.pos += 1;
+                self.token(TokenKind::ProgramDelimiter, start)
+            }
+            '*' => self.lex_integer(start, TokenKind::Checksum),
+            'n' | 'N' => self.lex_integer(start, TokenKind::LineNumber),
+            c if c.is_ascii_alphabetic() => self.lex_word(start, c.to_ascii_uppercase()),
+            c => {
+                self.pos += c.len_utf8();
+                self.error(LexErrorKind::UnexpectedCharacter(c), start)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<Result<TokenKind<'_>, LexErrorKind>> {
+        return Lexer::new(source)
+            .map(|token| token.map(|token| token.kind).map_err(|err| err.kind))
+            .collect();
+    }
+
+    #[test]
+    fn test_lex_words() {
+        assert_eq!(kinds("G1 X-1.5 y.25 F+100"), vec![
+            Ok(TokenKind::Word { letter: 'G', value: 1.0 }),
+            Ok(TokenKind::Word { letter: 'X', value: -1.5 }),
+            Ok(TokenKind::Word { letter: 'Y', value: 0.25 }),
+            Ok(TokenKind::Word { letter: 'F', value: 100.0 }),
+        ]);
+        assert_eq!(kinds("G38.2Z-10"), vec![
+            Ok(TokenKind::Word { letter: 'G', value: 38.2 }),
+            Ok(TokenKind::Word { letter: 'Z', value: -10.0 }),
+        ]);
+        assert_eq!(kinds("G 0 X 1."), vec![
+            Ok(TokenKind::Word { letter: 'G', value: 0.0 }),
+            Ok(TokenKind::Word { letter: 'X', value: 1.0 }),
+        ]);
+    }
+
+    #[test]
+    fn test_lex_comments() {
+        assert_eq!(kinds("G0 (rapid) X1 ; to the left\r\n"), vec![
+            Ok(TokenKind::Word { letter: 'G', value: 0.0 }),
+            Ok(TokenKind::Comment { kind: CommentKind::Parenthesized, text: "rapid" }),
+            Ok(TokenKind::Word { letter: 'X', value: 1.0 }),
+            Ok(TokenKind::Comment { kind: CommentKind::Semicolon, text: " to the left" }),
+            Ok(TokenKind::Newline),
+        ]);
+        assert_eq!(kinds("(unterminated\nG0"), vec![
+            Err(LexErrorKind::UnterminatedComment),
+            Ok(TokenKind::Newline),
+            Ok(TokenKind::Word { letter: 'G', value: 0.0 }),
+        ]);
+        assert_eq!(kinds("(a (b) c)"), vec![
+            Err(LexErrorKind::NestedComment),
+        ]);
+    }
+
+    #[test]
+    fn test_lex_line_number_and_checksum() {
+        assert_eq!(kinds("/N10 G1 X2*57\n%"), vec![
+            Ok(TokenKind::BlockDelete),
+            Ok(TokenKind::LineNumber(10)),
+            Ok(TokenKind::Word { letter: 'G', value: 1.0 }),
+            Ok(TokenKind::Word { letter: 'X', value: 2.0 }),
+            Ok(TokenKind::Checksum(57)),
+            Ok(TokenKind::Newline),
+            Ok(TokenKind::ProgramDelimiter),
+        ]);
+        assert_eq!(kinds("N1.5 *300"), vec![
+            Err(LexErrorKind::InvalidNumber),
+            Err(LexErrorKind::InvalidNumber),
+        ]);
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        assert_eq!(kinds("GX1 #"), vec![
+            Err(LexErrorKind::MissingValue('G')),
+            Ok(TokenKind::Word { letter: 'X', value: 1.0 }),
+            Err(LexErrorKind::UnexpectedCharacter('#')),
+        ]);
+        assert_eq!(kinds("X1.2.3"), vec![
+            Err(LexErrorKind::InvalidNumber),
+        ]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = "G0 X1\n  Y-2.5 (c)";
+        let tokens: Vec<_> = Lexer::new(source).map(Result::unwrap).collect();
+
+        assert_eq!(tokens[3].span, Span::new(8, 13));
+        assert_eq!(tokens[3].span.slice(source), "Y-2.5");
+        assert_eq!(tokens[3].span.location(source), (2, 3));
+        assert_eq!(tokens[4].span.slice(source), "(c)");
+        assert_eq!(tokens[0].span.location(source), (1, 1));
+    }
+}
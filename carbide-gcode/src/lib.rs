@@ -1,7 +1,19 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+mod lexer;
+mod ast;
+mod diagnostic;
+mod parser;
+mod point;
+mod arc;
+mod bounds;
+mod estimate;
+mod interpreter;
+
+pub use self::lexer::{CommentKind, LexError, LexErrorKind, Lexer, Span, Token, TokenKind};
+pub use self::ast::*;
+pub use self::diagnostic::{Diagnostic, DiagnosticKind, Severity};
+pub use self::parser::{parse, Program};
+pub use self::point::Point;
+pub use self::arc::{Arc, DEFAULT_ARC_TOLERANCE};
+pub use self::bounds::BoundingBox;
+pub use self::estimate::{estimate, Estimate, MachineModel};
+pub use self::interpreter::{Interpreter, ModalState, Move, MoveKind, Offsets, MILLIMETERS_PER_INCH};
@@ -0,0 +1,429 @@
+use crate::ast::*;
+use crate::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::lexer::{Lexer, Span, TokenKind};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub blocks: Vec<Block>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Program {
+    pub fn parse(source: &str) -> Self {
+        return parse(source);
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item=&Diagnostic> {
+        return self.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.is_error());
+    }
+
+    pub fn is_valid(&self) -> bool {
+        return self.errors().next().is_none();
+    }
+}
+
+// Parses the whole source into blocks.
+//
+// Parsing does not stop at the first problem: lines containing errors are reported and skipped, and parsing
+// continues with the next line so that all problems in a program are found in a single pass.
+pub fn parse(source: &str) -> Program {
+    let mut program = Program::default();
+
+    let mut line = Line::new(1, 0);
+
+    for token in Lexer::new(source) {
+        let token = match token {
+            Ok(token) => token,
+            Err(err) => {
+                line.error(DiagnosticKind::Lex(err.kind), err.span, &mut program.diagnostics);
+                continue;
+            }
+        };
+
+        match token.kind {
+            TokenKind::Newline => {
+                let number = line.number;
+                line.finish(source, token.span.start, &mut program);
+                line = Line::new(number + 1, token.span.end);
+            }
+
+            TokenKind::Comment { .. } | TokenKind::ProgramDelimiter => {}
+
+            TokenKind::BlockDelete => {
+                if line.seen {
+                    line.error(DiagnosticKind::MisplacedBlockDelete, token.span, &mut program.diagnostics);
+                }
+
+                line.block_delete = true;
+                line.seen(token.span, &mut program.diagnostics);
+            }
+
+            TokenKind::LineNumber(number) => {
+                if !line.words.is_empty() || line.line_number.is_some() {
+                    line.error(DiagnosticKind::MisplacedLineNumber, token.span, &mut program.diagnostics);
+                }
+
+                line.line_number = Some(number);
+                line.seen(token.span, &mut program.diagnostics);
+            }
+
+            TokenKind::Checksum(checksum) => {
+                line.seen(token.span, &mut program.diagnostics);
+                line.checksum = Some((checksum, token.span));
+            }
+
+            TokenKind::Word { letter, value } => {
+                line.seen(token.span, &mut program.diagnostics);
+                line.words.push(Word { letter, value, span: token.span });
+            }
+        }
+    }
+
+    line.finish(source, source.len(), &mut program);
+
+    return program;
+}
+
+struct Word {
+    letter: char,
+    value: f64,
+    span: Span,
+}
+
+struct Line {
+    number: usize,
+    start: usize,
+
+    // Set as soon as the line contains anything but comments
+    seen: bool,
+    failed: bool,
+
+    block_delete: bool,
+    line_number: Option<u32>,
+    checksum: Option<(u8, Span)>,
+    words: Vec<Word>,
+}
+
+impl Line {
+    fn new(number: usize, start: usize) -> Self {
+        return Self {
+            number,
+            start,
+            seen: false,
+            failed: false,
+            block_delete: false,
+            line_number: None,
+            checksum: None,
+            words: Vec::new(),
+        };
+    }
+
+    fn error(&mut self, kind: DiagnosticKind, span: Span, diagnostics: &mut Vec<Diagnostic>) {
+        diagnostics.push(Diagnostic::error(kind, self.number, span));
+        self.failed = true;
+    }
+
+    fn seen(&mut self, span: Span, diagnostics: &mut Vec<Diagnostic>) {
+        // Nothing but comments may follow the checksum
+        if let Some((_, checksum)) = self.checksum {
+            self.error(DiagnosticKind::MisplacedChecksum, checksum.join(span), diagnostics);
+        }
+
+        self.seen = true;
+    }
+
+    fn finish(mut self, source: &str, end: usize, program: &mut Program) {
+        if let Some((expected, span)) = self.checksum {
+            let actual = source[self.start..span.start].bytes()
+                .fold(0u8, |checksum, b| checksum ^ b);
+
+            if actual != expected {
+                self.error(DiagnosticKind::ChecksumMismatch { expected, actual }, span, &mut program.diagnostics);
+            }
+        }
+
+        if self.failed || self.words.is_empty() {
+            return;
+        }
+
+        let span = Span::new(self.start, usize::max(self.start, end));
+        if let Some(block) = self.build(span, &mut program.diagnostics) {
+            program.blocks.push(block);
+        }
+    }
+
+    fn build(&mut self, span: Span, diagnostics: &mut Vec<Diagnostic>) -> Option<Block> {
+        let mut block = Block {
+            line: self.number,
+            span,
+            block_delete: self.block_delete,
+            line_number: self.line_number,
+            ..Block::default()
+        };
+
+        for word in self.words.iter() {
+            let result = match word.letter {
+                'G' => g_word(&mut block, word.value),
+                'M' => m_word(&mut block, word.value),
+                'F' => non_negative(&mut block.feed, word),
+                'S' => non_negative(&mut block.speed, word),
+                'T' => integer(&mut block.tool, word),
+                'X' => real(&mut block.axes.x, word),
+                'Y' => real(&mut block.axes.y, word),
+                'Z' => real(&mut block.axes.z, word),
+                'I' => real(&mut block.offsets.i, word),
+                'J' => real(&mut block.offsets.j, word),
+                'K' => real(&mut block.offsets.k, word),
+                'R' => real(&mut block.radius, word),
+                'P' => real(&mut block.p, word),
+                'L' => integer(&mut block.l, word),
+                letter => Err(DiagnosticKind::UnsupportedWord(letter)),
+            };
+
+            if let Err(kind) = result {
+                diagnostics.push(Diagnostic::error(kind, self.number, word.span));
+                self.failed = true;
+            }
+        }
+
+        // Axis words can either belong to the motion or to another command but not to both
+        if block.motion.is_some() && block.has_axis_command() && !block.axes.is_empty() {
+            diagnostics.push(Diagnostic::error(DiagnosticKind::AxisCommandConflict, self.number, span));
+            self.failed = true;
+        }
+
+        if self.failed {
+            return None;
+        }
+
+        return Some(block);
+    }
+}
+
+// Converts the value of a command word into an integer code scaled by ten so that `G38.2` becomes `382`
+fn code(value: f64) -> Option<u16> {
+    let scaled = value * 10.0;
+
+    if value < 0.0 || scaled > f64::from(u16::MAX) || (scaled - scaled.round()).abs() > 1e-6 {
+        return None;
+    }
+
+    return Some(scaled.round() as u16);
+}
+
+fn modal<T>(slot: &mut Option<T>, value: T, group: ModalGroup) -> Result<(), DiagnosticKind> {
+    if slot.is_some() {
+        return Err(DiagnosticKind::ModalGroupConflict(group));
+    }
+
+    *slot = Some(value);
+    return Ok(());
+}
+
+fn g_word(block: &mut Block, value: f64) -> Result<(), DiagnosticKind> {
+    let unsupported = DiagnosticKind::UnsupportedGCode(value);
+
+    return match code(value).ok_or(unsupported)? {
+        0 => modal(&mut block.motion, Motion::Rapid, ModalGroup::Motion),
+        10 => modal(&mut block.motion, Motion::Linear, ModalGroup::Motion),
+        20 => modal(&mut block.motion, Motion::Arc(ArcDirection::Clockwise), ModalGroup::Motion),
+        30 => modal(&mut block.motion, Motion::Arc(ArcDirection::CounterClockwise), ModalGroup::Motion),
+        382 => modal(&mut block.motion, Motion::Probe(Probe { toward: true, signal_failure: true }), ModalGroup::Motion),
+        383 => modal(&mut block.motion, Motion::Probe(Probe { toward: true, signal_failure: false }), ModalGroup::Motion),
+        384 => modal(&mut block.motion, Motion::Probe(Probe { toward: false, signal_failure: true }), ModalGroup::Motion),
+        385 => modal(&mut block.motion, Motion::Probe(Probe { toward: false, signal_failure: false }), ModalGroup::Motion),
+        800 => modal(&mut block.motion, Motion::Cancel, ModalGroup::Motion),
+
+        40 => modal(&mut block.non_modal, NonModal::Dwell, ModalGroup::NonModal),
+        100 => modal(&mut block.non_modal, NonModal::SetCoordinateData, ModalGroup::NonModal),
+        280 => modal(&mut block.non_modal, NonModal::GoHome, ModalGroup::NonModal),
+        281 => modal(&mut block.non_modal, NonModal::SetHome, ModalGroup::NonModal),
+        300 => modal(&mut block.non_modal, NonModal::GoSecondaryHome, ModalGroup::NonModal),
+        301 => modal(&mut block.non_modal, NonModal::SetSecondaryHome, ModalGroup::NonModal),
+        530 => modal(&mut block.non_modal, NonModal::MachineCoordinates, ModalGroup::NonModal),
+        920 => modal(&mut block.non_modal, NonModal::SetOffset, ModalGroup::NonModal),
+        921 => modal(&mut block.non_modal, NonModal::ResetOffset, ModalGroup::NonModal),
+
+        170 => modal(&mut block.plane, Plane::XY, ModalGroup::Plane),
+        180 => modal(&mut block.plane, Plane::ZX, ModalGroup::Plane),
+        190 => modal(&mut block.plane, Plane::YZ, ModalGroup::Plane),
+
+        900 => modal(&mut block.distance, DistanceMode::Absolute, ModalGroup::Distance),
+        910 => modal(&mut block.distance, DistanceMode::Incremental, ModalGroup::Distance),
+
+        901 => modal(&mut block.arc_distance, DistanceMode::Absolute, ModalGroup::ArcDistance),
+        911 => modal(&mut block.arc_distance, DistanceMode::Incremental, ModalGroup::ArcDistance),
+
+        930 => modal(&mut block.feed_mode, FeedMode::InverseTime, ModalGroup::FeedMode),
+        940 => modal(&mut block.feed_mode, FeedMode::UnitsPerMinute, ModalGroup::FeedMode),
+
+        200 => modal(&mut block.units, Units::Inches, ModalGroup::Units),
+        210 => modal(&mut block.units, Units::Millimeters, ModalGroup::Units),
+
+        400 => modal(&mut block.cutter_compensation, CutterCompensation::Off, ModalGroup::CutterCompensation),
+
+        431 => modal(&mut block.tool_length_offset, ToolLengthOffset::Dynamic, ModalGroup::ToolLengthOffset),
+        490 => modal(&mut block.tool_length_offset, ToolLengthOffset::Cancel, ModalGroup::ToolLengthOffset),
+
+        code @ 540..=590 if code % 10 == 0 => {
+            let system = CoordinateSystem::ALL[usize::from((code - 540) / 10)];
+            modal(&mut block.coordinate_system, system, ModalGroup::CoordinateSystem)
+        }
+
+        _ => Err(unsupported),
+    };
+}
+
+fn m_word(block: &mut Block, value: f64) -> Result<(), DiagnosticKind> {
+    let unsupported = DiagnosticKind::UnsupportedMCode(value);
+
+    return match code(value).ok_or(unsupported)? {
+        0 => modal(&mut block.stop, Stop::Pause, ModalGroup::Stopping),
+        10 => modal(&mut block.stop, Stop::OptionalPause, ModalGroup::Stopping),
+        20 => modal(&mut block.stop, Stop::End, ModalGroup::Stopping),
+        300 => modal(&mut block.stop, Stop::EndAndRewind, ModalGroup::Stopping),
+
+        60 if block.tool_change => Err(DiagnosticKind::ModalGroupConflict(ModalGroup::ToolChange)),
+        60 => {
+            block.tool_change = true;
+            Ok(())
+        }
+
+        30 => modal(&mut block.spindle, Spindle::Clockwise, ModalGroup::Spindle),
+        40 => modal(&mut block.spindle, Spindle::CounterClockwise, ModalGroup::Spindle),
+        50 => modal(&mut block.spindle, Spindle::Off, ModalGroup::Spindle),
+
+        70 => modal(&mut block.coolant, Coolant::Mist, ModalGroup::Coolant),
+        80 => modal(&mut block.coolant, Coolant::Flood, ModalGroup::Coolant),
+        90 => modal(&mut block.coolant, Coolant::Off, ModalGroup::Coolant),
+
+        _ => Err(unsupported),
+    };
+}
+
+fn real(slot: &mut Option<f64>, word: &Word) -> Result<(), DiagnosticKind> {
+    if slot.is_some() {
+        return Err(DiagnosticKind::DuplicateWord(word.letter));
+    }
+
+    *slot = Some(word.value);
+    return Ok(());
+}
+
+fn non_negative(slot: &mut Option<f64>, word: &Word) -> Result<(), DiagnosticKind> {
+    if word.value < 0.0 {
+        return Err(DiagnosticKind::InvalidValue(word.letter));
+    }
+
+    return real(slot, word);
+}
+
+fn integer(slot: &mut Option<u32>, word: &Word) -> Result<(), DiagnosticKind> {
+    if word.value < 0.0 || word.value.fract() != 0.0 || word.value > f64::from(u32::MAX) {
+        return Err(DiagnosticKind::InvalidValue(word.letter));
+    }
+
+    if slot.is_some() {
+        return Err(DiagnosticKind::DuplicateWord(word.letter));
+    }
+
+    *slot = Some(word.value as u32);
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(program: &Program) -> Vec<(usize, DiagnosticKind)> {
+        return program.diagnostics.iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.kind))
+            .collect();
+    }
+
+    #[test]
+    fn test_parse_block() {
+        let program = parse("N10 G90 G21 G1 X1.5 Y-2 Z0.5 F300 S12000 M3 M8 T2");
+
+        assert!(program.is_valid());
+        assert_eq!(program.blocks, vec![Block {
+            line: 1,
+            span: Span::new(0, 49),
+            line_number: Some(10),
+            motion: Some(Motion::Linear),
+            distance: Some(DistanceMode::Absolute),
+            units: Some(Units::Millimeters),
+            spindle: Some(Spindle::Clockwise),
+            coolant: Some(Coolant::Flood),
+            feed: Some(300.0),
+            speed: Some(12000.0),
+            tool: Some(2),
+            axes: Axes { x: Some(1.5), y: Some(-2.0), z: Some(0.5) },
+            ..Block::default()
+        }]);
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let program = parse("G38.2 Z-10 F50\nG2 X10 Y0 I5 J0\nG10 L20 P1 X0\nG91.1 G93 G17 G55 G43.1 Z1\nM6 T1\n/G0 X0");
+
+        assert!(program.is_valid());
+        assert_eq!(program.blocks[0].motion, Some(Motion::Probe(Probe { toward: true, signal_failure: true })));
+        assert_eq!(program.blocks[1].motion, Some(Motion::Arc(ArcDirection::Clockwise)));
+        assert_eq!(program.blocks[1].offsets, ArcOffsets { i: Some(5.0), j: Some(0.0), k: None });
+        assert_eq!(program.blocks[2].non_modal, Some(NonModal::SetCoordinateData));
+        assert_eq!(program.blocks[2].l, Some(20));
+        assert_eq!(program.blocks[2].p, Some(1.0));
+        assert_eq!(program.blocks[3].arc_distance, Some(DistanceMode::Incremental));
+        assert_eq!(program.blocks[3].feed_mode, Some(FeedMode::InverseTime));
+        assert_eq!(program.blocks[3].plane, Some(Plane::XY));
+        assert_eq!(program.blocks[3].coordinate_system, Some(CoordinateSystem::G55));
+        assert_eq!(program.blocks[3].tool_length_offset, Some(ToolLengthOffset::Dynamic));
+        assert!(program.blocks[4].tool_change);
+        assert!(program.blocks[5].block_delete);
+    }
+
+    #[test]
+    fn test_parse_modal_group_conflict() {
+        let program = parse("G0 G1 X1\nM3 M4\nM7 M8\nG54 G55");
+
+        assert!(program.blocks.is_empty());
+        assert_eq!(kinds(&program), vec![
+            (1, DiagnosticKind::ModalGroupConflict(ModalGroup::Motion)),
+            (2, DiagnosticKind::ModalGroupConflict(ModalGroup::Spindle)),
+            (3, DiagnosticKind::ModalGroupConflict(ModalGroup::Coolant)),
+            (4, DiagnosticKind::ModalGroupConflict(ModalGroup::CoordinateSystem)),
+        ]);
+        assert_eq!(program.diagnostics[0].span, Span::new(3, 5));
+    }
+
+    #[test]
+    fn test_parse_recovers_after_errors() {
+        let program = parse("G0 X0\nG1 X1 X2\nG1 Q1 F-1\n(comment\nG4 P1\nG92 G0 X0\nG12\nM98\nG1 X3");
+
+        assert_eq!(program.blocks.iter().map(|block| block.line).collect::<Vec<_>>(), vec![1, 5, 9]);
+        assert_eq!(kinds(&program), vec![
+            (2, DiagnosticKind::DuplicateWord('X')),
+            (3, DiagnosticKind::UnsupportedWord('Q')),
+            (3, DiagnosticKind::InvalidValue('F')),
+            (4, DiagnosticKind::Lex(crate::lexer::LexErrorKind::UnterminatedComment)),
+            (6, DiagnosticKind::AxisCommandConflict),
+            (7, DiagnosticKind::UnsupportedGCode(12.0)),
+            (8, DiagnosticKind::UnsupportedMCode(98.0)),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_line_number_and_checksum() {
+        let program = parse("N1 G0 X1*97\nN2 G0 X2*0\nG0 N3 X3\nG0*119 X4\nN4");
+
+        assert_eq!(program.blocks.len(), 1);
+        assert_eq!(program.blocks[0].line_number, Some(1));
+        assert_eq!(kinds(&program), vec![
+            (2, DiagnosticKind::ChecksumMismatch { expected: 0, actual: 97 }),
+            (3, DiagnosticKind::MisplacedLineNumber),
+            (4, DiagnosticKind::MisplacedChecksum),
+        ]);
+    }
+}
@@ -0,0 +1,142 @@
+use std::ops;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        return Self { x, y, z };
+    }
+
+    pub fn zero() -> Self {
+        return Self { x: 0.0, y: 0.0, z: 0.0 };
+    }
+
+    pub fn length(&self) -> f64 {
+        return (self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+    }
+
+    pub fn distance(&self, other: Point) -> f64 {
+        return (other - *self).length();
+    }
+
+    pub fn min(self, other: Point) -> Self {
+        return Self {
+            x: f64::min(self.x, other.x),
+            y: f64::min(self.y, other.y),
+            z: f64::min(self.z, other.z),
+        };
+    }
+
+    pub fn max(self, other: Point) -> Self {
+        return Self {
+            x: f64::max(self.x, other.x),
+            y: f64::max(self.y, other.y),
+            z: f64::max(self.z, other.z),
+        };
+    }
+}
+
+impl ops::Index<usize> for Point {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &Self::Output {
+        return match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Invalid axis: {}", axis),
+        };
+    }
+}
+
+impl ops::IndexMut<usize> for Point {
+    fn index_mut(&mut self, axis: usize) -> &mut Self::Output {
+        return match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Invalid axis: {}", axis),
+        };
+    }
+}
+
+impl ops::Neg for Point {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        return Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        };
+    }
+}
+
+impl ops::Add<Self> for Point {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        return Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+        };
+    }
+}
+
+impl ops::Sub<Self> for Point {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        return Self {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
+        };
+    }
+}
+
+impl ops::Mul<f64> for Point {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        return Self {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        };
+    }
+}
+
+impl ops::Div<f64> for Point {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        return Self {
+            x: self.x / rhs,
+            y: self.y / rhs,
+            z: self.z / rhs,
+        };
+    }
+}
+
+impl From<(f64, f64, f64)> for Point {
+    fn from(f: (f64, f64, f64)) -> Self {
+        return Self {
+            x: f.0,
+            y: f.1,
+            z: f.2,
+        };
+    }
+}
+
+impl From<Point> for (f64, f64, f64) {
+    fn from(p: Point) -> Self {
+        return (p.x, p.y, p.z);
+    }
+}
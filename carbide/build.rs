@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::path::Path;
 use std::fs::File;
@@ -7,20 +8,134 @@ use std::io::Write;
 use csv;
 use phf_codegen;
 
-fn generate_grbl_alarm_codes(root: &Path, f: &mut impl Write) {
-    let mut csv = csv::Reader::from_reader(File::open(root.join("alarm_codes_en_US.csv")).unwrap());
+struct Code {
+    code: u8,
+    name: String,
+    unit: Option<String>,
+    desc: String,
+}
 
-    let mut builder = phf_codegen::Map::new();
+// Builds a variant name from a short name like "X-axis maximum rate" or "Value < 3 usec"
+fn identifier(name: &str) -> String {
+    return name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            return std::iter::once(first).chain(chars.map(|c| c.to_ascii_lowercase())).collect::<String>();
+        })
+        .collect();
+}
 
-    for code in csv.records() {
-        let code = code.unwrap();
-        builder.entry(u8::from_str(&code[0]).unwrap(),
-                      &format!("\"{}\"", &code[2]));
+fn read_codes(path: &Path, unit: bool) -> Vec<Code> {
+    let mut csv = csv::Reader::from_reader(File::open(path).unwrap());
+
+    return csv.records()
+        .map(|code| {
+            let code = code.unwrap();
+            return Code {
+                code: u8::from_str(code[0].trim()).unwrap(),
+                name: code[1].trim().to_owned(),
+                unit: if unit { Some(code[2].trim().to_owned()) } else { None },
+                desc: code[if unit { 3 } else { 2 }].trim().to_owned(),
+            };
+        })
+        .collect();
+}
+
+// Generates an enum with a variant per code and a catch-all variant for codes unknown at build time
+fn generate_enum(f: &mut impl Write, name: &str, unknown: &str, codes: &[Code]) {
+    // Some short names are shared by multiple codes, which are told apart by their code then
+    let mut counts = HashMap::new();
+    for code in codes {
+        *counts.entry(identifier(&code.name)).or_insert(0) += 1;
     }
 
-    write!(f, "pub const ALARM_CODES: phf::Map<u8, &'static str> = ").unwrap();
-    builder.build(f).unwrap();
-    write!(f, ";\n\n").unwrap();
+    let variants: Vec<String> = codes.iter()
+        .map(|code| {
+            let variant = identifier(&code.name);
+            return if counts[&variant] > 1 { format!("{}{}", variant, code.code) } else { variant };
+        })
+        .collect();
+
+    writeln!(f, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(f, "pub enum {} {{", name).unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(f, "    // {}: {}", code.code, code.desc.replace('\n', " ")).unwrap();
+        writeln!(f, "    {},", variant).unwrap();
+    }
+    writeln!(f, "    Unknown(u8),").unwrap();
+    writeln!(f, "}}\n").unwrap();
+
+    writeln!(f, "impl {} {{", name).unwrap();
+
+    writeln!(f, "    pub const ALL: &[{}] = &[", name).unwrap();
+    for variant in variants.iter() {
+        writeln!(f, "        {}::{},", name, variant).unwrap();
+    }
+    writeln!(f, "    ];\n").unwrap();
+
+    writeln!(f, "    pub fn from_code(code: u8) -> Self {{").unwrap();
+    writeln!(f, "        return match code {{").unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(f, "            {} => {}::{},", code.code, name, variant).unwrap();
+    }
+    writeln!(f, "            code => {}::Unknown(code),", name).unwrap();
+    writeln!(f, "        }};").unwrap();
+    writeln!(f, "    }}\n").unwrap();
+
+    writeln!(f, "    pub fn code(&self) -> u8 {{").unwrap();
+    writeln!(f, "        return match self {{").unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(f, "            {}::{} => {},", name, variant, code.code).unwrap();
+    }
+    writeln!(f, "            {}::Unknown(code) => *code,", name).unwrap();
+    writeln!(f, "        }};").unwrap();
+    writeln!(f, "    }}\n").unwrap();
+
+    writeln!(f, "    pub fn name(&self) -> &'static str {{").unwrap();
+    writeln!(f, "        return match self {{").unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(f, "            {}::{} => {:?},", name, variant, code.name).unwrap();
+    }
+    writeln!(f, "            {}::Unknown(_) => {:?},", name, unknown).unwrap();
+    writeln!(f, "        }};").unwrap();
+    writeln!(f, "    }}\n").unwrap();
+
+    if codes.iter().all(|code| code.unit.is_some()) {
+        writeln!(f, "    pub fn unit(&self) -> &'static str {{").unwrap();
+        writeln!(f, "        return match self {{").unwrap();
+        for (code, variant) in codes.iter().zip(variants.iter()) {
+            writeln!(f, "            {}::{} => {:?},", name, variant, code.unit.as_ref().unwrap()).unwrap();
+        }
+        writeln!(f, "            {}::Unknown(_) => \"\",", name).unwrap();
+        writeln!(f, "        }};").unwrap();
+        writeln!(f, "    }}\n").unwrap();
+    }
+
+    writeln!(f, "    pub fn description(&self) -> &'static str {{").unwrap();
+    writeln!(f, "        return match self {{").unwrap();
+    for (code, variant) in codes.iter().zip(variants.iter()) {
+        writeln!(f, "            {}::{} => {:?},", name, variant, code.desc).unwrap();
+    }
+    writeln!(f, "            {}::Unknown(_) => \"\",", name).unwrap();
+    writeln!(f, "        }};").unwrap();
+    writeln!(f, "    }}").unwrap();
+
+    writeln!(f, "}}\n").unwrap();
+
+    writeln!(f, "impl std::fmt::Display for {} {{", name).unwrap();
+    writeln!(f, "    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {{").unwrap();
+    writeln!(f, "        return write!(f, \"{{}}\", self.name());").unwrap();
+    writeln!(f, "    }}").unwrap();
+    writeln!(f, "}}\n").unwrap();
+}
+
+fn generate_grbl_alarm_codes(root: &Path, f: &mut impl Write) {
+    let codes = read_codes(&root.join("alarm_codes_en_US.csv"), false);
+
+    generate_enum(f, "AlarmCode", "Unknown alarm", &codes);
 }
 
 fn generate_grbl_build_option_codes(root: &Path, f: &mut impl Write) {
@@ -40,43 +155,15 @@ fn generate_grbl_build_option_codes(root: &Path, f: &mut impl Write) {
 }
 
 fn generate_grbl_error_codes(root: &Path, f: &mut impl Write) {
-    let mut csv = csv::Reader::from_reader(File::open(root.join("error_codes_en_US.csv")).unwrap());
-
-    let mut builder = phf_codegen::Map::new();
-
-    for code in csv.records() {
-        let code = code.unwrap();
-        builder.entry(u8::from_str(&code[0]).unwrap(),
-                      &format!("\"{}\"", &code[2]));
-    }
+    let codes = read_codes(&root.join("error_codes_en_US.csv"), false);
 
-    write!(f, "pub const ERROR_CODES: phf::Map<u8, &'static str> = ").unwrap();
-    builder.build(f).unwrap();
-    write!(f, ";\n\n").unwrap();
+    generate_enum(f, "ErrorCode", "Unknown error", &codes);
 }
 
 fn generate_grbl_setting_codes(root: &Path, f: &mut impl Write) {
-    let mut csv = csv::Reader::from_reader(File::open(root.join("setting_codes_en_US.csv")).unwrap());
-
-    let mut builder = phf_codegen::Map::new();
+    let codes = read_codes(&root.join("setting_codes_en_US.csv"), true);
 
-    for code in csv.records() {
-        let code = code.unwrap();
-        builder.entry(u8::from_str(&code[0]).unwrap(),
-                      &format!("Setting {{ name: \"{}\", unit: \"{}\", desc: \"{}\" }}", &code[1], &code[2], &code[3]));
-
-        writeln!(f, "pub const SETTING_CODE_{}: u8 = {};",
-                 &code[1]
-                     .replace(' ', "_")
-                     .replace('-', "_")
-                     .to_uppercase(),
-                 &code[0],
-        ).unwrap();
-    }
-
-    write!(f, "pub const SETTING_CODES: phf::Map<u8, Setting> = ").unwrap();
-    builder.build(f).unwrap();
-    write!(f, ";\n\n").unwrap();
+    generate_enum(f, "SettingCode", "Unknown setting", &codes);
 }
 
 fn generate_grbl_codes() {
@@ -93,4 +180,3 @@ fn generate_grbl_codes() {
 fn main() {
     generate_grbl_codes();
 }
-
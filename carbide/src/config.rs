@@ -1,18 +1,22 @@
 use std::fs::File;
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use failure::Error;
 use serde_derive::Deserialize;
 
 use crate::controller::Controller;
-use crate::controller::grbl::GrblControllerConfig;
+use crate::controller::grbl::{GrblControllerConfig, GrblSimulatorConfig};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum ControllerConfig {
     #[serde(rename = "grbl", alias = "GRBL")]
     GRBL(GrblControllerConfig),
+
+    // Virtual Grbl controller for trying things out without a machine
+    #[serde(rename = "simulator")]
+    Simulator(GrblSimulatorConfig),
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -21,10 +25,27 @@ pub struct ServerConfig {
     pub port: u16,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobsConfig {
+    // Directory holding the job library
+    pub path: PathBuf,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        return Self {
+            path: PathBuf::from("jobs"),
+        };
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub controller: ControllerConfig,
     pub server: ServerConfig,
+
+    #[serde(default)]
+    pub jobs: JobsConfig,
 }
 
 impl Config {
@@ -0,0 +1,283 @@
+use std::mem;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use failure::Error;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use futures::stream;
+use serde_derive::Deserialize;
+use tokio::codec::BytesCodec;
+use tokio::codec::FramedRead;
+use tokio::codec::FramedWrite;
+use tokio::io::AsyncRead;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+use crate::controller;
+use crate::controller::Sender;
+use crate::utils::stream::subscribers::Subscribers;
+
+use super::controller::GrblSender;
+use super::proto;
+
+// Lines of a single sender waiting for their response
+const MAX_PENDING_LINES: usize = 1024;
+
+fn default_host() -> IpAddr {
+    return IpAddr::V4(Ipv4Addr::LOCALHOST);
+}
+
+// Socket posing as a Grbl controller for other senders like bCNC or UGS, which drive the machine through carbide this
+// way. Senders only talking to serial ports can be given a pseudo terminal connected to the socket, for example by
+// `socat pty,link=/tmp/ttyGRBL,raw,echo=0 tcp:localhost:2323`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeConfig {
+    #[serde(default = "default_host")]
+    pub host: IpAddr,
+    pub port: u16,
+}
+
+// Accepts senders on the listener until it fails, which leaves the controller running
+pub fn serve(listener: TcpListener,
+             sender: GrblSender,
+             received: Subscribers<String>) -> impl Future<Item=(), Error=Error> {
+    if let Ok(address) = listener.local_addr() {
+        log::info!("GRBL: Bridge listening on {}", address);
+    }
+
+    // Senders expect to be greeted like the controller does after a reset
+    let welcome = Arc::new(Mutex::new(None));
+    let welcomer = received.subscribe()
+        .for_each({
+            let welcome = welcome.clone();
+            move |line| {
+                if let Ok(proto::GrblMessage::Welcome { .. }) = proto::GrblMessage::parse(&line) {
+                    *welcome.lock().unwrap() = Some(line);
+                }
+                return Ok(());
+            }
+        })
+        .map_err(|_| unreachable!());
+
+    let server = listener.incoming()
+        .map_err(Error::from)
+        .for_each(move |stream| {
+            let peer = stream.peer_addr()?;
+            log::info!("GRBL: Bridge client {} connected", peer);
+
+            // Realtime commands are single bytes which must not wait for more to come
+            stream.set_nodelay(true)?;
+
+            let welcome = welcome.lock().unwrap().clone();
+            tokio::spawn(client(stream, sender.clone(), received.subscribe(), welcome)
+                .then(move |result| {
+                    match result {
+                        Ok(()) => log::info!("GRBL: Bridge client {} disconnected", peer),
+                        Err(err) => log::warn!("GRBL: Bridge client {} failed: {}", peer, err),
+                    }
+                    return Ok(());
+                }));
+
+            return Ok(());
+        })
+        .or_else(|err| {
+            log::error!("GRBL: Bridge failed: {}", err);
+            return Ok(());
+        });
+
+    return server.join(welcomer)
+        .map(|_| ());
+}
+
+fn client<M>(stream: TcpStream,
+             sender: GrblSender,
+             messages: M,
+             welcome: Option<String>) -> impl Future<Item=(), Error=Error>
+    where M: Stream<Item=String, Error=()> {
+    let (reader, writer) = stream.split();
+
+    // Realtime commands are picked from the stream as they come, just like Grbl does, and the other bytes form lines
+    let lines = FramedRead::new(reader, BytesCodec::new())
+        .map_err(Error::from)
+        .map({
+            let sender = sender.clone();
+            let mut line = Vec::new();
+            move |data| {
+                let mut lines = Vec::new();
+                let capacity = sender.line_capacity();
+
+                for &byte in data.iter() {
+                    match proto::GrblRealtimeCommand::from_code(byte) {
+                        // Status reports polled by carbide are passed on anyway
+                        Some(proto::GrblRealtimeCommand::StatusReportQuery) => {}
+                        Some(command) => sender.send_grbl_realtime(command),
+                        None if byte == b'\n' || byte == b'\r' => lines.push(mem::replace(&mut line, Vec::new())),
+
+                        // Lines not fitting into the receive buffer are refused by the sender, so there is no need to
+                        // keep more of them
+                        None if line.len() >= capacity => {}
+                        None => line.push(byte),
+                    }
+                }
+
+                return stream::iter_ok(lines);
+            }
+        })
+        .flatten();
+
+    // Lines share the buffer with all other commands, responses go back to the sender of the line only
+    let responses = lines
+        .map(move |line| sender.send_line(&String::from_utf8_lossy(&line))
+            .then(|response| Ok(match response {
+                Ok(controller::Response::Ok) => Some("ok".to_owned()),
+                Ok(controller::Response::Error(fault)) => fault.code.map(|code| format!("error:{}", code)),
+
+                // Grbl silently drops the lines discarded by a reset or a disconnect
+                Err(_) => None,
+            })))
+        .buffered(MAX_PENDING_LINES)
+        .filter_map(|response| response);
+
+    // Responses to other commands are not meant for this sender
+    let messages = messages
+        .map_err(|_| -> Error { unreachable!() })
+        .filter(|line| match proto::GrblMessage::parse(line) {
+            Ok(proto::GrblMessage::Response(_)) => false,
+            _ => true,
+        });
+
+    let output = Output {
+        messages,
+        responses,
+        response: None,
+    };
+
+    return stream::iter_ok(welcome)
+        .chain(output)
+        .map(|line| Bytes::from(format!("{}\r\n", line)))
+        .forward(FramedWrite::new(writer, BytesCodec::new()))
+        .map(|_| ());
+}
+
+// Merges responses into the messages received from the controller. A response is passed on only after all messages
+// received before it, as these may be output of the command it acknowledges.
+struct Output<M, R>
+    where M: Stream<Item=String, Error=Error>,
+          R: Stream<Item=String, Error=Error> {
+    messages: M,
+    responses: R,
+    response: Option<String>,
+}
+
+impl<M, R> Stream for Output<M, R>
+    where M: Stream<Item=String, Error=Error>,
+          R: Stream<Item=String, Error=Error> {
+    type Item = String;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.response.is_none() {
+            match self.responses.poll()? {
+                Async::Ready(Some(response)) => self.response = Some(response),
+
+                // The sender hung up
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => {}
+            }
+        }
+
+        if let Async::Ready(message) = self.messages.poll()? {
+            return Ok(Async::Ready(message));
+        }
+
+        return Ok(match self.response.take() {
+            Some(response) => Async::Ready(Some(response)),
+            None => Async::NotReady,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Write;
+    use std::net;
+    use std::time::Duration;
+
+    use super::*;
+    use super::super::GrblController;
+    use super::super::GrblSimulatorConfig;
+
+    #[test]
+    fn test_config() {
+        let config: BridgeConfig = serde_yaml::from_str("port: 2323").unwrap();
+        assert_eq!(config.host, IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let config: GrblSimulatorConfig = serde_yaml::from_str("bridge: {host: 0.0.0.0, port: 2323}").unwrap();
+        assert_eq!(config.bridge.map(|bridge| bridge.port), Some(2323));
+    }
+
+    #[test]
+    fn test_bridge() {
+        // Grab a free port and release it again
+        let port = net::TcpListener::bind("127.0.0.1:0").unwrap()
+            .local_addr().unwrap()
+            .port();
+
+        let (_controller, driver) = GrblController::simulated(&GrblSimulatorConfig {
+            settings: BTreeMap::new(),
+            bridge: Some(BridgeConfig {
+                host: default_host(),
+                port,
+            }),
+            record: None,
+        }).unwrap();
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(driver.map_err(|err| panic!("{}", err)));
+
+        // Give the controller some time to connect so the banner is known
+        std::thread::sleep(Duration::from_millis(500));
+
+        let mut stream = net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // Carbide queries the parser state on its own, which reaches all senders
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines()
+            .map(|line| line.unwrap())
+            .filter(|line| !line.is_empty() && !line.starts_with('<') && !line.starts_with("[GC:"));
+
+        assert_eq!(lines.next().unwrap(), "Grbl 1.1h ['$' for help]");
+
+        stream.write_all(b"$X\nG0 X-1\n").unwrap();
+        assert_eq!(lines.next().unwrap(), "[MSG:Caution: Unlocked]");
+        assert_eq!(lines.next().unwrap(), "ok");
+        assert_eq!(lines.next().unwrap(), "ok");
+
+        // Grbl skips spaces, so lines only need to fit into its receive buffer
+        stream.write_all(format!("G0 X-2{}\n", " ".repeat(100)).as_bytes()).unwrap();
+        assert_eq!(lines.next().unwrap(), "ok");
+
+        // Lines too long for the receive buffer are refused without getting in the way of others
+        stream.write_all(format!("G0 X-2.{}\nG0 X-3\n", "0".repeat(200)).as_bytes()).unwrap();
+        assert_eq!(lines.next().unwrap(), "error:11");
+        assert_eq!(lines.next().unwrap(), "ok");
+
+        stream.write_all(b"G0 X10\n").unwrap();
+        assert_eq!(lines.next().unwrap(), "ALARM:2");
+        assert_eq!(lines.next().unwrap(), "[MSG:Reset to continue]");
+
+        // Realtime commands are forwarded right away
+        stream.write_all(&[0x18]).unwrap();
+        assert_eq!(lines.next().unwrap(), "Grbl 1.1h ['$' for help]");
+        assert_eq!(lines.next().unwrap(), "[MSG:'$H'|'$X' to unlock]");
+    }
+}
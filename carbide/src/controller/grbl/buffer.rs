@@ -1,4 +1,6 @@
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use futures::Async;
 use futures::AsyncSink;
@@ -11,38 +13,84 @@ use futures::try_ready;
 
 use super::proto;
 
-const BUFFER_SIZE: usize = 128;
+// Receive buffer size of stock Grbl, used until the controller reports its actual size
+const DEFAULT_BUFFER_SIZE: usize = 128;
 
-pub type Command = (String, oneshot::Sender<proto::GrblResponse>);
+// Error for commands which were sent but got lost in a reset of the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reset;
+
+pub type Outcome = Result<proto::GrblResponse, Reset>;
+
+pub type Command = (String, oneshot::Sender<Outcome>);
 
 struct Inner {
-    outstanding: VecDeque<(oneshot::Sender<proto::GrblResponse>, usize)>,
-    remaining: usize,
+    outstanding: VecDeque<(oneshot::Sender<Outcome>, usize)>,
+
+    // Bytes sent to the controller but not yet acknowledged
+    used: usize,
 }
 
 pub struct Sender<S, E>
     where S: Stream<Item=Command, Error=E> {
     stream: Peekable<S>,
     inner: BiLock<Inner>,
+    flush: Arc<AtomicBool>,
+    capacity: Arc<AtomicUsize>,
 }
 
 pub struct Tracker {
     inner: BiLock<Inner>,
 }
 
-pub fn sender<S, E>(stream: S) -> (Sender<S, E>, Tracker)
+// Handle to discard all queued commands not yet sent to the controller
+#[derive(Clone, Default)]
+pub struct Flush(Arc<AtomicBool>);
+
+impl Flush {
+    pub fn flush(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+// Handle to change the size of the controller's receive buffer
+#[derive(Clone)]
+pub struct Capacity(Arc<AtomicUsize>);
+
+impl Capacity {
+    pub fn resize(&self, size: usize) {
+        self.0.store(size, Ordering::SeqCst);
+    }
+
+    pub fn size(&self) -> usize {
+        return self.0.load(Ordering::SeqCst);
+    }
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        return Capacity(Arc::new(AtomicUsize::new(DEFAULT_BUFFER_SIZE)));
+    }
+}
+
+pub fn sender<S, E>(stream: S) -> (Sender<S, E>, Tracker, Flush, Capacity)
     where S: Stream<Item=Command, Error=E> {
     let (inner1, inner2) = BiLock::new(Inner {
         outstanding: VecDeque::new(),
-        remaining: BUFFER_SIZE,
+        used: 0,
     });
 
+    let flush = Arc::new(AtomicBool::new(false));
+    let capacity = Arc::new(AtomicUsize::new(DEFAULT_BUFFER_SIZE));
+
     return (Sender {
         stream: stream.peekable(),
         inner: inner1,
+        flush: flush.clone(),
+        capacity: capacity.clone(),
     }, Tracker {
         inner: inner2,
-    });
+    }, Flush(flush), Capacity(capacity));
 }
 
 impl<S, E> Stream for Sender<S, E>
@@ -51,6 +99,11 @@ impl<S, E> Stream for Sender<S, E>
     type Error = E;
 
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
+        if self.flush.swap(false, Ordering::SeqCst) {
+            // Dropping the commands cancels the response of each of them
+            while let Async::Ready(Some(_)) = self.stream.poll()? {}
+        }
+
         let next = try_ready!(self.stream.peek());
         if let Some(next) = next {
             let mut inner = match self.inner.poll_lock() {
@@ -58,10 +111,10 @@ impl<S, E> Stream for Sender<S, E>
                 Async::NotReady => return Ok(Async::NotReady),
             };
 
-            if inner.remaining >= next.0.len() {
+            if inner.used + next.0.len() <= self.capacity.load(Ordering::SeqCst) {
                 let next = try_ready!(self.stream.poll()).unwrap();
 
-                inner.remaining -= next.0.len();
+                inner.used += next.0.len();
                 inner.outstanding.push_back((next.1, next.0.len()));
 
                 return Ok(Async::Ready(Some(next.0)));
@@ -74,23 +127,55 @@ impl<S, E> Stream for Sender<S, E>
     }
 }
 
+// Matches responses to the commands sent and resynchronizes with the controller after it got reset
 impl Sink for Tracker {
-    type SinkItem = proto::GrblResponse;
+    type SinkItem = proto::GrblMessage;
     type SinkError = ();
 
     fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
-        let mut inner = match self.inner.poll_lock() {
-            Async::Ready(inner) => inner,
-            Async::NotReady => return Ok(AsyncSink::NotReady(item)),
-        };
+        match item {
+            proto::GrblMessage::Response(response) => {
+                let mut inner = match self.inner.poll_lock() {
+                    Async::Ready(inner) => inner,
+                    Async::NotReady => return Ok(AsyncSink::NotReady(proto::GrblMessage::Response(response))),
+                };
+
+                let (sender, length) = match inner.outstanding.pop_front() {
+                    Some(outstanding) => outstanding,
+                    None => {
+                        log::warn!("GRBL: Dropping response without command: {:?}", response);
+                        return Ok(AsyncSink::Ready);
+                    }
+                };
+
+                // The issuer of the command might not be interested in the response
+                let _ = sender.send(Ok(response));
+
+                inner.used -= length;
+            }
+
+            proto::GrblMessage::Welcome { version } => {
+                let mut inner = match self.inner.poll_lock() {
+                    Async::Ready(inner) => inner,
+                    Async::NotReady => return Ok(AsyncSink::NotReady(proto::GrblMessage::Welcome { version })),
+                };
 
-        let (sender, length) = inner.outstanding.pop_front()
-            .expect("Command tracking went totally wrong");
+                // The controller dropped its receive buffer and will never respond to the commands in there
+                if !inner.outstanding.is_empty() {
+                    log::warn!("GRBL: Controller reset with {} commands outstanding", inner.outstanding.len());
+                }
 
-        sender.send(item)
-            .expect("Receiver closed");
+                for (sender, _) in inner.outstanding.drain(..) {
+                    let _ = sender.send(Err(Reset));
+                }
 
-        inner.remaining += length;
+                // Commands queued up before the reset got flushed when it was sent, the ones queued since are meant for
+                // the controller after the reset
+                inner.used = 0;
+            }
+
+            _ => {}
+        }
 
         return Ok(AsyncSink::Ready);
     }
@@ -102,4 +187,52 @@ impl Sink for Tracker {
     fn close(&mut self) -> Result<Async<()>, Self::SinkError> {
         return Ok(Async::Ready(()));
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+    use futures::future;
+    use futures::stream;
+
+    use super::*;
+
+    #[test]
+    fn test_reset() {
+        future::lazy(|| {
+            let (senders, mut receivers): (Vec<_>, Vec<_>) = (0..5)
+                .map(|_| oneshot::channel())
+                .unzip();
+
+            let commands = senders.into_iter()
+                .map(|sender| ("G1 X1 Y1 Z1 F1000".repeat(3) + "\n", sender));
+
+            let (mut sender, mut tracker, flush, _) = sender(stream::iter_ok::<_, ()>(commands));
+
+            // The third command does not fit into the buffer
+            assert!(sender.poll().unwrap().is_ready());
+            assert!(sender.poll().unwrap().is_ready());
+            assert!(sender.poll().unwrap().is_not_ready());
+
+            tracker.start_send(proto::GrblMessage::Response(proto::GrblResponse::Ok)).unwrap();
+            assert_eq!(receivers[0].poll(), Ok(Async::Ready(Ok(proto::GrblResponse::Ok))));
+
+            tracker.start_send(proto::GrblMessage::Welcome { version: "1.1f".to_owned() }).unwrap();
+            assert_eq!(receivers[1].poll(), Ok(Async::Ready(Err(Reset))));
+
+            // Commands still queued are sent to the controller after the reset
+            assert!(sender.poll().unwrap().is_ready());
+            assert!(sender.poll().unwrap().is_ready());
+            assert!(sender.poll().unwrap().is_not_ready());
+
+            // Flushing discards them
+            flush.flush();
+            assert_eq!(sender.poll(), Ok(Async::Ready(None)));
+            assert!(receivers[4].poll().is_err());
+
+            // Responses without a command must not bring everything down
+            tracker.start_send(proto::GrblMessage::Response(proto::GrblResponse::Ok)).unwrap();
+
+            return Ok::<_, ()>(());
+        }).wait().unwrap();
+    }
+}
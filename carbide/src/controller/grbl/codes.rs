@@ -1,9 +1,4 @@
 #![allow(dead_code)]
 
-pub struct Setting {
-    pub name: &'static str,
-    pub unit: &'static str,
-    pub desc: &'static str,
-}
-
+// Alarm, error and setting codes as enums and build options as a map, all generated from Grbl's documentation
 include!(concat!(env!("OUT_DIR"), "/grbl_codes.rs"));
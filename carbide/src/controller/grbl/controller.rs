@@ -1,11 +1,19 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
+use std::time::Instant;
 
 use bytes::Bytes;
 use failure::Error;
 use futures::Async;
 use futures::Future;
 use futures::future;
-use futures::IntoFuture;
+use futures::future::Loop;
 use futures::Sink;
 use futures::Stream;
 use futures::sync::mpsc;
@@ -15,55 +23,243 @@ use tokio::codec::FramedRead;
 use tokio::codec::FramedWrite;
 use tokio::codec::LinesCodec;
 use tokio::io::AsyncRead;
+use tokio::net::TcpListener;
 use tokio::sync::watch;
+use tokio::timer::Delay;
 use tokio::timer::Interval;
-use tokio_serial as serial;
 
 use crate::controller;
+use crate::position::Position;
 use crate::utils::stream::broadcast::Broadcast;
+use crate::utils::stream::subscribers::Subscribers;
 
+use super::bridge;
+use super::bridge::BridgeConfig;
 use super::buffer;
 use super::GrblControllerConfig;
+use super::GrblSimulatorConfig;
 use super::proto;
+use super::recording;
 use super::codes;
+use super::simulator::Simulator;
 use super::state::State;
+use super::state::Update;
+use super::transport::Port;
+use super::transport::Transport;
 use crate::server;
 
-pub struct GrblController {
-    description: String,
+type LineSender = mpsc::UnboundedSender<(proto::GrblLineCommand, oneshot::Sender<buffer::Outcome>)>;
 
+// Channels to the currently connected controller
+#[derive(Clone)]
+struct Channels {
     // Sender used to send line commands to the controller
-    lines: mpsc::UnboundedSender<(proto::GrblLineCommand, oneshot::Sender<proto::GrblResponse>)>,
+    lines: LineSender,
 
     // Sender used to send realtime commands
     realtime: mpsc::UnboundedSender<proto::GrblRealtimeCommand>,
 
+    // Discards queued line commands
+    flush: buffer::Flush,
+
+    // Size of the controller's receive buffer
+    capacity: buffer::Capacity,
+}
+
+impl Channels {
+    // Channels dropping all commands while no controller is connected, which cancels their responses
+    fn closed() -> Self {
+        return Self {
+            lines: mpsc::unbounded().0,
+            realtime: mpsc::unbounded().0,
+            flush: buffer::Flush::default(),
+            capacity: buffer::Capacity::default(),
+        };
+    }
+}
+
+// Raised if the device on the port does not identify itself as a Grbl controller
+#[derive(Debug)]
+struct HandshakeError(String);
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+impl error::Error for HandshakeError {}
+
+// Raised if the device did not identify itself in time, which may change once it is up
+#[derive(Debug)]
+struct HandshakeTimeout(String);
+
+impl fmt::Display for HandshakeTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+impl error::Error for HandshakeTimeout {}
+
+pub struct GrblController {
+    description: String,
+
+    // Replaced on every reconnect
+    channels: Arc<Mutex<Channels>>,
+
     // State changes of the controller
     state: watch::Receiver<controller::State>,
+
+    // Raw traffic exchanged with the controller
+    console: Subscribers<controller::ConsoleMessage>,
+
+    // Every line received from the controller as is
+    received: Subscribers<String>,
 }
 
 impl GrblController {
-    const PORT_SETTINGS: serial::SerialPortSettings = serial::SerialPortSettings {
-        baud_rate: 115200,
-        data_bits: serial::DataBits::Eight,
-        flow_control: serial::FlowControl::None,
-        parity: serial::Parity::None,
-        stop_bits: serial::StopBits::One,
-        timeout: Duration::from_millis(1),
-    };
-
     // GRBL docs recommend 5Hz
     const STATUS_INTERVAL: Duration = Duration::from_millis(1000 / 5);
 
+    // Time given to the controller to identify itself after opening the port, which resets most boards
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Number of unrecognized lines after which the device is not taken for a Grbl controller
+    const MAX_FOREIGN_LINES: usize = 3;
+
+    // Delays between attempts to reopen the port, doubling while it fails to open or the device does not answer
+    const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+    const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
     pub fn new(config: &GrblControllerConfig) -> Result<(Self, impl Future<Item=(), Error=Error>), Error> {
+        let (controller, driver) = Self::with_transport(recording::recorded(config.transport.transport(),
+                                                                                   config.record.as_ref()))?;
+        return controller.with_bridge(driver, config.bridge.as_ref());
+    }
+
+    pub fn simulated(config: &GrblSimulatorConfig) -> Result<(Self, impl Future<Item=(), Error=Error>), Error> {
+        let (controller, driver) = Self::with_transport(recording::recorded(Box::new(Simulator::new(config.settings.clone())),
+                                                                                   config.record.as_ref()))?;
+        return controller.with_bridge(driver, config.bridge.as_ref());
+    }
+
+    // Serves the bridge for other senders next to the driver, if configured
+    fn with_bridge(self,
+                   driver: impl Future<Item=(), Error=Error>,
+                   config: Option<&BridgeConfig>) -> Result<(Self, impl Future<Item=(), Error=Error>), Error> {
+        let bridge = match config {
+            Some(config) => {
+                let listener = TcpListener::bind(&SocketAddr::new(config.host, config.port))?;
+                future::Either::A(bridge::serve(listener, self.grbl_sender(), self.received.clone()))
+            }
+            None => future::Either::B(future::ok(())),
+        };
+
+        return Ok((self, driver.join(bridge).map(|_| ())));
+    }
+
+    pub fn with_transport(transport: Box<Transport>) -> Result<(Self, impl Future<Item=(), Error=Error>), Error> {
+        let name = transport.to_string();
+
+        let channels = Arc::new(Mutex::new(Channels::closed()));
+        let console = Subscribers::new();
+        let received = Subscribers::new();
+
+        // The state survives reconnects and is fed by the current connection
+        let (state, state_watch) = State::new();
+        let (updates, update_receiver) = mpsc::unbounded();
+        let state_handler = update_receiver
+            .forward(state)
+            .map_err(|_| unreachable!())
+            .map(|_| ());
+
+        // Keep reconnecting until the device turns out to be something else than a Grbl controller
+        let connector = future::loop_fn(Self::RECONNECT_DELAY_MIN, {
+            let name = name.clone();
+            let channels = channels.clone();
+            let console = console.clone();
+            let received = received.clone();
+            let state_watch = state_watch.clone();
+            move |delay| {
+                let session = transport.open()
+                    .then({
+                        let name = name.clone();
+                        let channels = channels.clone();
+                        let console = console.clone();
+                        let received = received.clone();
+                        let updates = updates.clone();
+                        let state_watch = state_watch.clone();
+                        move |port| match port {
+                            // Start over with short delays after the controller answered
+                            Ok(port) => future::Either::A(Self::connect(&name, port, &channels, &console, &received, &updates, &state_watch)
+                                .then(move |result| match result {
+                                    Err(ref err) if err.downcast_ref::<HandshakeTimeout>().is_some() => {
+                                        Ok((result, delay, cmp::min(delay * 2, Self::RECONNECT_DELAY_MAX)))
+                                    }
+                                    _ => Ok((result, Self::RECONNECT_DELAY_MIN, Self::RECONNECT_DELAY_MIN)),
+                                })),
+                            Err(err) => future::Either::B(future::ok((Err(err), delay, cmp::min(delay * 2, Self::RECONNECT_DELAY_MAX)))),
+                        }
+                    });
+
+                let channels = channels.clone();
+                let updates = updates.clone();
+                return session.and_then(move |(result, delay, next)| -> Box<Future<Item=Loop<(), Duration>, Error=Error> + Send> {
+                    *channels.lock().unwrap() = Channels::closed();
+
+                    let err = match result {
+                        Ok(()) => failure::err_msg("Connection closed"),
+                        Err(err) => err,
+                    };
+
+                    if let Some(err) = err.downcast_ref::<HandshakeError>() {
+                        log::error!("GRBL: {}", err);
+                        return Box::new(future::ok(Loop::Break(())));
+                    }
+
+                    log::warn!("GRBL: Disconnected, retrying in {}s: {}", delay.as_secs(), err);
+                    let _ = updates.unbounded_send(Update::Connection(controller::Connection::Disconnected(err.to_string())));
+
+                    return Box::new(Delay::new(Instant::now() + delay)
+                        .map_err(Error::from)
+                        .map(move |_| Loop::Continue(next)));
+                });
+            }
+        });
+
+        let driver = connector.join(state_handler)
+            .map(|_| ());
+
+        return Ok((Self {
+            description: format!("GRBL: {}", name),
+            channels,
+            state: state_watch,
+            console,
+            received,
+        }, driver));
+    }
+
+    // Talks to the controller on an opened port until the connection fails
+    fn connect(name: &str,
+               port: Box<Port>,
+               channels: &Mutex<Channels>,
+               console: &Subscribers<controller::ConsoleMessage>,
+               received: &Subscribers<String>,
+               updates: &mpsc::UnboundedSender<Update>,
+               state_watch: &watch::Receiver<controller::State>) -> Box<Future<Item=(), Error=Error> + Send> {
+        let (reader, writer) = port.split();
+
+        let _ = updates.unbounded_send(Update::Connection(controller::Connection::Connecting));
+
         // Create channel for sending commands
         let (line_sender, line_receiver) = mpsc::unbounded();
         let (realtime_sender, realtime_receiver) = mpsc::unbounded();
 
         // Process line commands through streamer to avoid buffer underflow
         let line_receiver = line_receiver
-            .map(|cmd: (proto::GrblLineCommand, oneshot::Sender<proto::GrblResponse>)| (format!("{}\n", cmd.0.into_string()), cmd.1));
-        let (line_receiver, line_tracker) = buffer::sender(line_receiver);
+            .map(|cmd: (proto::GrblLineCommand, oneshot::Sender<buffer::Outcome>)| (format!("{}\n", cmd.0.into_string()), cmd.1));
+        let (line_receiver, line_tracker, line_flush, line_capacity) = buffer::sender(line_receiver);
 
         // Send status queries request commands to controller every now and then
         let status_poller = Interval::new_interval(Self::STATUS_INTERVAL)
@@ -79,24 +275,24 @@ impl GrblController {
             realtime_receiver.map(|cmd| Bytes::from(cmd.to_code())),
         );
 
-        // Open serial port
-        let mut port = serial::Serial::from_path(&config.path, &Self::PORT_SETTINGS)?;
-        port.set_exclusive(false)?;
-
-        let (reader, writer) = port.split();
-
-        log::info!("GRBL: Port opened");
-
         let writer = FramedWrite::new(writer, BytesCodec::new());
 
-        // Wake up controller by sending some newlines
-        // let writer = writer.send(Bytes::from_static(b"\r\n\r\n"));
-        // TODO: Send some newlines and sleep a second to wake up controller
+        // Wake up controller by sending an empty line, which is acknowledged like any other
+        send(&line_sender, proto::GrblLineCommand::Line(String::new()));
 
         // Write commands to controller
         let writer = receiver
             .map_err(|_| unreachable!()) // FIXME
             .inspect(|cmd| log::trace!("GRBL > {:?}", cmd))
+            .inspect({
+                let console = console.clone();
+                move |cmd| {
+                    // Status polling would drown everything else
+                    if cmd.as_ref() != b"?" {
+                        console.publish(controller::ConsoleMessage::new(controller::Direction::Sent, escape(cmd)));
+                    }
+                }
+            })
             .fold(writer, |writer, cmd| {
                 // Flush after each send
                 return writer.send(cmd)
@@ -107,76 +303,491 @@ impl GrblController {
 
         // Handle incoming messages from controller
         let reader = FramedRead::new(reader, LinesCodec::new())
+            .then(|line| match line {
+                Ok(line) => Ok(Some(line)),
+
+                // Drop lines garbled by noise on the line instead of giving up on the connection
+                Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+                    log::warn!("GRBL: Dropping garbled line: {}", err);
+                    Ok(None)
+                }
+
+                Err(err) => Err(err),
+            })
+            .filter_map(|line| line)
             .map_err(Error::from)
             .inspect(|msg| log::trace!("GRBL < {:?}", msg))
-            .and_then(|msg| proto::GrblMessage::parse(&msg).into_future())
+            .inspect({
+                let received = received.clone();
+                move |msg| received.publish(msg.clone())
+            })
+            .inspect({
+                let console = console.clone();
+                move |msg| {
+                    if !msg.starts_with('<') {
+                        console.publish(controller::ConsoleMessage::new(controller::Direction::Received, msg.trim_end().to_owned()));
+                    }
+                }
+            })
+            .filter_map(|msg| match proto::GrblMessage::parse(&msg) {
+                Ok(msg) => Some(msg),
+                Err(err) => {
+                    log::warn!("GRBL: Failed to parse message: {}", err);
+                    None
+                }
+            })
             .inspect(|msg| log::trace!("GRBL << {:?}", msg));
 
         let mut reader = Broadcast::new(reader);
 
         // Handle response messages
         let response_handler = reader.receive()
+            .forward(line_tracker)
+            .map_err(|_| unreachable!())
+            .map(|_| ());
+
+        // Use the whole receive buffer of controllers reporting a size different to stock Grbl
+        let buffer_sizer = reader.receive()
             .filter_map(|msg| match msg {
-                proto::GrblMessage::Response(response) => Some(response),
+                proto::GrblMessage::BuildOptions(options) => options.rx_buffer,
                 _ => None,
             })
-            .forward(line_tracker)
+            .for_each({
+                let line_capacity = line_capacity.clone();
+                move |size| {
+                    log::info!("GRBL: Receive buffer size is {} bytes", size);
+                    line_capacity.resize(size);
+                    return Ok(());
+                }
+            })
+            .map_err(|_| unreachable!());
+
+        // The controller identifies itself by its banner after being reset by opening the port, or by a status report
+        // if it kept running. A device sending anything else is not a Grbl controller.
+        let handshake = reader.receive()
+            .map(Some)
+            .select(Delay::new(Instant::now() + Self::HANDSHAKE_TIMEOUT)
+                .into_stream()
+                .map(|_| None)
+                .map_err(|_| ()))
+            .filter_map({
+                let name = name.to_owned();
+                let mut connection = controller::Connection::Connecting;
+                let mut foreign = 0;
+                move |msg| {
+                    if connection != controller::Connection::Connecting {
+                        return None;
+                    }
+
+                    connection = match msg {
+                        Some(proto::GrblMessage::Welcome { .. }) | Some(proto::GrblMessage::StatusReport(_)) => {
+                            controller::Connection::Ready
+                        }
+                        Some(proto::GrblMessage::Other(line)) => {
+                            foreign += 1;
+                            if foreign < Self::MAX_FOREIGN_LINES {
+                                return None;
+                            }
+                            controller::Connection::Failed(format!("Device on {} is not a Grbl controller, it sent {:?}", name, line))
+                        }
+                        Some(_) => return None,
+                        None => controller::Connection::Disconnected(format!("No response from {} within {} seconds",
+                                                                             name, Self::HANDSHAKE_TIMEOUT.as_secs())),
+                    };
+
+                    return Some(connection.clone());
+                }
+            })
+            .inspect(|connection| if let controller::Connection::Ready = connection {
+                log::info!("GRBL: Controller ready");
+            });
+
+        // Query build info, settings, parameters and parser state to learn about capabilities, buffer size, report
+        // units, machine limits, offsets and modes. Like after a reset, this waits for the first status report, which
+        // follows the banner once the controller is up.
+        let setup = reader.receive()
+            .fold(false, {
+                let line_sender = line_sender.clone();
+                move |done, msg| {
+                    if let (false, proto::GrblMessage::StatusReport(_)) = (done, msg) {
+                        send(&line_sender, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewBuildInfo));
+                        send(&line_sender, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewSettings));
+                        send(&line_sender, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParameters));
+                        send(&line_sender, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParserState));
+                        return Ok(true);
+                    }
+                    return Ok(done);
+                }
+            })
             .map_err(|_| unreachable!())
             .map(|_| ());
 
-        // Handle state updates
-        let (state, state_watch) = State::new();
+        // Handle state updates, ending the connection once the handshake failed or timed out
         let state_handler = reader.receive()
-            .forward(state)
+            .map(Update::Message)
+            .select(handshake.map(Update::Connection))
+            .map_err(|_| -> Error { unreachable!() })
+            .for_each({
+                let updates = updates.clone();
+                move |update| {
+                    let failed = match update {
+                        Update::Connection(controller::Connection::Failed(ref reason)) => Some(HandshakeError(reason.clone())),
+                        // Reported by the reconnect loop, which tries again
+                        Update::Connection(controller::Connection::Disconnected(ref reason)) => {
+                            return Err(Error::from(HandshakeTimeout(reason.clone())));
+                        }
+                        _ => None,
+                    };
+
+                    let _ = updates.unbounded_send(update);
+
+                    return match failed {
+                        Some(err) => Err(Error::from(err)),
+                        None => Ok(()),
+                    };
+                }
+            });
+
+        // A reset restores the default modes and runs the startup blocks, so query them again. This waits for the next
+        // status report to make sure the commands queued before the reset have been discarded.
+        let reset_handler = reader.receive()
+            .fold(false, {
+                let line_sender = line_sender.clone();
+                move |reset, msg| {
+                    return Ok(match msg {
+                        proto::GrblMessage::Welcome { .. } => true,
+                        proto::GrblMessage::StatusReport(_) if reset => {
+                            send(&line_sender, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParserState));
+                            false
+                        }
+                        _ => reset,
+                    });
+                }
+            })
+            .map_err(|_| unreachable!())
+            .map(|_| ());
+
+        // Jobs, jogs and manual commands may change modes, so query them again whenever the machine comes to rest
+        let modal_query = state_watch.clone()
+            .map(|state| match state.status {
+                controller::MachineStatus::Idle => true,
+                _ => false,
+            })
+            .fold(true, {
+                let line_sender = line_sender.clone();
+                move |was_idle, idle| {
+                    if idle && !was_idle {
+                        send(&line_sender, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParserState));
+                    }
+                    return Ok(idle);
+                }
+            })
             .map_err(|_| unreachable!())
             .map(|_| ());
 
-        let driver = future::join_all::<Vec<Box<Future<Item=(), Error=Error> + Send>>>(vec![
+        // The port vanishing may end the stream instead of failing it
+        let reader = reader
+            .and_then(|_| Err::<(), _>(failure::err_msg("Port closed")));
+
+        *channels.lock().unwrap() = Channels {
+            lines: line_sender,
+            realtime: realtime_sender,
+            flush: line_flush,
+            capacity: line_capacity,
+        };
+
+        let session = future::join_all::<Vec<Box<Future<Item=(), Error=Error> + Send>>>(vec![
             Box::new(reader),
             Box::new(writer),
             Box::new(status_poller),
             Box::new(response_handler),
+            Box::new(buffer_sizer),
             Box::new(state_handler),
+            Box::new(modal_query),
+            Box::new(reset_handler),
+            Box::new(setup),
         ]).map(|_| ());
 
-        return Ok((Self {
-            description: format!("GRBL: {}", &config.path),
-            lines: line_sender,
-            realtime: realtime_sender,
-            state: state_watch,
-        }, driver));
+        return Box::new(session);
+    }
+
+    fn lines(&self) -> LineSender {
+        return self.channels.lock().unwrap().lines.clone();
+    }
+
+    fn grbl_sender(&self) -> GrblSender {
+        return GrblSender {
+            channels: self.channels.clone(),
+        };
     }
 }
 
+// Renders raw commands as text with realtime commands and control characters escaped
+fn escape(cmd: &Bytes) -> String {
+    let cmd = if cmd.ends_with(b"\n") { &cmd[..cmd.len() - 1] } else { &cmd[..] };
+
+    return cmd.iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect();
+}
+
 impl controller::Controller for GrblController {
     fn description(&self) -> (server::ControllerType, &str) {
         return (server::ControllerType::Grbl, &self.description);
     }
 
     fn sender(&self) -> Box<controller::Sender + Send> {
-        return Box::new(GrblSender(self.lines.clone()));
+        return Box::new(self.grbl_sender());
     }
 
     fn state(&self) -> Box<Stream<Item=controller::State, Error=()> + Send> {
         return Box::new(self.state.clone()
             .map_err(|_| unreachable!()));
     }
+
+    fn current_state(&self) -> controller::State {
+        return (*self.state.get_ref()).clone();
+    }
+
+    fn console(&self) -> Box<Stream<Item=controller::ConsoleMessage, Error=()> + Send> {
+        return Box::new(self.console.subscribe());
+    }
+
+    fn set_coordinate_system(&self, index: usize, offset: Position) -> Box<Future<Item=controller::Response, Error=controller::Canceled> + Send> {
+        if index >= 6 {
+            return Box::new(future::ok(controller::Response::Error(controller::Fault::new(&format!("Invalid coordinate system: {}", index)))));
+        }
+
+        let lines = self.lines();
+        let state = self.state.clone();
+
+        // Values are given in millimeters but switching units would stick, so they are converted to the current ones.
+        // These may have changed since the parser state was last reported, which is up to date once the query is
+        // acknowledged.
+        let response = send(&lines, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParserState))
+            .and_then(move |_| {
+                let line = match state.get_ref().modal.units {
+                    controller::Units::Millimeters => format!("G10 L2 P{} X{:.3} Y{:.3} Z{:.3}",
+                                                              index + 1, offset.x, offset.y, offset.z),
+                    controller::Units::Inches => format!("G10 L2 P{} X{:.4} Y{:.4} Z{:.4}",
+                                                         index + 1,
+                                                         offset.x / carbide_gcode::MILLIMETERS_PER_INCH,
+                                                         offset.y / carbide_gcode::MILLIMETERS_PER_INCH,
+                                                         offset.z / carbide_gcode::MILLIMETERS_PER_INCH),
+                };
+
+                let response = send(&lines, proto::GrblLineCommand::Line(line));
+
+                // Read back the stored offsets and the active coordinate system
+                send(&lines, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParameters));
+                send(&lines, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewParserState));
+
+                return response;
+            });
+
+        return Box::new(response);
+    }
+
+    fn unlock(&self) -> Box<Future<Item=controller::Response, Error=controller::Canceled> + Send> {
+        return send(&self.lines(), proto::GrblLineCommand::System(proto::GrblSystemCommand::KillAlarmLock));
+    }
+
+    fn home(&self) -> Box<Future<Item=controller::Response, Error=controller::Canceled> + Send> {
+        return send(&self.lines(), proto::GrblLineCommand::System(proto::GrblSystemCommand::RunHomingCycle));
+    }
+
+    // Grbl only takes relative steps, so these start from the override last reported. Changing it again before the
+    // next report adds up with the steps still under way.
+    fn set_feed_override(&self, percent: f64) {
+        let sender = self.grbl_sender();
+        for step in proto::GrblFeedOverride::steps(self.current_state().overrides.feed, percent) {
+            sender.send_grbl_realtime(proto::GrblRealtimeCommand::FeedOverride(step));
+        }
+    }
+
+    fn set_rapid_override(&self, rapids: controller::RapidOverride) {
+        self.grbl_sender().send_grbl_realtime(proto::GrblRealtimeCommand::RapidOverride(match rapids {
+            controller::RapidOverride::Full => proto::GrblRapidOverride::Full,
+            controller::RapidOverride::Half => proto::GrblRapidOverride::Half,
+            controller::RapidOverride::Quarter => proto::GrblRapidOverride::Quarter,
+        }));
+    }
+
+    fn set_speed_override(&self, percent: f64) {
+        let sender = self.grbl_sender();
+        for step in proto::GrblSpeedOverride::steps(self.current_state().overrides.speed, percent) {
+            sender.send_grbl_realtime(proto::GrblRealtimeCommand::SpeedOverride(step));
+        }
+    }
+
+    fn write_setting(&self, code: u8, value: f64) -> Box<Future<Item=controller::Response, Error=controller::Canceled> + Send> {
+        let lines = self.lines();
+
+        let write = send(&lines, proto::GrblLineCommand::System(proto::GrblSystemCommand::WriteSetting {
+            code: codes::SettingCode::from_code(code),
+            value,
+        }));
+
+        // Grbl has no command to query a single setting
+        let read = send(&lines, proto::GrblLineCommand::System(proto::GrblSystemCommand::ViewSettings));
+
+        // Settings are reported before the read is acknowledged, so the state is up to date once it completes
+        let state = self.state.clone();
+
+        return Box::new(write.join(read).map(move |(write, read)| {
+            if let controller::Response::Error(_) = write {
+                return write;
+            }
+
+            if let controller::Response::Error(_) = read {
+                return read;
+            }
+
+            let stored = state.get_ref().settings.iter()
+                .find(|setting| setting.code == code)
+                .map(|setting| setting.value);
+
+            // Grbl reports settings with three decimals
+            return match stored {
+                Some(stored) if (stored - value).abs() < 0.001 => controller::Response::Ok,
+                Some(stored) => controller::Response::Error(controller::Fault::new(
+                    &format!("Setting ${} reads back as {} instead of {}", code, stored, value))),
+                None => controller::Response::Error(controller::Fault::new(
+                    &format!("Setting ${} is not reported by the controller", code))),
+            };
+        }));
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct GrblSender {
+    channels: Arc<Mutex<Channels>>,
 }
 
-struct GrblSender(mpsc::UnboundedSender<(proto::GrblLineCommand, oneshot::Sender<proto::GrblResponse>)>);
+impl GrblSender {
+    pub(super) fn send_grbl_realtime(&self, command: proto::GrblRealtimeCommand) {
+        let channels = self.channels.lock().unwrap();
+
+        // Grbl drops its own buffer on reset, so drop the lines queued up for it, too
+        if let proto::GrblRealtimeCommand::SoftReset = command {
+            channels.flush.flush();
+        }
+
+        // Commands are dropped while disconnected
+        let _ = channels.realtime.unbounded_send(command);
+    }
+
+    // Size of the controller's receive buffer, which limits the length of lines including their line feed
+    pub(super) fn line_capacity(&self) -> usize {
+        return self.channels.lock().unwrap().capacity.size();
+    }
+}
+
+// Queues a line command and resolves to the controller's response
+fn send(lines: &LineSender,
+        command: proto::GrblLineCommand) -> Box<Future<Item=controller::Response, Error=controller::Canceled> + Send> {
+    let (sender, receiver) = oneshot::channel();
+
+    // A closed channel drops the sender which cancels the response
+    let _ = lines.unbounded_send((command, sender));
+
+    return Box::new(receiver.map(|response| {
+        return match response {
+            Ok(proto::GrblResponse::Ok) => controller::Response::Ok,
+            Ok(proto::GrblResponse::Error(code)) => controller::Response::Error(code.into()),
+            Err(buffer::Reset) => controller::Response::Error(controller::Fault::new("Controller was reset")),
+        };
+    }));
+}
 
 impl controller::Sender for GrblSender {
     fn send_line(&self, line: &str) -> Box<Future<Item=controller::Response, Error=controller::Canceled> + Send> {
-        let (sender, receiver) = oneshot::channel();
+        // A line not fitting into the controller's receive buffer at all would stall all commands queued behind it,
+        // so it is refused like an overflowing line
+        if line.len() >= self.line_capacity() {
+            return Box::new(future::ok(controller::Response::Error(codes::ErrorCode::from_code(11).into())));
+        }
 
-        self.0.unbounded_send((proto::GrblLineCommand::Line(line.to_owned()), sender))
-            .unwrap();
+        return send(&self.channels.lock().unwrap().lines, proto::GrblLineCommand::Line(line.to_owned()));
+    }
 
-        return Box::new(receiver.map(|response| {
-            return match response {
-                proto::GrblResponse::Ok => controller::Response::Ok,
-                proto::GrblResponse::Error(code) => controller::Response::Error(codes::ERROR_CODES.get(&code).unwrap().to_string()),
-            };
-        }));
+    fn send_realtime(&self, command: controller::RealtimeCommand) {
+        self.send_grbl_realtime(match command {
+            controller::RealtimeCommand::FeedHold => proto::GrblRealtimeCommand::FeedHold,
+            controller::RealtimeCommand::CycleStart => proto::GrblRealtimeCommand::CycleStartResume,
+            controller::RealtimeCommand::SoftReset => proto::GrblRealtimeCommand::SoftReset,
+            controller::RealtimeCommand::SafetyDoor => proto::GrblRealtimeCommand::SafetyDoor,
+            controller::RealtimeCommand::JogCancel => proto::GrblRealtimeCommand::JogCancel,
+            controller::RealtimeCommand::ToggleSpindleStop => proto::GrblRealtimeCommand::ToggleSpindleStop,
+            controller::RealtimeCommand::ToggleFloodCoolant => proto::GrblRealtimeCommand::ToggleFloodCoolant,
+            controller::RealtimeCommand::ToggleMistCoolant => proto::GrblRealtimeCommand::ToggleMistCoolant,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::net;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::controller::Controller;
+
+    use super::*;
+    use super::super::transport::TcpConfig;
+
+    // Reaches a device staying silent on the first attempt and a simulated controller afterwards
+    struct Waking {
+        silent: TcpConfig,
+        opened: AtomicBool,
+    }
+
+    impl fmt::Display for Waking {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            return write!(f, "waking device");
+        }
+    }
+
+    impl Transport for Waking {
+        fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send> {
+            if self.opened.swap(true, Ordering::SeqCst) {
+                return Simulator::new(BTreeMap::new()).open();
+            }
+
+            return self.silent.open();
+        }
+    }
+
+    #[test]
+    fn test_handshake_timeout() {
+        // Connections are accepted by the system, but nothing is ever sent on them
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let (controller, driver) = GrblController::with_transport(Box::new(Waking {
+            silent: TcpConfig {
+                host: address.ip().to_string(),
+                port: address.port(),
+            },
+            opened: AtomicBool::new(false),
+        })).unwrap();
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(driver.map_err(|err| panic!("{}", err)));
+
+        let mut disconnected = false;
+        let deadline = Instant::now() + GrblController::HANDSHAKE_TIMEOUT * 3;
+        while controller.current_state().connection != controller::Connection::Ready {
+            assert!(Instant::now() < deadline, "Controller did not become ready");
+
+            if let controller::Connection::Disconnected(_) = controller.current_state().connection {
+                disconnected = true;
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        assert!(disconnected);
     }
 }
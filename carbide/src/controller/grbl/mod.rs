@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
 use serde_derive::Deserialize;
 
 mod proto;
@@ -5,10 +8,36 @@ mod codes;
 mod buffer;
 mod state;
 mod controller;
+mod transport;
+mod simulator;
+mod bridge;
+mod recording;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct GrblControllerConfig {
-    pub path: String,
+    #[serde(flatten)]
+    pub transport: transport::TransportConfig,
+
+    // Socket for other senders to drive the machine through carbide
+    #[serde(default)]
+    pub bridge: Option<bridge::BridgeConfig>,
+
+    // File to append all traffic with the controller to, which can be replayed later
+    #[serde(default)]
+    pub record: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GrblSimulatorConfig {
+    // Settings of the simulated controller differing from its defaults, like `130: 500.0` for more travel on X
+    #[serde(default)]
+    pub settings: BTreeMap<u16, f64>,
+
+    #[serde(default)]
+    pub bridge: Option<bridge::BridgeConfig>,
+
+    #[serde(default)]
+    pub record: Option<PathBuf>,
 }
 
 pub use self::controller::GrblController;
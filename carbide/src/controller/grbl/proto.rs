@@ -1,15 +1,18 @@
 #![allow(dead_code)]
 
+use std::error;
+use std::fmt;
 use std::str::FromStr;
 
 use bytes::Bytes;
-use failure::Error;
 use lazy_static::lazy_static;
 use regex::{Regex, Captures};
 
 use crate::controller;
 use crate::position::Position;
 
+use super::codes;
+
 #[derive(Debug, Clone)]
 pub enum GrblRestoreCommand {
     Settings,
@@ -21,8 +24,7 @@ pub enum GrblRestoreCommand {
 pub enum GrblSystemCommand {
     Help,
     ViewSettings,
-    WriteSetting { code: u8, value: f64 },
-    // TODO: Replace code with enum
+    WriteSetting { code: codes::SettingCode, value: f64 },
     ViewParameters,
     ViewParserState,
     ViewBuildInfo,
@@ -42,7 +44,7 @@ impl GrblSystemCommand {
         return match self {
             GrblSystemCommand::Help => format!("$"),
             GrblSystemCommand::ViewSettings => format!("$$"),
-            GrblSystemCommand::WriteSetting { code, value } => format!("${}={}", code, value),
+            GrblSystemCommand::WriteSetting { code, value } => format!("${}={}", code.code(), value),
             GrblSystemCommand::ViewParameters => format!("$#"),
             GrblSystemCommand::ViewParserState => format!("$G"),
             GrblSystemCommand::ViewBuildInfo => format!("$I"),
@@ -87,6 +89,74 @@ pub enum GrblSpeedOverride {
     Decrease1,
 }
 
+// Percentages Grbl keeps feed and speed overrides within
+pub const MIN_OVERRIDE: i32 = 10;
+pub const MAX_OVERRIDE: i32 = 200;
+
+impl GrblFeedOverride {
+    // Steps changing the feed override from the current percentage to the target
+    pub fn steps(current: f64, target: f64) -> Vec<Self> {
+        return override_steps(current, target, (GrblFeedOverride::Reset,
+                                                GrblFeedOverride::Increase10,
+                                                GrblFeedOverride::Decrease10,
+                                                GrblFeedOverride::Increase1,
+                                                GrblFeedOverride::Decrease1));
+    }
+}
+
+impl GrblSpeedOverride {
+    // Steps changing the spindle speed override from the current percentage to the target
+    pub fn steps(current: f64, target: f64) -> Vec<Self> {
+        return override_steps(current, target, (GrblSpeedOverride::Reset,
+                                                GrblSpeedOverride::Increase10,
+                                                GrblSpeedOverride::Decrease10,
+                                                GrblSpeedOverride::Increase1,
+                                                GrblSpeedOverride::Decrease1));
+    }
+}
+
+// Grbl can only be told to reset an override to 100% or to change it by 10% or 1%. The shortest way to the target
+// may start with a reset and may overshoot by tens, unless Grbl would stop at a limit on the way.
+fn override_steps<T>(current: f64, target: f64, (reset, increase10, decrease10, increase1, decrease1): (T, T, T, T, T)) -> Vec<T>
+    where T: Clone {
+    let clamp = |percent: f64| (percent.round() as i32).max(MIN_OVERRIDE).min(MAX_OVERRIDE);
+
+    let current = clamp(current);
+    let target = clamp(target);
+
+    // Tens and ones from the given percentage to the target
+    let changes = |from: i32| {
+        let diff = target - from;
+        let tens = [diff.div_euclid(10), diff.div_euclid(10) + 1].iter()
+            .cloned()
+            .filter(|tens| (MIN_OVERRIDE..=MAX_OVERRIDE).contains(&(from + tens * 10)))
+            .min_by_key(|tens| tens.abs() + (diff - tens * 10).abs())
+            .unwrap();
+
+        return (tens, diff - tens * 10);
+    };
+
+    let (direct_tens, direct_ones) = changes(current);
+    let (reset_tens, reset_ones) = changes(100);
+
+    let mut steps = Vec::new();
+
+    let (tens, ones) = if 1 + reset_tens.abs() + reset_ones.abs() < direct_tens.abs() + direct_ones.abs() {
+        steps.push(reset);
+        (reset_tens, reset_ones)
+    } else {
+        (direct_tens, direct_ones)
+    };
+
+    let ten = if tens > 0 { increase10 } else { decrease10 };
+    steps.extend(std::iter::repeat(ten).take(tens.abs() as usize));
+
+    let one = if ones > 0 { increase1 } else { decrease1 };
+    steps.extend(std::iter::repeat(one).take(ones.abs() as usize));
+
+    return steps;
+}
+
 #[derive(Debug, Clone)]
 pub enum GrblRealtimeCommand {
     SoftReset,
@@ -136,6 +206,34 @@ impl GrblRealtimeCommand {
             GrblRealtimeCommand::ToggleMistCoolant => Bytes::from_static(&[0xA1]),
         };
     }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        return match code {
+            0x18 => Some(GrblRealtimeCommand::SoftReset),
+            b'?' => Some(GrblRealtimeCommand::StatusReportQuery),
+            b'~' => Some(GrblRealtimeCommand::CycleStartResume),
+            b'!' => Some(GrblRealtimeCommand::FeedHold),
+            0x84 => Some(GrblRealtimeCommand::SafetyDoor),
+            0x85 => Some(GrblRealtimeCommand::JogCancel),
+            0x90 => Some(GrblRealtimeCommand::FeedOverride(GrblFeedOverride::Reset)),
+            0x91 => Some(GrblRealtimeCommand::FeedOverride(GrblFeedOverride::Increase10)),
+            0x92 => Some(GrblRealtimeCommand::FeedOverride(GrblFeedOverride::Decrease10)),
+            0x93 => Some(GrblRealtimeCommand::FeedOverride(GrblFeedOverride::Increase1)),
+            0x94 => Some(GrblRealtimeCommand::FeedOverride(GrblFeedOverride::Decrease1)),
+            0x95 => Some(GrblRealtimeCommand::RapidOverride(GrblRapidOverride::Full)),
+            0x96 => Some(GrblRealtimeCommand::RapidOverride(GrblRapidOverride::Half)),
+            0x97 => Some(GrblRealtimeCommand::RapidOverride(GrblRapidOverride::Quarter)),
+            0x99 => Some(GrblRealtimeCommand::SpeedOverride(GrblSpeedOverride::Reset)),
+            0x9A => Some(GrblRealtimeCommand::SpeedOverride(GrblSpeedOverride::Increase10)),
+            0x9B => Some(GrblRealtimeCommand::SpeedOverride(GrblSpeedOverride::Decrease10)),
+            0x9C => Some(GrblRealtimeCommand::SpeedOverride(GrblSpeedOverride::Increase1)),
+            0x9D => Some(GrblRealtimeCommand::SpeedOverride(GrblSpeedOverride::Decrease1)),
+            0x9E => Some(GrblRealtimeCommand::ToggleSpindleStop),
+            0xA0 => Some(GrblRealtimeCommand::ToggleFloodCoolant),
+            0xA1 => Some(GrblRealtimeCommand::ToggleMistCoolant),
+            _ => None,
+        };
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -157,7 +255,7 @@ impl GrblLineCommand {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GrblResponse {
     Ok,
-    Error(u8), // TODO: Replace code with enum
+    Error(codes::ErrorCode),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -174,7 +272,7 @@ pub enum GrblMachineDoorStatus {
     Resuming,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GrblMachineState {
     Idle,
     Run,
@@ -185,6 +283,9 @@ pub enum GrblMachineState {
     Check,
     Home,
     Sleep,
+
+    // States of forks like grblHAL's `Tool` for tool changes
+    Unknown(String),
 }
 
 impl Into<controller::MachineStatus> for GrblMachineState {
@@ -207,6 +308,7 @@ impl Into<controller::MachineStatus> for GrblMachineState {
             GrblMachineState::Check => controller::MachineStatus::Check,
             GrblMachineState::Home => controller::MachineStatus::Home,
             GrblMachineState::Sleep => controller::MachineStatus::Sleep,
+            GrblMachineState::Unknown(state) => controller::MachineStatus::Unknown(state),
         };
     }
 }
@@ -219,8 +321,8 @@ pub enum GrblPositionStatus {
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct GrblBufferStatus {
-    pub planner: u8,
-    pub rx: u8,
+    pub planner: usize,
+    pub rx: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -281,6 +383,74 @@ impl Default for GrblAccessoryStatus {
     }
 }
 
+impl From<GrblBufferStatus> for controller::BufferState {
+    fn from(status: GrblBufferStatus) -> Self {
+        return Self {
+            planner: status.planner,
+            rx: status.rx,
+        };
+    }
+}
+
+impl From<GrblInputPinsStatus> for controller::InputPins {
+    fn from(status: GrblInputPinsStatus) -> Self {
+        return Self {
+            x_limit: status.x_limit,
+            y_limit: status.y_limit,
+            z_limit: status.z_limit,
+            probe: status.probe,
+            door: status.door,
+            hold: status.hold,
+            soft_reset: status.soft_reset,
+            cycle_start: status.cycle_start,
+        };
+    }
+}
+
+impl From<GrblOverrridesStatus> for controller::Overrides {
+    fn from(status: GrblOverrridesStatus) -> Self {
+        return Self {
+            feed: status.feed,
+            rapids: status.rapids,
+            speed: status.speed,
+        };
+    }
+}
+
+impl From<GrblAccessoryStatus> for controller::Accessories {
+    fn from(status: GrblAccessoryStatus) -> Self {
+        return Self {
+            spindle: match status.spindle {
+                GrblSpindleStatus::Off => controller::SpindleState::Off,
+                GrblSpindleStatus::CW => controller::SpindleState::Clockwise,
+                GrblSpindleStatus::CCW => controller::SpindleState::CounterClockwise,
+            },
+            flood_coolant: status.flood_coolant,
+            mist_coolant: status.mist_coolant,
+        };
+    }
+}
+
+impl From<codes::ErrorCode> for controller::Fault {
+    fn from(code: codes::ErrorCode) -> Self {
+        return Self {
+            code: Some(code.code()),
+            name: code.name().to_owned(),
+            description: code.description().to_owned(),
+        };
+    }
+}
+
+impl From<codes::AlarmCode> for controller::Fault {
+    fn from(code: codes::AlarmCode) -> Self {
+        return Self {
+            code: Some(code.code()),
+            name: code.name().to_owned(),
+            description: code.description().to_owned(),
+        };
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct GrblStatusReport {
     pub machine_state: GrblMachineState,
@@ -295,26 +465,225 @@ pub struct GrblStatusReport {
     pub accessory: Option<GrblAccessoryStatus>,
 }
 
+// Build options as reported by `$I`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrblBuildOptions {
+    // Letters of the enabled options, see `codes::BUILD_OPTION_CODES`
+    pub codes: String,
+
+    // Size of the planner and the receive buffer, not reported by versions before 1.1
+    pub planner_blocks: Option<usize>,
+    pub rx_buffer: Option<usize>,
+}
+
+impl GrblBuildOptions {
+    pub fn has(&self, code: char) -> bool {
+        return self.codes.contains(code);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrblParameter {
+    // Offset of the work coordinate systems G54 to G59 by index
+    CoordinateSystem(usize, Position),
+
+    // Positions stored by G28.1 and G30.1
+    Home(Position),
+    SecondaryHome(Position),
+
+    // Offset set by G92
+    Offset(Position),
+
+    ToolLengthOffset(f64),
+
+    Probe { position: Position, success: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrblMotionMode {
+    Seek,
+    Linear,
+    CwArc,
+    CcwArc,
+    ProbeToward,
+    ProbeTowardNoError,
+    ProbeAway,
+    ProbeAwayNoError,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrblPlane {
+    XY,
+    ZX,
+    YZ,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrblUnits {
+    Inches,
+    Millimeters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrblDistanceMode {
+    Absolute,
+    Incremental,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrblFeedMode {
+    InverseTime,
+    UnitsPerMinute,
+}
+
+// Modal state of the g-code parser as reported by `$G`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrblParserState {
+    pub motion: GrblMotionMode,
+    pub coordinate_system: usize,
+    pub plane: GrblPlane,
+    pub units: GrblUnits,
+    pub distance: GrblDistanceMode,
+    pub feed_mode: GrblFeedMode,
+    pub spindle: GrblSpindleStatus,
+    pub flood_coolant: bool,
+    pub mist_coolant: bool,
+    pub tool: u32,
+    pub feed: f64,
+    pub speed: f64,
+}
+
+impl Default for GrblParserState {
+    // State after power up or reset
+    fn default() -> Self {
+        return Self {
+            motion: GrblMotionMode::Seek,
+            coordinate_system: 0,
+            plane: GrblPlane::XY,
+            units: GrblUnits::Millimeters,
+            distance: GrblDistanceMode::Absolute,
+            feed_mode: GrblFeedMode::UnitsPerMinute,
+            spindle: GrblSpindleStatus::Off,
+            flood_coolant: false,
+            mist_coolant: false,
+            tool: 0,
+            feed: 0.0,
+            speed: 0.0,
+        };
+    }
+}
+
+impl From<GrblParserState> for controller::ModalState {
+    fn from(state: GrblParserState) -> Self {
+        return Self {
+            motion: match state.motion {
+                GrblMotionMode::Seek => controller::MotionMode::Rapid,
+                GrblMotionMode::Linear => controller::MotionMode::Linear,
+                GrblMotionMode::CwArc => controller::MotionMode::ClockwiseArc,
+                GrblMotionMode::CcwArc => controller::MotionMode::CounterClockwiseArc,
+                GrblMotionMode::ProbeToward => controller::MotionMode::ProbeToward,
+                GrblMotionMode::ProbeTowardNoError => controller::MotionMode::ProbeTowardNoError,
+                GrblMotionMode::ProbeAway => controller::MotionMode::ProbeAway,
+                GrblMotionMode::ProbeAwayNoError => controller::MotionMode::ProbeAwayNoError,
+                GrblMotionMode::None => controller::MotionMode::None,
+            },
+            coordinate_system: state.coordinate_system,
+            plane: match state.plane {
+                GrblPlane::XY => controller::Plane::XY,
+                GrblPlane::ZX => controller::Plane::ZX,
+                GrblPlane::YZ => controller::Plane::YZ,
+            },
+            units: match state.units {
+                GrblUnits::Inches => controller::Units::Inches,
+                GrblUnits::Millimeters => controller::Units::Millimeters,
+            },
+            distance: match state.distance {
+                GrblDistanceMode::Absolute => controller::DistanceMode::Absolute,
+                GrblDistanceMode::Incremental => controller::DistanceMode::Incremental,
+            },
+            feed_mode: match state.feed_mode {
+                GrblFeedMode::InverseTime => controller::FeedMode::InverseTime,
+                GrblFeedMode::UnitsPerMinute => controller::FeedMode::UnitsPerMinute,
+            },
+            spindle: match state.spindle {
+                GrblSpindleStatus::Off => controller::SpindleState::Off,
+                GrblSpindleStatus::CW => controller::SpindleState::Clockwise,
+                GrblSpindleStatus::CCW => controller::SpindleState::CounterClockwise,
+            },
+            flood_coolant: state.flood_coolant,
+            mist_coolant: state.mist_coolant,
+            tool: state.tool,
+            feed: state.feed,
+            speed: state.speed,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrblParseErrorKind {
+    InvalidNumber(String),
+    MissingValues(String),
+    MissingPosition,
+    MalformedField(String),
+    UnknownMachineState(String),
+}
+
+impl fmt::Display for GrblParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            GrblParseErrorKind::InvalidNumber(val) => write!(f, "Invalid number: {:?}", val),
+            GrblParseErrorKind::MissingValues(val) => write!(f, "Missing values: {:?}", val),
+            GrblParseErrorKind::MissingPosition => write!(f, "Missing position"),
+            GrblParseErrorKind::MalformedField(field) => write!(f, "Malformed field: {:?}", field),
+            GrblParseErrorKind::UnknownMachineState(state) => write!(f, "Unknown machine state: {:?}", state),
+        };
+    }
+}
+
+// Error for a line received from the controller which could not be understood
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrblParseError {
+    pub kind: GrblParseErrorKind,
+    pub line: String,
+}
+
+impl fmt::Display for GrblParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{} in {:?}", self.kind, self.line);
+    }
+}
+
+impl error::Error for GrblParseError {}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum GrblMessage {
     Response(GrblResponse),
-    Alarm(u8),
-    // TODO: Replace code with enum
-    Setting { code: u8, value: f64 },
-    // TODO: Replace code with enum
+    // Banner printed after power up and every reset
+    Welcome { version: String },
+    Alarm(codes::AlarmCode),
+    Setting { code: codes::SettingCode, value: f64 },
     StartupLine { nr: u8, line: String },
     Feedback(String),
-    ParserState(String),
+    ParserState(GrblParserState),
     Help(String),
-    Parameter(String),
+    Parameter(GrblParameter),
     Version { version: String, note: String },
-    BuildOptions(String),
+    BuildOptions(GrblBuildOptions),
     StatusReport(GrblStatusReport),
     Other(String),
 }
 
 impl GrblMessage {
-    pub fn parse(line: &str) -> Result<Self, Error> {
+    pub fn parse(line: &str) -> Result<Self, GrblParseError> {
+        return Self::parse_line(line)
+            .map_err(|kind| GrblParseError {
+                kind,
+                line: line.to_owned(),
+            });
+    }
+
+    fn parse_line(line: &str) -> Result<Self, GrblParseErrorKind> {
         lazy_static! {
                 // ok
                 static ref RE_RESPONSE_OK: Regex = Regex::new(r"^ok$").unwrap();
@@ -322,6 +691,9 @@ impl GrblMessage {
                 // error:x
                 static ref RE_RESPONSE_ERROR: Regex = Regex::new(r"^error:(.+)$").unwrap();
 
+                // Grbl 1.1f ['$' for help]
+                static ref RE_WELCOME: Regex = Regex::new(r"^Grbl(?:HAL)? (\S+) \[.*\]$").unwrap();
+
                 // ALARM:x
                 static ref RE_ALARM: Regex = Regex::new(r"^ALARM:(.+)$").unwrap();
 
@@ -375,6 +747,10 @@ impl GrblMessage {
             return Self::parse_response_error(captures);
         }
 
+        if let Some(captures) = RE_WELCOME.captures(line) {
+            return Self::parse_welcome(captures);
+        }
+
         if let Some(captures) = RE_ALARM.captures(line) {
             return Self::parse_alarm(captures);
         }
@@ -418,84 +794,173 @@ impl GrblMessage {
         return Ok(GrblMessage::Other(line.to_owned()));
     }
 
-    fn parse_response_ok(_: Captures) -> Result<Self, Error> {
+    fn parse_response_ok(_: Captures) -> Result<Self, GrblParseErrorKind> {
         return Ok(GrblMessage::Response(GrblResponse::Ok));
     }
 
-    fn parse_response_error(captures: Captures) -> Result<Self, Error> {
-        let code = captures.get(1).unwrap().as_str().parse()?;
+    fn parse_response_error(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let code = Self::parse_value(&captures[1])?;
+
+        return Ok(GrblMessage::Response(GrblResponse::Error(codes::ErrorCode::from_code(code))));
+    }
+
+    fn parse_welcome(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let version = captures[1].to_owned();
 
-        return Ok(GrblMessage::Response(GrblResponse::Error(code)));
+        return Ok(GrblMessage::Welcome { version });
     }
 
-    fn parse_alarm(captures: Captures) -> Result<Self, Error> {
-        let code = captures.get(1).unwrap().as_str().parse()?;
+    fn parse_alarm(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let code = Self::parse_value(&captures[1])?;
 
-        return Ok(GrblMessage::Alarm(code));
+        return Ok(GrblMessage::Alarm(codes::AlarmCode::from_code(code)));
     }
 
-    fn parse_setting(captures: Captures) -> Result<Self, Error> {
-        let code = captures.get(1).unwrap().as_str().parse()?;
-        let value = captures.get(2).unwrap().as_str().parse()?;
+    fn parse_setting(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let code = Self::parse_value(&captures[1])?;
+        let value = Self::parse_value(&captures[2])?;
 
-        return Ok(GrblMessage::Setting { code, value });
+        return Ok(GrblMessage::Setting { code: codes::SettingCode::from_code(code), value });
     }
 
-    fn parse_startup_line(captures: Captures) -> Result<Self, Error> {
-        let nr = captures.get(1).unwrap().as_str().parse()?;
-        let line = captures.get(2).unwrap().as_str().parse()?;
+    fn parse_startup_line(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let nr = Self::parse_value(&captures[1])?;
+        let line = captures[2].to_owned();
 
         return Ok(GrblMessage::StartupLine { nr, line });
     }
 
-    fn parse_feedback(captures: Captures) -> Result<Self, Error> {
-        let line = captures.get(1).unwrap().as_str().to_owned();
+    fn parse_feedback(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let line = captures[1].to_owned();
 
         return Ok(GrblMessage::Feedback(line));
     }
 
-    fn parse_parser_state(captures: Captures) -> Result<Self, Error> {
-        // TODO: Parse to more detail
-        let line = captures.get(1).unwrap().as_str().to_owned();
+    fn parse_parser_state(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let mut state = GrblParserState::default();
+
+        for word in captures[1].split_whitespace() {
+            // Split after the first character rather than the first byte as garbled lines may contain anything
+            let (letter, value) = word.split_at(word.chars().next().map_or(0, char::len_utf8));
+
+            match (letter, value) {
+                ("G", "0") => state.motion = GrblMotionMode::Seek,
+                ("G", "1") => state.motion = GrblMotionMode::Linear,
+                ("G", "2") => state.motion = GrblMotionMode::CwArc,
+                ("G", "3") => state.motion = GrblMotionMode::CcwArc,
+                ("G", "38.2") => state.motion = GrblMotionMode::ProbeToward,
+                ("G", "38.3") => state.motion = GrblMotionMode::ProbeTowardNoError,
+                ("G", "38.4") => state.motion = GrblMotionMode::ProbeAway,
+                ("G", "38.5") => state.motion = GrblMotionMode::ProbeAwayNoError,
+                ("G", "80") => state.motion = GrblMotionMode::None,
+
+                ("G", "54") => state.coordinate_system = 0,
+                ("G", "55") => state.coordinate_system = 1,
+                ("G", "56") => state.coordinate_system = 2,
+                ("G", "57") => state.coordinate_system = 3,
+                ("G", "58") => state.coordinate_system = 4,
+                ("G", "59") => state.coordinate_system = 5,
+
+                ("G", "17") => state.plane = GrblPlane::XY,
+                ("G", "18") => state.plane = GrblPlane::ZX,
+                ("G", "19") => state.plane = GrblPlane::YZ,
+
+                ("G", "20") => state.units = GrblUnits::Inches,
+                ("G", "21") => state.units = GrblUnits::Millimeters,
+
+                ("G", "90") => state.distance = GrblDistanceMode::Absolute,
+                ("G", "91") => state.distance = GrblDistanceMode::Incremental,
+
+                ("G", "93") => state.feed_mode = GrblFeedMode::InverseTime,
+                ("G", "94") => state.feed_mode = GrblFeedMode::UnitsPerMinute,
+
+                // Tool length offset, program flow and parking override modes are not tracked, and neither are the
+                // modes forks like grblHAL or FluidNC add, like cutter compensation, scaling or canned cycle retract
+                ("G", "43.1") | ("G", "49") => {}
+                ("M", "0") | ("M", "1") | ("M", "2") | ("M", "30") | ("M", "56") => {}
+
+                ("M", "3") => state.spindle = GrblSpindleStatus::CW,
+                ("M", "4") => state.spindle = GrblSpindleStatus::CCW,
+                ("M", "5") => state.spindle = GrblSpindleStatus::Off,
+
+                // Both coolants may be reported at once
+                ("M", "7") => state.mist_coolant = true,
+                ("M", "8") => state.flood_coolant = true,
+                ("M", "9") => {
+                    state.mist_coolant = false;
+                    state.flood_coolant = false;
+                }
+
+                ("T", val) => state.tool = Self::parse_value(val)?,
+                ("F", val) => state.feed = Self::parse_value(val)?,
+                ("S", val) => state.speed = Self::parse_value(val)?,
+
+                _ => log::debug!("GRBL: Ignoring unknown parser state word {:?}", word),
+            }
+        }
 
-        return Ok(GrblMessage::ParserState(line));
+        return Ok(GrblMessage::ParserState(state));
     }
 
-    fn parse_help(captures: Captures) -> Result<Self, Error> {
-        let line = captures.get(1).unwrap().as_str().to_owned();
+    fn parse_help(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let line = captures[1].to_owned();
 
         return Ok(GrblMessage::Help(line));
     }
 
-    fn parse_parameter(captures: Captures) -> Result<Self, Error> {
-        // TODO: Parse to more detail
-        let line = captures.get(1).unwrap().as_str().to_owned();
+    fn parse_parameter(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let val = &captures[2];
+
+        let parameter = match &captures[1] {
+            "G54" => GrblParameter::CoordinateSystem(0, Self::parse_position(val)?),
+            "G55" => GrblParameter::CoordinateSystem(1, Self::parse_position(val)?),
+            "G56" => GrblParameter::CoordinateSystem(2, Self::parse_position(val)?),
+            "G57" => GrblParameter::CoordinateSystem(3, Self::parse_position(val)?),
+            "G58" => GrblParameter::CoordinateSystem(4, Self::parse_position(val)?),
+            "G59" => GrblParameter::CoordinateSystem(5, Self::parse_position(val)?),
+            "G28" => GrblParameter::Home(Self::parse_position(val)?),
+            "G30" => GrblParameter::SecondaryHome(Self::parse_position(val)?),
+            "G92" => GrblParameter::Offset(Self::parse_position(val)?),
+            "TLO" => GrblParameter::ToolLengthOffset(Self::parse_value(val)?),
+            _ => {
+                // Probe results are reported as `x,y,z:success`
+                let i = val.rfind(':')
+                    .ok_or_else(|| GrblParseErrorKind::MissingValues(val.to_owned()))?;
+
+                GrblParameter::Probe {
+                    position: Self::parse_position(&val[..i])?,
+                    success: Self::parse_value::<u8>(&val[(i + 1)..])? != 0,
+                }
+            }
+        };
 
-        return Ok(GrblMessage::Parameter(line));
+        return Ok(GrblMessage::Parameter(parameter));
     }
 
-    fn parse_version(captures: Captures) -> Result<Self, Error> {
-        let version = captures.get(1).unwrap().as_str().to_owned();
-        let note = captures.get(2).unwrap().as_str().to_owned();
+    fn parse_version(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        let version = captures[1].to_owned();
+        let note = captures[2].to_owned();
 
         return Ok(GrblMessage::Version { version, note });
     }
 
-    fn parse_build_options(captures: Captures) -> Result<Self, Error> {
-        // TODO: Parse to more detail
-        let line = captures.get(1).unwrap().as_str().to_owned();
+    fn parse_build_options(captures: Captures) -> Result<Self, GrblParseErrorKind> {
+        // Forks like grblHAL append further values which are ignored
+        let mut parts = captures[1].split(',');
 
-        return Ok(GrblMessage::BuildOptions(line));
+        let codes = parts.next().unwrap_or("").to_owned();
+        let planner_blocks = parts.next().map(Self::parse_value).transpose()?;
+        let rx_buffer = parts.next().map(Self::parse_value).transpose()?;
+
+        return Ok(GrblMessage::BuildOptions(GrblBuildOptions { codes, planner_blocks, rx_buffer }));
     }
 
-    fn parse_status_report(captures: Captures) -> Result<Self, Error> {
+    fn parse_status_report(captures: Captures) -> Result<Self, GrblParseErrorKind> {
         // Split status into items
-        let mut items = captures.get(1).unwrap().as_str()
-            .split('|')
-            .into_iter();
+        let mut items = captures[1].split('|');
 
         // First item is always the machine state
-        let machine_state = match items.next().unwrap() {
+        let machine_state = match items.next().unwrap_or("") {
             "Idle" => GrblMachineState::Idle,
             "Run" => GrblMachineState::Run,
             "Hold:0" => GrblMachineState::Hold(GrblMachineHoldStatus::Complete),
@@ -509,24 +974,23 @@ impl GrblMessage {
             "Check" => GrblMachineState::Check,
             "Home" => GrblMachineState::Home,
             "Sleep" => GrblMachineState::Sleep,
-            _ => unreachable!(),
+            "" => return Err(GrblParseErrorKind::UnknownMachineState(String::new())),
+            state => GrblMachineState::Unknown(state.to_owned()),
         };
 
         // Parse all remaining items as `key:val`
         let mut items = items.map(|item| {
-            let i = item.find(':').unwrap();
-
-            let key = &item[..i];
-            let val = &item[(i + 1)..];
-
-            return (key, val);
+            return match item.find(':') {
+                Some(i) => Ok((&item[..i], &item[(i + 1)..])),
+                None => Err(GrblParseErrorKind::MalformedField(item.to_owned())),
+            };
         });
 
         // Second item is guaranteed to be always position
-        let position = match items.next().unwrap() {
-            ("MPos", val) => GrblPositionStatus::MachinePosition(Self::parse_position(val)),
-            ("WPos", val) => GrblPositionStatus::WorkPosition(Self::parse_position(val)),
-            _ => unreachable!(),
+        let position = match items.next().transpose()? {
+            Some(("MPos", val)) => GrblPositionStatus::MachinePosition(Self::parse_position(val)?),
+            Some(("WPos", val)) => GrblPositionStatus::WorkPosition(Self::parse_position(val)?),
+            _ => return Err(GrblParseErrorKind::MissingPosition),
         };
 
         // Parse remaining items in arbitrary order
@@ -544,52 +1008,50 @@ impl GrblMessage {
         };
 
         for item in items {
-            match item {
+            match item? {
                 ("WCO", val) => {
-                    report.wco = Some(Self::parse_position(val));
+                    report.wco = Some(Self::parse_position(val)?);
                 }
                 ("Bf", val) => {
-                    let parts = Self::parse_parts(val);
+                    let parts = Self::parse_parts(val, 2)?;
                     report.buffer = Some(GrblBufferStatus {
                         planner: parts[0],
                         rx: parts[1],
                     });
                 }
                 ("Ln", val) => {
-                    let parts = Self::parse_parts(val);
+                    let parts = Self::parse_parts(val, 1)?;
                     report.line = Some(parts[0]);
                 }
                 ("F", val) => {
-                    let parts = Self::parse_parts(val);
+                    let parts = Self::parse_parts(val, 1)?;
                     report.feed = Some(parts[0]);
                 }
                 ("FS", val) => {
-                    let parts = Self::parse_parts(val);
+                    let parts = Self::parse_parts(val, 2)?;
                     report.feed = Some(parts[0]);
                     report.speed = Some(parts[1]);
                 }
                 ("Pn", val) => {
-                    let input_pins = val.chars()
-                        .fold(GrblInputPinsStatus::default(), |mut pins, char| {
-                            match char {
-                                'X' => pins.x_limit = true,
-                                'Y' => pins.y_limit = true,
-                                'Z' => pins.z_limit = true,
-                                'P' => pins.probe = true,
-                                'D' => pins.door = true,
-                                'H' => pins.hold = true,
-                                'R' => pins.soft_reset = true,
-                                'S' => pins.cycle_start = true,
-                                _ => unreachable!(),
-                            }
-
-                            return pins;
-                        });
-
-                    report.input_pins = Some(input_pins);
+                    let mut pins = GrblInputPinsStatus::default();
+                    for char in val.chars() {
+                        match char {
+                            'X' => pins.x_limit = true,
+                            'Y' => pins.y_limit = true,
+                            'Z' => pins.z_limit = true,
+                            'P' => pins.probe = true,
+                            'D' => pins.door = true,
+                            'H' => pins.hold = true,
+                            'R' => pins.soft_reset = true,
+                            'S' => pins.cycle_start = true,
+                            _ => log::debug!("GRBL: Ignoring unknown pin {:?}", char),
+                        }
+                    }
+
+                    report.input_pins = Some(pins);
                 }
                 ("Ov", val) => {
-                    let parts = Self::parse_parts(val);
+                    let parts = Self::parse_parts(val, 3)?;
                     report.overrides = Some(GrblOverrridesStatus {
                         feed: parts[0],
                         rapids: parts[1],
@@ -597,46 +1059,57 @@ impl GrblMessage {
                     });
                 }
                 ("A", val) => {
-                    let accessory = val.chars()
-                        .fold(GrblAccessoryStatus::default(), |mut accessory, char| {
-                            match char {
-                                'S' => accessory.spindle = GrblSpindleStatus::CW,
-                                'C' => accessory.spindle = GrblSpindleStatus::CCW,
-                                'F' => accessory.flood_coolant = true,
-                                'M' => accessory.mist_coolant = true,
-                                _ => unreachable!(),
-                            }
-
-                            return accessory;
-                        });
+                    let mut accessory = GrblAccessoryStatus::default();
+                    for char in val.chars() {
+                        match char {
+                            'S' => accessory.spindle = GrblSpindleStatus::CW,
+                            'C' => accessory.spindle = GrblSpindleStatus::CCW,
+                            'F' => accessory.flood_coolant = true,
+                            'M' => accessory.mist_coolant = true,
+                            _ => log::debug!("GRBL: Ignoring unknown accessory {:?}", char),
+                        }
+                    }
 
                     report.accessory = Some(accessory);
                 }
-                _ => unreachable!(),
+
+                // Forks report more, like grblHAL the homing state or the current tool
+                (key, _) => log::debug!("GRBL: Ignoring unknown status report field {:?}", key),
             }
         }
 
         return Ok(GrblMessage::StatusReport(report));
     }
 
-    fn parse_parts<T>(val: &str) -> Vec<T>
-        where T: FromStr,
-              T::Err: std::fmt::Debug {
-        return val.split(',').into_iter()
-            .map(|part| T::from_str(part).unwrap())
-            .collect();
+    fn parse_value<T>(val: &str) -> Result<T, GrblParseErrorKind>
+        where T: FromStr {
+        return T::from_str(val)
+            .map_err(|_| GrblParseErrorKind::InvalidNumber(val.to_owned()));
     }
 
-    fn parse_position(val: &str) -> Position {
-        let parts = Self::parse_parts(val);
+    // Parses comma separated values expecting at least the given number of values
+    fn parse_parts<T>(val: &str, count: usize) -> Result<Vec<T>, GrblParseErrorKind>
+        where T: FromStr {
+        let parts = val.split(',')
+            .map(Self::parse_value)
+            .collect::<Result<Vec<T>, _>>()?;
 
-        assert_eq!(parts.len(), 3);
+        if parts.len() < count {
+            return Err(GrblParseErrorKind::MissingValues(val.to_owned()));
+        }
 
-        return Position {
+        return Ok(parts);
+    }
+
+    fn parse_position(val: &str) -> Result<Position, GrblParseErrorKind> {
+        // Machines with more than three axes report additional values which are ignored
+        let parts = Self::parse_parts(val, 3)?;
+
+        return Ok(Position {
             x: parts[0],
             y: parts[1],
             z: parts[2],
-        };
+        });
     }
 }
 
@@ -652,30 +1125,42 @@ mod tests {
 
     #[test]
     fn test_parse_error() {
+        assert_eq!(GrblMessage::parse("error:20").unwrap(),
+                   GrblMessage::Response(GrblResponse::Error(codes::ErrorCode::UnsupportedCommand)));
         assert_eq!(GrblMessage::parse("error:0").unwrap(),
-                   GrblMessage::Response(GrblResponse::Error(0)));
+                   GrblMessage::Response(GrblResponse::Error(codes::ErrorCode::Unknown(0))));
         assert_eq!(GrblMessage::parse("error:255").unwrap(),
-                   GrblMessage::Response(GrblResponse::Error(255)));
+                   GrblMessage::Response(GrblResponse::Error(codes::ErrorCode::Unknown(255))));
+    }
+
+    #[test]
+    fn test_parse_welcome() {
+        assert_eq!(GrblMessage::parse("Grbl 1.1f ['$' for help]").unwrap(),
+                   GrblMessage::Welcome { version: "1.1f".to_owned() });
+        assert_eq!(GrblMessage::parse("GrblHAL 1.1f ['$' or '$HELP' for help]").unwrap(),
+                   GrblMessage::Welcome { version: "1.1f".to_owned() });
     }
 
     #[test]
     fn test_parse_alarm() {
+        assert_eq!(GrblMessage::parse("ALARM:1").unwrap(),
+                   GrblMessage::Alarm(codes::AlarmCode::HardLimit));
         assert_eq!(GrblMessage::parse("ALARM:0").unwrap(),
-                   GrblMessage::Alarm(0));
+                   GrblMessage::Alarm(codes::AlarmCode::Unknown(0)));
         assert_eq!(GrblMessage::parse("ALARM:255").unwrap(),
-                   GrblMessage::Alarm(255));
+                   GrblMessage::Alarm(codes::AlarmCode::Unknown(255)));
     }
 
     #[test]
     fn test_parse_setting() {
         assert_eq!(GrblMessage::parse("$13=0").unwrap(),
-                   GrblMessage::Setting { code: 13, value: 0.0 });
+                   GrblMessage::Setting { code: codes::SettingCode::ReportInInches, value: 0.0 });
         assert_eq!(GrblMessage::parse("$100=250.0").unwrap(),
-                   GrblMessage::Setting { code: 100, value: 250.0 });
+                   GrblMessage::Setting { code: codes::SettingCode::XAxisTravelResolution, value: 250.0 });
         assert_eq!(GrblMessage::parse("$12=0.002").unwrap(),
-                   GrblMessage::Setting { code: 12, value: 0.002 });
+                   GrblMessage::Setting { code: codes::SettingCode::ArcTolerance, value: 0.002 });
         assert_eq!(GrblMessage::parse("$30=1000").unwrap(),
-                   GrblMessage::Setting { code: 30, value: 1000.0 });
+                   GrblMessage::Setting { code: codes::SettingCode::MaximumSpindleSpeed, value: 1000.0 });
     }
 
     #[test]
@@ -697,7 +1182,36 @@ mod tests {
     #[test]
     fn test_parse_parser_state() {
         assert_eq!(GrblMessage::parse("[GC:G0 G54 G17 G21 G90 G94 M5 M9 T0 F0.0 S0]").unwrap(),
-                   GrblMessage::ParserState("G0 G54 G17 G21 G90 G94 M5 M9 T0 F0.0 S0".to_owned()));
+                   GrblMessage::ParserState(GrblParserState::default()));
+        assert_eq!(GrblMessage::parse("[GC:G3 G56 G18 G20 G91 G93 M0 M4 M7 M8 T3 F12.5 S12000]").unwrap(),
+                   GrblMessage::ParserState(GrblParserState {
+                       motion: GrblMotionMode::CcwArc,
+                       coordinate_system: 2,
+                       plane: GrblPlane::ZX,
+                       units: GrblUnits::Inches,
+                       distance: GrblDistanceMode::Incremental,
+                       feed_mode: GrblFeedMode::InverseTime,
+                       spindle: GrblSpindleStatus::CCW,
+                       flood_coolant: true,
+                       mist_coolant: true,
+                       tool: 3,
+                       feed: 12.5,
+                       speed: 12000.0,
+                   }));
+        assert_eq!(GrblMessage::parse("[GC:G38.2 G54 G17 G21 G90 G94 M5 M9 T0 F0 S0]").unwrap(),
+                   GrblMessage::ParserState(GrblParserState {
+                       motion: GrblMotionMode::ProbeToward,
+                       ..GrblParserState::default()
+                   }));
+
+        // Modes of forks are ignored
+        assert_eq!(GrblMessage::parse("[GC:G0 G54 G17 G20 G90 G94 G40 G49 G50 G98 M5 M9 T0 F0 S0 G99]").unwrap(),
+                   GrblMessage::ParserState(GrblParserState {
+                       units: GrblUnits::Inches,
+                       ..GrblParserState::default()
+                   }));
+        assert_eq!(GrblMessage::parse("[GC:G0 G54 G17 G21 G90 G94 M5 M9 T0 Fx S0]").unwrap_err().kind,
+                   GrblParseErrorKind::InvalidNumber("x".to_owned()));
     }
 
     #[test]
@@ -708,17 +1222,29 @@ mod tests {
 
     #[test]
     fn test_parse_parameter() {
-        // [G54:0.000,0.000,0.000]
-        // [G55:0.000,0.000,0.000]
-        // [G56:0.000,0.000,0.000]
-        // [G57:0.000,0.000,0.000]
-        // [G58:0.000,0.000,0.000]
-        // [G59:0.000,0.000,0.000]
-        // [G28:0.000,0.000,0.000]
-        // [G30:0.000,0.000,0.000]
-        // [G92:0.000,0.000,0.000]
-        // [TLO:0.000]
-        // [PRB:0.000,0.000,0.000:0]
+        assert_eq!(GrblMessage::parse("[G54:0.000,0.000,0.000]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::CoordinateSystem(0, Position::from((0.0, 0.0, 0.0)))));
+        assert_eq!(GrblMessage::parse("[G55:-10.000,5.500,0.000]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::CoordinateSystem(1, Position::from((-10.0, 5.5, 0.0)))));
+        assert_eq!(GrblMessage::parse("[G59:1.000,2.000,3.000]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::CoordinateSystem(5, Position::from((1.0, 2.0, 3.0)))));
+        assert_eq!(GrblMessage::parse("[G28:1.000,2.000,3.000]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::Home(Position::from((1.0, 2.0, 3.0)))));
+        assert_eq!(GrblMessage::parse("[G30:4.000,5.000,6.000]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::SecondaryHome(Position::from((4.0, 5.0, 6.0)))));
+        assert_eq!(GrblMessage::parse("[G92:0.000,0.000,-1.000]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::Offset(Position::from((0.0, 0.0, -1.0)))));
+        assert_eq!(GrblMessage::parse("[TLO:2.500]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::ToolLengthOffset(2.5)));
+        assert_eq!(GrblMessage::parse("[PRB:0.000,0.000,1.492:1]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::Probe { position: Position::from((0.0, 0.0, 1.492)), success: true }));
+        assert_eq!(GrblMessage::parse("[PRB:0.000,0.000,0.000:0]").unwrap(),
+                   GrblMessage::Parameter(GrblParameter::Probe { position: Position::from((0.0, 0.0, 0.0)), success: false }));
+
+        assert_eq!(GrblMessage::parse("[PRB:0.000,0.000,0.000]").unwrap_err().kind,
+                   GrblParseErrorKind::MissingValues("0.000,0.000,0.000".to_owned()));
+        assert_eq!(GrblMessage::parse("[TLO:x]").unwrap_err().kind,
+                   GrblParseErrorKind::InvalidNumber("x".to_owned()));
     }
 
     #[test]
@@ -732,7 +1258,25 @@ mod tests {
     #[test]
     fn test_parse_build_options() {
         assert_eq!(GrblMessage::parse("[OPT:VL,15,128]").unwrap(),
-                   GrblMessage::BuildOptions("VL,15,128".to_owned()));
+                   GrblMessage::BuildOptions(GrblBuildOptions {
+                       codes: "VL".to_owned(),
+                       planner_blocks: Some(15),
+                       rx_buffer: Some(128),
+                   }));
+        assert_eq!(GrblMessage::parse("[OPT:VNMZHSL,35,1024,3,0]").unwrap(),
+                   GrblMessage::BuildOptions(GrblBuildOptions {
+                       codes: "VNMZHSL".to_owned(),
+                       planner_blocks: Some(35),
+                       rx_buffer: Some(1024),
+                   }));
+        assert_eq!(GrblMessage::parse("[OPT:V]").unwrap(),
+                   GrblMessage::BuildOptions(GrblBuildOptions {
+                       codes: "V".to_owned(),
+                       planner_blocks: None,
+                       rx_buffer: None,
+                   }));
+        assert_eq!(GrblMessage::parse("[OPT:V,15,x]").unwrap_err().kind,
+                   GrblParseErrorKind::InvalidNumber("x".to_owned()));
     }
 
     #[test]
@@ -822,6 +1366,23 @@ mod tests {
                        overrides: None,
                        accessory: None,
                    }));
+        // grblHAL reports receive buffers larger than stock Grbl's
+        assert_eq!(GrblMessage::parse("<Idle|MPos:5.000,2.000,0.000|Bf:35,1023>").unwrap(),
+                   GrblMessage::StatusReport(GrblStatusReport {
+                       machine_state: GrblMachineState::Idle,
+                       position: GrblPositionStatus::MachinePosition(Position::from((5.0, 2.0, 0.0))),
+                       wco: None,
+                       buffer: Some(GrblBufferStatus {
+                           planner: 35,
+                           rx: 1023,
+                       }),
+                       line: None,
+                       feed: None,
+                       speed: None,
+                       input_pins: None,
+                       overrides: None,
+                       accessory: None,
+                   }));
         assert_eq!(GrblMessage::parse("<Idle|MPos:5.000,2.000,0.000|Pn:XYZR>").unwrap(),
                    GrblMessage::StatusReport(GrblStatusReport {
                        machine_state: GrblMachineState::Idle,
@@ -862,4 +1423,215 @@ mod tests {
                        }),
                    }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_invalid() {
+        let kind = |line: &str| GrblMessage::parse(line).unwrap_err().kind;
+
+        assert_eq!(kind("<>"), GrblParseErrorKind::UnknownMachineState("".to_owned()));
+        assert_eq!(kind("<Idle>"), GrblParseErrorKind::MissingPosition);
+        assert_eq!(kind("<Idle|FS:0,0>"), GrblParseErrorKind::MissingPosition);
+        assert_eq!(kind("<Idle|MPos:1.000,2.000>"), GrblParseErrorKind::MissingValues("1.000,2.000".to_owned()));
+        assert_eq!(kind("<Idle|MPos:1.000,2.000,3.000|FS:0>"), GrblParseErrorKind::MissingValues("0".to_owned()));
+        assert_eq!(kind("<Idle|MPos:1.000,2.0#0,3.000>"), GrblParseErrorKind::InvalidNumber("2.0#0".to_owned()));
+        assert_eq!(kind("<Idle|MPos:1.000,2.000,3.000|Bf>"), GrblParseErrorKind::MalformedField("Bf".to_owned()));
+        assert_eq!(kind("error:x"), GrblParseErrorKind::InvalidNumber("x".to_owned()));
+        assert_eq!(kind("ALARM:256"), GrblParseErrorKind::InvalidNumber("256".to_owned()));
+        assert_eq!(kind("$13=on"), GrblParseErrorKind::InvalidNumber("on".to_owned()));
+
+        assert_eq!(GrblMessage::parse("<Idle|MPos>").unwrap_err().line, "<Idle|MPos>");
+
+        // Additional axes are ignored
+        assert_eq!(GrblMessage::parse("<Idle|MPos:1.000,2.000,3.000,4.000>").unwrap(),
+                   GrblMessage::StatusReport(GrblStatusReport {
+                       machine_state: GrblMachineState::Idle,
+                       position: GrblPositionStatus::MachinePosition(Position::from((1.0, 2.0, 3.0))),
+                       wco: None,
+                       buffer: None,
+                       line: None,
+                       feed: None,
+                       speed: None,
+                       input_pins: None,
+                       overrides: None,
+                       accessory: None,
+                   }));
+
+        // States, fields, pins and accessories of forks like grblHAL are skipped rather than failing the report
+        let report = match GrblMessage::parse("<Tool|MPos:1.000,2.000,3.000|Pn:XQ|A:SX|H:1,7|T:2|Sc:XY>").unwrap() {
+            GrblMessage::StatusReport(report) => report,
+            msg => panic!("Expected status report, got {:?}", msg),
+        };
+
+        assert_eq!(report.machine_state, GrblMachineState::Unknown("Tool".to_owned()));
+        assert_eq!(report.input_pins.map(|pins| pins.x_limit), Some(true));
+        assert_eq!(report.accessory.map(|accessory| accessory.spindle), Some(GrblSpindleStatus::CW));
+    }
+
+    // Minimal xorshift generator to keep the randomized tests reproducible
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            return self.0;
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            return (self.next() % n as u64) as usize;
+        }
+
+        fn chance(&mut self) -> bool {
+            return self.next() % 2 == 0;
+        }
+
+        fn choose<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            return items[self.below(items.len())];
+        }
+
+        fn value(&mut self) -> f64 {
+            return (self.below(2_000_000) as f64 - 1_000_000.0) / 1000.0;
+        }
+    }
+
+    #[test]
+    fn test_parse_fuzz() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+        let samples = [
+            "ok", "error:20", "ALARM:1", "$110=500.000", "$N0=G54", "[MSG:Pgm End]", "[GC:G0 G54 G17 G21 G90 G94 M5 M9 T0 F0 S0]",
+            "[VER:1.1f.20170801:]", "[OPT:V,15,128]", "[PRB:0.000,0.000,1.492:1]", "[G54:1.000,2.000,3.000]", "[TLO:0.000]",
+            "<Idle|MPos:3.000,2.000,0.000|FS:0,0>",
+            "<Run|WPos:-1.250,2.000,0.500|Bf:15,128|Ln:42|FS:500,8000|Pn:XYZPDHRS|WCO:1.000,2.000,3.000>",
+            "<Hold:1|MPos:5.000,2.000,0.000|F:100|Ov:110,50,90|A:CFM>",
+        ];
+        let alphabet: Vec<char> = "<>|:,.-+0123456789eE$=[]IdleRunHoldMPosWPosFSOvAPnBfLnWCOXYZSCM \u{0}\u{7f}\u{fffd}".chars().collect();
+
+        for _ in 0..20000 {
+            let line: String = if random.chance() {
+                // Mutate a valid message by replacing, inserting and removing characters
+                let mut line: Vec<char> = random.choose(&samples).chars().collect();
+                for _ in 0..=random.below(4) {
+                    let i = random.below(line.len() + 1);
+                    match random.below(3) {
+                        0 if i < line.len() => line[i] = alphabet[random.below(alphabet.len())],
+                        1 => line.insert(i, alphabet[random.below(alphabet.len())]),
+                        _ => line.truncate(i),
+                    }
+                }
+                line.into_iter().collect()
+            } else {
+                (0..random.below(64)).map(|_| alphabet[random.below(alphabet.len())]).collect()
+            };
+
+            // Garbage must never panic, but yield an error or an unknown message
+            let _ = GrblMessage::parse(&line);
+        }
+    }
+
+    #[test]
+    fn test_parse_status_report_roundtrip() {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+
+        let states = ["Idle", "Run", "Hold:0", "Hold:1", "Jog", "Alarm", "Door:0", "Door:1", "Door:2", "Door:3", "Check", "Home", "Sleep"];
+
+        for _ in 0..2000 {
+            let state = random.choose(&states);
+            let position = Position::from((random.value(), random.value(), random.value()));
+            let wco = if random.chance() { Some(Position::from((random.value(), random.value(), random.value()))) } else { None };
+            let buffer = if random.chance() { Some(GrblBufferStatus { planner: random.below(64), rx: random.below(1024) }) } else { None };
+            let line = if random.chance() { Some(random.below(100_000)) } else { None };
+            let feed = random.below(10_000) as f64;
+            let speed = random.below(30_000) as f64;
+            let pins: String = "XYZPDHRS".chars().filter(|_| random.chance()).collect();
+            let overrides = GrblOverrridesStatus {
+                feed: (10 + random.below(191)) as f64,
+                rapids: random.choose(&["25", "50", "100"]).parse().unwrap(),
+                speed: (10 + random.below(191)) as f64,
+            };
+            let accessory = random.choose(&["S", "C", "SF", "CM", "FM", "SFM"]);
+
+            let mut fields = vec![
+                state.to_owned(),
+                format!("MPos:{:.3},{:.3},{:.3}", position.x, position.y, position.z),
+            ];
+            if let Some(buffer) = buffer.as_ref() {
+                fields.push(format!("Bf:{},{}", buffer.planner, buffer.rx));
+            }
+            if let Some(line) = line {
+                fields.push(format!("Ln:{}", line));
+            }
+            fields.push(format!("FS:{},{}", feed, speed));
+            if !pins.is_empty() {
+                fields.push(format!("Pn:{}", pins));
+            }
+            if let Some(wco) = wco {
+                fields.push(format!("WCO:{:.3},{:.3},{:.3}", wco.x, wco.y, wco.z));
+            }
+            fields.push(format!("Ov:{},{},{}", overrides.feed, overrides.rapids, overrides.speed));
+            fields.push(format!("A:{}", accessory));
+
+            let report = match GrblMessage::parse(&format!("<{}>", fields.join("|"))).unwrap() {
+                GrblMessage::StatusReport(report) => report,
+                message => panic!("Unexpected message: {:?}", message),
+            };
+
+            assert_eq!(report.position, GrblPositionStatus::MachinePosition(position));
+            assert_eq!(report.wco, wco);
+            assert_eq!(report.buffer, buffer);
+            assert_eq!(report.line, line);
+            assert_eq!(report.feed, Some(feed));
+            assert_eq!(report.speed, Some(speed));
+            assert_eq!(report.input_pins.is_some(), !pins.is_empty());
+            assert_eq!(report.input_pins.unwrap_or_default().probe, pins.contains('P'));
+            assert_eq!(report.overrides, Some(overrides));
+            assert_eq!(report.accessory.as_ref().map(|a| a.flood_coolant), Some(accessory.contains('F')));
+            assert_eq!(report.accessory.as_ref().map(|a| a.spindle == GrblSpindleStatus::CCW), Some(accessory.contains('C')));
+        }
+    }
+
+    #[test]
+    fn test_realtime_code_roundtrip() {
+        for code in 0..=255u8 {
+            if let Some(command) = GrblRealtimeCommand::from_code(code) {
+                assert_eq!(command.to_code(), Bytes::from(vec![code]));
+            }
+        }
+
+        assert!(GrblRealtimeCommand::from_code(b'G').is_none());
+        assert!(GrblRealtimeCommand::from_code(0x98).is_none());
+    }
+
+    #[test]
+    fn test_override_steps() {
+        let steps = |current, target| -> Vec<u8> {
+            return GrblFeedOverride::steps(current, target).into_iter()
+                .flat_map(|step| GrblRealtimeCommand::FeedOverride(step).to_code())
+                .collect();
+        };
+
+        assert!(steps(100.0, 100.0).is_empty());
+        assert_eq!(steps(100.0, 120.0), vec![0x91, 0x91]);
+        assert_eq!(steps(100.0, 73.0), vec![0x92, 0x92, 0x92, 0x93, 0x93, 0x93]);
+
+        // Overshooting by ten is shorter than nine single steps
+        assert_eq!(steps(100.0, 109.0), vec![0x91, 0x94]);
+
+        // Unless Grbl would stop at the limit on the way
+        assert_eq!(steps(191.0, 200.0), vec![0x93; 9]);
+
+        // Resetting first is shorter than stepping all the way
+        assert_eq!(steps(150.0, 100.0), vec![0x90]);
+        assert_eq!(steps(200.0, 105.0), vec![0x90, 0x93, 0x93, 0x93, 0x93, 0x93]);
+
+        // Targets beyond the limits end up at the limit
+        assert_eq!(steps(20.0, 0.0), vec![0x92]);
+        assert_eq!(steps(195.0, 250.0), vec![0x93; 5]);
+
+        let steps: Vec<u8> = GrblSpeedOverride::steps(100.0, 89.0).into_iter()
+            .flat_map(|step| GrblRealtimeCommand::SpeedOverride(step).to_code())
+            .collect();
+        assert_eq!(steps, vec![0x9B, 0x9D]);
+    }
+}
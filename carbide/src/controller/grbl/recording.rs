@@ -0,0 +1,478 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use failure::Error;
+use failure::err_msg;
+use futures::Async;
+use futures::Future;
+use futures::future;
+use serde_derive::Deserialize;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::timer::Delay;
+
+use crate::controller::Direction;
+
+use super::proto;
+use super::transport::Port;
+use super::transport::Transport;
+
+// Chunk of traffic in a recording, timed in seconds since the recording started
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time: f64,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+// Recordings are text files with one chunk per line, like `0.052113 < ok\r\n`, where `>` marks bytes sent to the
+// controller and `<` bytes received from it. Lines starting with `#` begin another session, which continues the time
+// of the one before.
+pub fn parse(text: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+
+    let mut start = 0.0;
+    let mut last = 0.0;
+
+    for (number, line) in text.lines().enumerate() {
+        let invalid = || err_msg(format!("Invalid recording in line {}: {}", number + 1, line));
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('#') {
+            start = last;
+            continue;
+        }
+
+        let mut parts = line.splitn(3, ' ');
+
+        let time: f64 = parts.next()
+            .and_then(|time| time.parse().ok())
+            .filter(|time: &f64| time.is_finite() && *time >= 0.0)
+            .ok_or_else(invalid)?;
+
+        let direction = match parts.next() {
+            Some(">") => Direction::Sent,
+            Some("<") => Direction::Received,
+            _ => return Err(invalid()),
+        };
+
+        let data = unescape(parts.next().unwrap_or(""))
+            .ok_or_else(invalid)?;
+
+        last = start + time;
+
+        if !data.is_empty() {
+            entries.push(Entry {
+                time: last,
+                direction,
+                data,
+            });
+        }
+    }
+
+    return Ok(entries);
+}
+
+fn escape(data: &[u8]) -> String {
+    let escaped = data.iter()
+        .flat_map(|&byte| std::ascii::escape_default(byte))
+        .collect();
+    return String::from_utf8(escaped).unwrap();
+}
+
+fn unescape(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            data.push(byte);
+            continue;
+        }
+
+        data.push(match bytes.next()? {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            byte @ b'\\' | byte @ b'\'' | byte @ b'"' => byte,
+            _ => return None,
+        });
+    }
+
+    return Some(data);
+}
+
+// Transport writing all traffic of another one to a recording, which grows by a session on every reconnect
+pub struct Recorder {
+    transport: Box<Transport>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    pub fn new(transport: Box<Transport>, path: PathBuf) -> Self {
+        return Self {
+            transport,
+            path,
+        };
+    }
+}
+
+// Wraps the transport into a recorder, if a recording is configured
+pub fn recorded(transport: Box<Transport>, path: Option<&PathBuf>) -> Box<Transport> {
+    return match path {
+        Some(path) => Box::new(Recorder::new(transport, path.clone())),
+        None => transport,
+    };
+}
+
+impl fmt::Display for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.transport);
+    }
+}
+
+impl Transport for Recorder {
+    fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send> {
+        let path = self.path.clone();
+        let name = self.transport.to_string();
+
+        return Box::new(self.transport.open()
+            .and_then(move |port| {
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?;
+
+                let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                writeln!(file, "# {} at {}", name, since.as_secs())?;
+
+                log::info!("GRBL: Recording traffic to {}", path.display());
+
+                return Ok(Box::new(RecordingPort {
+                    port,
+                    file,
+                    start: Instant::now(),
+                }) as Box<Port>);
+            }));
+    }
+}
+
+struct RecordingPort {
+    port: Box<Port>,
+    file: fs::File,
+    start: Instant,
+}
+
+impl RecordingPort {
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let marker = match direction {
+            Direction::Sent => '>',
+            Direction::Received => '<',
+        };
+
+        // A line is written at once, so a crash loses no more than the chunk at hand
+        let line = format!("{:.6} {} {}\n", self.start.elapsed().as_secs_f64(), marker, escape(data));
+        if let Err(err) = self.file.write_all(line.as_bytes()) {
+            log::warn!("GRBL: Failed to record traffic: {}", err);
+        }
+    }
+}
+
+impl io::Read for RecordingPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.port.read(buf)?;
+        self.record(Direction::Received, &buf[..len]);
+        return Ok(len);
+    }
+}
+
+impl io::Write for RecordingPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.port.write(buf)?;
+        self.record(Direction::Sent, &buf[..len]);
+        return Ok(len);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.port.flush();
+    }
+}
+
+impl AsyncRead for RecordingPort {}
+
+impl AsyncWrite for RecordingPort {
+    fn shutdown(&mut self) -> io::Result<Async<()>> {
+        return self.port.shutdown();
+    }
+}
+
+fn default_speed() -> f64 {
+    return 1.0;
+}
+
+// Controller played back from a recording, which receives the recorded bytes in their original timing and swallows
+// everything sent to it
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplayConfig {
+    pub replay: PathBuf,
+
+    // Factor to play the recording faster with
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+}
+
+impl fmt::Display for ReplayConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "replay of {}", self.replay.display());
+    }
+}
+
+impl Transport for ReplayConfig {
+    fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send> {
+        if !(self.speed.is_finite() && self.speed > 0.0) {
+            return Box::new(future::err(err_msg(format!("Invalid replay speed: {}", self.speed))));
+        }
+
+        let entries = match fs::read_to_string(&self.replay).map_err(Error::from).and_then(|text| parse(&text)) {
+            Ok(entries) => entries,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        log::info!("GRBL: Replaying {} chunks from {}", entries.len(), self.replay.display());
+
+        return Box::new(future::ok(Box::new(ReplayPort::new(entries, self.speed)) as Box<Port>));
+    }
+}
+
+struct ReplayPort {
+    received: VecDeque<Entry>,
+
+    // Lines sent in the recording, which carbide is expected to send again. Realtime commands are left out, as these
+    // depend on timers rather than on the traffic.
+    expected: VecDeque<u8>,
+    matched: usize,
+    diverged: bool,
+
+    start: Instant,
+    speed: f64,
+    delay: Option<Delay>,
+}
+
+impl ReplayPort {
+    fn new(entries: Vec<Entry>, speed: f64) -> Self {
+        let (sent, received): (Vec<_>, Vec<_>) = entries.into_iter()
+            .partition(|entry| entry.direction == Direction::Sent);
+
+        let expected = sent.into_iter()
+            .flat_map(|entry| entry.data)
+            .filter(|&byte| proto::GrblRealtimeCommand::from_code(byte).is_none())
+            .collect();
+
+        return Self {
+            received: received.into(),
+            expected,
+            matched: 0,
+            diverged: false,
+            start: Instant::now(),
+            speed,
+            delay: None,
+        };
+    }
+}
+
+impl io::Read for ReplayPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The port stays open after the recording ended to keep the last state around
+        let time = match self.received.front() {
+            Some(entry) => entry.time,
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+
+        // A huge time or a tiny speed is not meant seriously, but must not bring down the driver
+        let due = Duration::try_from_secs_f64(time / self.speed).ok()
+            .and_then(|delay| self.start.checked_add(delay))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Replay time out of range"))?;
+
+        if Instant::now() < due {
+            // The delay is kept around, as the wake up is lost if it is dropped
+            let mut delay = Delay::new(due);
+            match delay.poll() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => {
+                    self.delay = Some(delay);
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+            }
+        }
+
+        let entry = self.received.front_mut().unwrap();
+
+        let len = cmp::min(buf.len(), entry.data.len());
+        buf[..len].copy_from_slice(&entry.data[..len]);
+        entry.data.drain(..len);
+
+        if entry.data.is_empty() {
+            self.received.pop_front();
+
+            if self.received.is_empty() {
+                log::info!("GRBL: Replay finished");
+            }
+        }
+
+        return Ok(len);
+    }
+}
+
+impl io::Write for ReplayPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if self.diverged || proto::GrblRealtimeCommand::from_code(byte).is_some() {
+                continue;
+            }
+
+            if self.expected.front() == Some(&byte) {
+                self.expected.pop_front();
+                self.matched += 1;
+            } else {
+                // Received bytes still follow the recording, but will no longer match what carbide did
+                log::warn!("GRBL: Replay diverged from the recording after {} bytes sent, expected {:?} but got {:?}",
+                           self.matched,
+                           self.expected.front().map(|&byte| escape(&[byte])),
+                           escape(&[byte]));
+                self.diverged = true;
+            }
+        }
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl AsyncRead for ReplayPort {}
+
+impl AsyncWrite for ReplayPort {
+    fn shutdown(&mut self) -> io::Result<Async<()>> {
+        return Ok(Async::Ready(()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::process;
+    use std::thread;
+
+    use crate::controller::Connection;
+    use crate::controller::Controller;
+
+    use super::*;
+    use super::super::GrblController;
+    use super::super::simulator::Simulator;
+
+    #[test]
+    fn test_escape() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(unescape(&escape(&data)), Some(data));
+
+        assert_eq!(escape(b"ok\r\n"), "ok\\r\\n");
+        assert_eq!(escape(&[0x85, b' ']), "\\x85 ");
+
+        assert_eq!(unescape("\\q"), None);
+        assert_eq!(unescape("\\x8"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let entries = parse("# /dev/ttyUSB0 at 0\n\
+                             0.5 < Grbl 1.1h ['$' for help]\\r\\n\n\
+                             1.0 > $X\\n\n\
+                             # /dev/ttyUSB0 at 10\n\
+                             0.25 >  \\x18\n").unwrap();
+
+        assert_eq!(entries, vec![
+            Entry { time: 0.5, direction: Direction::Received, data: b"Grbl 1.1h ['$' for help]\r\n".to_vec() },
+            Entry { time: 1.0, direction: Direction::Sent, data: b"$X\n".to_vec() },
+            Entry { time: 1.25, direction: Direction::Sent, data: b" \x18".to_vec() },
+        ]);
+
+        assert!(parse("0.5 ok\\r\\n").is_err());
+        assert!(parse("now < ok\\r\\n").is_err());
+        assert!(parse("-1.0 < ok\\r\\n").is_err());
+        assert!(parse("inf < ok\\r\\n").is_err());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = env::temp_dir().join(format!("carbide-recording-{}.txt", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let run = |transport: Box<Transport>, duration: Duration| {
+            let (controller, driver) = GrblController::with_transport(transport).unwrap();
+
+            let mut runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.spawn(driver.map_err(|err| panic!("{}", err)));
+
+            thread::sleep(duration);
+
+            let state = controller.current_state();
+            runtime.shutdown_now().wait().unwrap();
+            return state;
+        };
+
+        let recorded = run(Box::new(Recorder::new(Box::new(Simulator::new(BTreeMap::new())), path.clone())),
+                           Duration::from_millis(600));
+        assert_eq!(recorded.connection, Connection::Ready);
+        assert!(!recorded.settings.is_empty());
+        assert!(recorded.capabilities.version.is_some());
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# simulator at "));
+        assert!(text.contains("Grbl 1.1h [\\'$\\' for help]\\r\\n"));
+
+        // Played back faster, the controller reaches the state it was in while recording
+        let replayed = run(Box::new(ReplayConfig { replay: path.clone(), speed: 4.0 }),
+                           Duration::from_millis(400));
+        assert_eq!(replayed.connection, Connection::Ready);
+        assert_eq!(replayed.machine_position, recorded.machine_position);
+        assert_eq!(replayed.settings, recorded.settings);
+        assert_eq!(replayed.capabilities.version, recorded.capabilities.version);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        let config = ReplayConfig {
+            replay: PathBuf::from("session.txt"),
+            speed: 0.0,
+        };
+
+        assert!(config.open().wait().is_err());
+    }
+}
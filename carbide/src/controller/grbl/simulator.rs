@@ -0,0 +1,1337 @@
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+use carbide_gcode::{ArcDirection, CoordinateSystem, DiagnosticKind, DistanceMode, FeedMode, Interpreter, LexErrorKind};
+use carbide_gcode::{Motion, MoveKind, NonModal, Offsets, Plane, Point, Probe, Spindle, Stop, Units};
+use carbide_gcode::MILLIMETERS_PER_INCH;
+use failure::Error;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use futures::future;
+use futures::sync::mpsc;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::timer::Interval;
+
+use super::proto::{GrblFeedOverride, GrblRapidOverride, GrblRealtimeCommand, GrblSpeedOverride};
+use super::transport::Port;
+use super::transport::Transport;
+
+const VERSION: &str = "1.1h";
+const BUILD_DATE: &str = "20190825";
+
+// Size of Grbl's line buffer, which takes lines up to one byte shorter not counting spaces and control characters
+const LINE_BUFFER_SIZE: usize = 80;
+
+const PLANNER_SIZE: usize = 15;
+const RX_BUFFER_SIZE: usize = 128;
+
+// Interval in which the simulated machine moves on
+const TICK: Duration = Duration::from_millis(10);
+
+// Grbl's default settings, but with homing and soft limits enabled
+const DEFAULT_SETTINGS: [(u16, f64); 34] = [
+    (0, 10.0), (1, 25.0), (2, 0.0), (3, 0.0), (4, 0.0), (5, 0.0), (6, 0.0),
+    (10, 1.0), (11, 0.010), (12, 0.002), (13, 0.0),
+    (20, 1.0), (21, 0.0), (22, 1.0), (23, 0.0), (24, 25.0), (25, 500.0), (26, 250.0), (27, 1.0),
+    (30, 1000.0), (31, 0.0), (32, 0.0),
+    (100, 250.0), (101, 250.0), (102, 250.0),
+    (110, 500.0), (111, 500.0), (112, 500.0),
+    (120, 10.0), (121, 10.0), (122, 10.0),
+    (130, 200.0), (131, 200.0), (132, 200.0),
+];
+
+// Virtual Grbl 1.1 controller running in process, which is powered up on every open
+pub struct Simulator {
+    settings: BTreeMap<u16, f64>,
+}
+
+impl Simulator {
+    // Settings given here replace the defaults of the simulated controller
+    pub fn new(settings: BTreeMap<u16, f64>) -> Self {
+        return Self { settings };
+    }
+}
+
+impl fmt::Display for Simulator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "simulator");
+    }
+}
+
+impl Transport for Simulator {
+    fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send> {
+        let settings = self.settings.clone();
+
+        return Box::new(future::lazy(move || {
+            let (input_sender, input_receiver) = mpsc::unbounded();
+            let (output_sender, output_receiver) = mpsc::unbounded();
+
+            tokio::spawn(Emulation {
+                machine: Machine::new(settings),
+                input: input_receiver,
+                output: output_sender,
+                ticks: Interval::new(Instant::now() + TICK, TICK),
+                last: Instant::now(),
+            });
+
+            log::info!("GRBL: Simulator powered up");
+
+            return Ok(Box::new(SimulatorPort {
+                input: input_sender,
+                output: output_receiver,
+                pending: Bytes::new(),
+            }) as Box<Port>);
+        }));
+    }
+}
+
+// Drives the machine in time until the port is dropped
+struct Emulation {
+    machine: Machine,
+    input: mpsc::UnboundedReceiver<Bytes>,
+    output: mpsc::UnboundedSender<Bytes>,
+    ticks: Interval,
+    last: Instant,
+}
+
+impl Future for Emulation {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.input.poll()? {
+                Async::Ready(Some(data)) => self.machine.receive(&data),
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => break,
+            }
+        }
+
+        while let Async::Ready(Some(now)) = self.ticks.poll()
+            .map_err(|err| log::error!("GRBL: Simulator timer failed: {}", err))? {
+            self.machine.step(now.duration_since(self.last));
+            self.last = now;
+        }
+
+        let output = self.machine.take_output();
+        if !output.is_empty() && self.output.unbounded_send(Bytes::from(output)).is_err() {
+            return Ok(Async::Ready(()));
+        }
+
+        return Ok(Async::NotReady);
+    }
+}
+
+struct SimulatorPort {
+    input: mpsc::UnboundedSender<Bytes>,
+    output: mpsc::UnboundedReceiver<Bytes>,
+
+    // Output received from the machine but not yet read
+    pending: Bytes,
+}
+
+impl io::Read for SimulatorPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.output.poll() {
+                Ok(Async::Ready(Some(data))) => self.pending = data,
+                Ok(Async::Ready(None)) | Err(()) => return Ok(0),
+                Ok(Async::NotReady) => return Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending.split_to(len));
+
+        return Ok(len);
+    }
+}
+
+impl io::Write for SimulatorPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.unbounded_send(Bytes::from(buf))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Simulator stopped"))?;
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl AsyncRead for SimulatorPort {}
+
+impl AsyncWrite for SimulatorPort {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        return Ok(Async::Ready(()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Run,
+    Hold,
+    Jog,
+    Alarm,
+    Door,
+    Check,
+    Home,
+    Sleep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Alarm {
+    // Cleared by unlocking or homing
+    Locked,
+
+    // Stops all processing until reset, like for soft limit violations
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SegmentKind {
+    Rapid,
+    Feed(f64),
+    Jog(f64),
+    Home(f64),
+    Probe(f64, Probe),
+    Dwell(f64),
+}
+
+// A straight move to the target in machine coordinates as held by the planner
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Segment {
+    target: Point,
+    kind: SegmentKind,
+}
+
+// Pending acknowledgement of the line currently executed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Completion {
+    // The line is acknowledged after all motion has finished instead of once it is planned
+    sync: bool,
+
+    // Enter feed hold after the line, as done for `M0`
+    pause: bool,
+}
+
+struct Machine {
+    settings: BTreeMap<u16, f64>,
+    startup_lines: [String; 2],
+
+    // Parser state with the position at the end of the planned motion
+    interpreter: Interpreter,
+
+    // Actual position in machine coordinates
+    position: Point,
+    probe: (Point, bool),
+
+    rx: VecDeque<u8>,
+    planner: VecDeque<Segment>,
+
+    // Segments of the current line not fitting into the planner yet
+    pending: VecDeque<Segment>,
+    completion: Option<Completion>,
+    reset_requested: bool,
+
+    alarm: Option<Alarm>,
+    hold: bool,
+    door: bool,
+    check: bool,
+    sleep: bool,
+
+    feed_override: u32,
+    rapid_override: u32,
+    speed_override: u32,
+    spindle_stop: bool,
+    flood_coolant: bool,
+    mist_coolant: bool,
+
+    wco_counter: u32,
+    override_counter: u32,
+    reported_wco: Option<Point>,
+
+    output: Vec<u8>,
+}
+
+impl Machine {
+    fn new(settings: BTreeMap<u16, f64>) -> Self {
+        let mut defaults: BTreeMap<u16, f64> = DEFAULT_SETTINGS.iter().cloned().collect();
+        defaults.extend(settings);
+
+        let mut machine = Self {
+            settings: defaults,
+            startup_lines: Default::default(),
+            interpreter: Interpreter::new(),
+            position: Point::zero(),
+            probe: (Point::zero(), false),
+            rx: VecDeque::with_capacity(RX_BUFFER_SIZE),
+            planner: VecDeque::with_capacity(PLANNER_SIZE),
+            pending: VecDeque::new(),
+            completion: None,
+            reset_requested: false,
+            alarm: None,
+            hold: false,
+            door: false,
+            check: false,
+            sleep: false,
+            feed_override: 100,
+            rapid_override: 100,
+            speed_override: 100,
+            spindle_stop: false,
+            flood_coolant: false,
+            mist_coolant: false,
+            wco_counter: 0,
+            override_counter: 0,
+            reported_wco: None,
+            output: Vec::new(),
+        };
+
+        // The position is unknown after power up if the machine is meant to be homed
+        if machine.setting(22) != 0.0 {
+            machine.alarm = Some(Alarm::Locked);
+        }
+
+        machine.welcome();
+
+        return machine;
+    }
+
+    fn setting(&self, code: u16) -> f64 {
+        return self.settings.get(&code).cloned().unwrap_or(0.0);
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        return mem::replace(&mut self.output, Vec::new());
+    }
+
+    fn send(&mut self, line: &str) {
+        self.output.extend_from_slice(line.as_bytes());
+        self.output.extend_from_slice(b"\r\n");
+    }
+
+    fn welcome(&mut self) {
+        self.output.extend_from_slice(b"\r\n");
+        self.send(&format!("Grbl {} ['$' for help]", VERSION));
+
+        if self.alarm.is_some() {
+            self.send("[MSG:'$H'|'$X' to unlock]");
+        }
+    }
+
+    fn state(&self) -> State {
+        return if self.sleep {
+            State::Sleep
+        } else if self.alarm.is_some() {
+            State::Alarm
+        } else if self.door {
+            State::Door
+        } else if self.hold {
+            State::Hold
+        } else if self.check {
+            State::Check
+        } else {
+            match self.planner.front().or(self.pending.front()).map(|segment| segment.kind) {
+                Some(SegmentKind::Home(_)) => State::Home,
+                Some(SegmentKind::Jog(_)) => State::Jog,
+                Some(_) => State::Run,
+                None => State::Idle,
+            }
+        };
+    }
+
+    fn receive(&mut self, data: &[u8]) {
+        for &byte in data {
+            if let Some(command) = GrblRealtimeCommand::from_code(byte) {
+                self.realtime(command);
+                continue;
+            }
+
+            // Like on the real controller, data exceeding the buffer is lost
+            if self.rx.len() < RX_BUFFER_SIZE {
+                self.rx.push_back(byte);
+            } else {
+                log::warn!("GRBL: Simulator receive buffer overflow");
+            }
+        }
+
+        self.process();
+    }
+
+    // Moves the machine on by the elapsed time
+    fn step(&mut self, elapsed: Duration) {
+        let mut time = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+
+        while time > 0.0 && self.alarm.is_none() && !self.hold && !self.door && !self.sleep {
+            let segment = match self.planner.front().cloned() {
+                Some(segment) => segment,
+                None => break,
+            };
+
+            if let SegmentKind::Dwell(remaining) = segment.kind {
+                if remaining > time {
+                    self.planner[0].kind = SegmentKind::Dwell(remaining - time);
+                    break;
+                }
+
+                time -= remaining;
+                self.planner.pop_front();
+                continue;
+            }
+
+            let distance = self.position.distance(segment.target);
+            let rate = self.rate(&segment) / 60.0;
+
+            if distance > rate * time {
+                self.position = self.position + (segment.target - self.position) * (rate * time / distance);
+                break;
+            }
+
+            if distance > 0.0 {
+                time -= distance / rate;
+            }
+
+            self.position = segment.target;
+            self.planner.pop_front();
+
+            if let SegmentKind::Probe(_, probe) = segment.kind {
+                self.probed(probe);
+            }
+        }
+
+        self.process();
+    }
+
+    // Speed in mm/min for moving from the current position along the segment
+    fn rate(&self, segment: &Segment) -> f64 {
+        let direction = segment.target - self.position;
+        let length = direction.length();
+
+        let mut limit = f64::INFINITY;
+        for axis in 0..3 {
+            if direction[axis] != 0.0 {
+                limit = limit.min(self.setting(110 + axis as u16) * length / direction[axis].abs());
+            }
+        }
+
+        let rate = match segment.kind {
+            SegmentKind::Rapid => limit * f64::from(self.rapid_override) / 100.0,
+            SegmentKind::Feed(feed) => feed * f64::from(self.feed_override) / 100.0,
+            SegmentKind::Jog(feed) | SegmentKind::Home(feed) | SegmentKind::Probe(feed, _) => feed,
+            SegmentKind::Dwell(_) => 0.0,
+        };
+
+        return rate.min(limit);
+    }
+
+    // There is nothing to touch, so every probing motion ends without contact
+    fn probed(&mut self, probe: Probe) {
+        self.probe = (self.position, false);
+        self.send(&format!("[PRB:{}:0]", self.format_position(self.position)));
+
+        if probe.signal_failure {
+            self.alarm(5, false);
+        }
+    }
+
+    // Plans and executes received lines as long as there is room in the planner
+    fn process(&mut self) {
+        loop {
+            while self.planner.len() < PLANNER_SIZE {
+                match self.pending.pop_front() {
+                    Some(segment) => self.planner.push_back(segment),
+                    None => break,
+                }
+            }
+
+            if let Some(completion) = self.completion {
+                if !self.pending.is_empty() || (completion.sync && !self.planner.is_empty()) {
+                    return;
+                }
+
+                self.completion = None;
+                self.hold |= completion.pause;
+                self.send("ok");
+            }
+
+            if self.reset_requested {
+                self.reset_requested = false;
+                self.reset();
+            }
+
+            if self.sleep || self.alarm == Some(Alarm::Critical) {
+                return;
+            }
+
+            let end = match self.rx.iter().position(|&byte| byte == b'\n' || byte == b'\r') {
+                Some(end) => end,
+                None => return,
+            };
+
+            let mut line: Vec<u8> = self.rx.drain(..=end).collect();
+            line.pop();
+
+            self.execute(&String::from_utf8_lossy(&line));
+        }
+    }
+
+    fn execute(&mut self, line: &str) {
+        let result = if line.bytes().filter(|&byte| byte > b' ').count() >= LINE_BUFFER_SIZE {
+            Err(11)
+        } else if line.starts_with('$') {
+            let command: String = line[1..].chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            self.system(&command.to_ascii_uppercase())
+        } else {
+            self.gcode(line)
+        };
+
+        match result {
+            // Lines interrupted by a critical alarm are never acknowledged
+            Ok(_) if self.alarm == Some(Alarm::Critical) => {}
+            Ok(completion) => self.completion = Some(completion),
+            Err(code) => self.send(&format!("error:{}", code)),
+        }
+    }
+
+    fn system(&mut self, command: &str) -> Result<Completion, u8> {
+        let state = self.state();
+        let idle = state == State::Idle || state == State::Alarm;
+
+        match command {
+            "" => self.send("[HLP:$$ $# $G $I $N $x=val $Nx=line $J=line $SLP $C $X $H ~ ! ? ctrl-x]"),
+
+            "$" => {
+                if state == State::Run || state == State::Hold {
+                    return Err(8);
+                }
+
+                let settings: Vec<_> = self.settings.iter()
+                    .map(|(&code, &value)| format_setting(code, value))
+                    .collect();
+                for setting in settings {
+                    self.send(&setting);
+                }
+            }
+
+            "G" => {
+                let state = self.parser_state();
+                self.send(&state);
+            }
+
+            "C" => {
+                if self.check {
+                    // Leaving check mode resets to get rid of the parser state built by the checked lines
+                    self.send("[MSG:Disabled]");
+                    self.reset_requested = true;
+                } else if state == State::Idle {
+                    self.check = true;
+                    self.send("[MSG:Enabled]");
+                } else {
+                    return Err(8);
+                }
+            }
+
+            "X" => {
+                if self.alarm.is_some() {
+                    self.alarm = None;
+                    self.send("[MSG:Caution: Unlocked]");
+                }
+            }
+
+            _ if command.starts_with("J=") => return self.jog(&command[2..]),
+
+            _ if !idle => return Err(8),
+
+            "#" => self.parameters(),
+
+            "H" => {
+                if self.setting(22) == 0.0 {
+                    return Err(5);
+                }
+
+                // Homing directions default to positive, leaving the machine space in negative coordinates
+                let mut target = Point::zero();
+                for axis in 0..3 {
+                    target[axis] = if self.positive_space(axis) { 1.0 } else { -1.0 } * self.setting(27);
+                }
+
+                self.alarm = None;
+                self.interpreter.set_position(target);
+                self.pending.push_back(Segment {
+                    target,
+                    kind: SegmentKind::Home(self.setting(25)),
+                });
+
+                return Ok(Completion { sync: true, pause: false });
+            }
+
+            "I" => {
+                self.send(&format!("[VER:{}.{}:]", VERSION, BUILD_DATE));
+                // Homing places the origin at the home position, which is what forcing the origin does in Grbl
+                self.send(&format!("[OPT:VZ,{},{}]", PLANNER_SIZE, RX_BUFFER_SIZE));
+            }
+
+            "N" => {
+                let lines: Vec<_> = self.startup_lines.iter().enumerate()
+                    .map(|(nr, line)| format!("$N{}={}", nr, line))
+                    .collect();
+                for line in lines {
+                    self.send(&line);
+                }
+            }
+
+            "SLP" => {
+                self.stop();
+                self.sleep = true;
+                self.send("[MSG:Sleeping]");
+            }
+
+            "RST=$" | "RST=#" | "RST=*" => {
+                if command != "RST=#" {
+                    self.settings = DEFAULT_SETTINGS.iter().cloned().collect();
+                }
+                if command != "RST=$" {
+                    self.interpreter = Interpreter::with_offsets(Offsets::default());
+                    self.interpreter.set_position(self.position);
+                }
+
+                self.send("[MSG:Restoring defaults]");
+                self.reset_requested = true;
+            }
+
+            _ => {
+                let (code, value) = match command.find('=') {
+                    Some(index) => (&command[..index], &command[index + 1..]),
+                    None => return Err(3),
+                };
+
+                if code.starts_with('N') {
+                    let nr: usize = code[1..].parse().map_err(|_| 3)?;
+                    let line = self.startup_lines.get_mut(nr).ok_or(3)?;
+                    *line = value.to_owned();
+                } else {
+                    self.write_setting(code, value)?;
+                }
+            }
+        }
+
+        return Ok(Completion::default());
+    }
+
+    fn write_setting(&mut self, code: &str, value: &str) -> Result<(), u8> {
+        let code: u16 = code.parse().map_err(|_| 3)?;
+        let value: f64 = value.parse().map_err(|_| 2)?;
+
+        if !self.settings.contains_key(&code) {
+            return Err(3);
+        }
+
+        if value < 0.0 {
+            return Err(4);
+        }
+
+        // Soft limits need the position established by homing
+        if code == 20 && value != 0.0 && self.setting(22) == 0.0 {
+            return Err(10);
+        }
+
+        self.settings.insert(code, value);
+
+        return Ok(());
+    }
+
+    fn gcode(&mut self, line: &str) -> Result<Completion, u8> {
+        match self.state() {
+            State::Alarm | State::Sleep => return Err(9),
+            _ => {}
+        }
+
+        let program = carbide_gcode::parse(line);
+        if let Some(diagnostic) = program.errors().next() {
+            return Err(error_code(diagnostic.kind));
+        }
+
+        // Lines holding nothing but comments
+        let block = match program.blocks.first() {
+            Some(block) => block,
+            None => return Ok(Completion::default()),
+        };
+
+        let mut interpreter = self.interpreter.clone();
+        let mut moves = Vec::new();
+        interpreter.execute(block, &mut moves)
+            .map_err(|diagnostic| error_code(diagnostic.kind))?;
+
+        let mut completion = Completion {
+            sync: block.stop.is_some(),
+            pause: block.stop == Some(Stop::Pause),
+        };
+
+        let mut segments = Vec::new();
+        for motion in moves.iter() {
+            match motion.kind {
+                MoveKind::Rapid => segments.push(Segment { target: motion.end, kind: SegmentKind::Rapid }),
+                MoveKind::Linear => segments.push(Segment { target: motion.end, kind: SegmentKind::Feed(motion.feed) }),
+                MoveKind::Arc(_) => {
+                    for point in motion.linearize(self.setting(12)) {
+                        segments.push(Segment { target: point, kind: SegmentKind::Feed(motion.feed) });
+                    }
+                }
+                MoveKind::Probe(probe) => {
+                    segments.push(Segment { target: motion.end, kind: SegmentKind::Probe(motion.feed, probe) });
+                    completion.sync = true;
+                }
+                MoveKind::Dwell(seconds) => {
+                    segments.push(Segment { target: motion.end, kind: SegmentKind::Dwell(seconds) });
+                    completion.sync = true;
+                }
+            }
+        }
+
+        if segments.iter().any(|segment| !self.within_limits(segment.target)) {
+            self.alarm(2, true);
+            return Ok(completion);
+        }
+
+        self.interpreter = interpreter;
+
+        if block.coolant.is_some() || matches!(block.stop, Some(Stop::End) | Some(Stop::EndAndRewind)) {
+            self.flood_coolant = self.interpreter.state().flood_coolant;
+            self.mist_coolant = self.interpreter.state().mist_coolant;
+        }
+
+        if !self.check {
+            self.pending.extend(segments);
+        }
+
+        return Ok(completion);
+    }
+
+    fn jog(&mut self, line: &str) -> Result<Completion, u8> {
+        match self.state() {
+            State::Idle | State::Jog => {}
+            _ => return Err(8),
+        }
+
+        let program = carbide_gcode::parse(line);
+        if let Some(diagnostic) = program.errors().next() {
+            return Err(error_code(diagnostic.kind));
+        }
+
+        let mut block = program.blocks.first().cloned().ok_or(16)?;
+
+        // Jogging takes nothing but axis words, a feed rate and the unit, distance and machine coordinate modes
+        if block.motion.is_some() || block.plane.is_some() || block.arc_distance.is_some()
+            || block.feed_mode.is_some() || block.cutter_compensation.is_some() || block.tool_length_offset.is_some()
+            || block.coordinate_system.is_some() || block.stop.is_some() || block.tool_change
+            || block.spindle.is_some() || block.coolant.is_some() || block.speed.is_some() || block.tool.is_some()
+            || block.radius.is_some() || block.p.is_some() || block.l.is_some()
+            || block.non_modal.map_or(false, |non_modal| non_modal != NonModal::MachineCoordinates) {
+            return Err(16);
+        }
+
+        if block.feed.is_none() {
+            return Err(22);
+        }
+
+        if block.axes.is_empty() {
+            return Err(26);
+        }
+
+        block.motion = Some(Motion::Linear);
+        block.feed_mode = Some(FeedMode::UnitsPerMinute);
+
+        // The jog runs on a copy of the parser state as it must not change the modal state
+        let mut interpreter = self.interpreter.clone();
+        let mut moves = Vec::new();
+        interpreter.execute(&block, &mut moves)
+            .map_err(|diagnostic| error_code(diagnostic.kind))?;
+
+        let motion = moves.first().ok_or(16)?;
+        if !self.within_limits(motion.end) {
+            return Err(15);
+        }
+
+        self.interpreter.set_position(motion.end);
+        self.pending.push_back(Segment {
+            target: motion.end,
+            kind: SegmentKind::Jog(motion.feed),
+        });
+
+        return Ok(Completion::default());
+    }
+
+    fn realtime(&mut self, command: GrblRealtimeCommand) {
+        let state = self.state();
+
+        match command {
+            GrblRealtimeCommand::SoftReset => self.reset(),
+            GrblRealtimeCommand::StatusReportQuery => self.status_report(),
+
+            GrblRealtimeCommand::CycleStartResume => {
+                // The simulated door closes again as soon as resuming is requested
+                if state == State::Hold || state == State::Door {
+                    self.hold = false;
+                    self.door = false;
+                    self.spindle_stop = false;
+                }
+            }
+
+            GrblRealtimeCommand::FeedHold => match state {
+                State::Run => self.hold = true,
+                State::Jog => self.stop(),
+                _ => {}
+            },
+
+            GrblRealtimeCommand::SafetyDoor => {
+                if state != State::Alarm && state != State::Sleep && state != State::Check {
+                    self.door = true;
+                }
+            }
+
+            GrblRealtimeCommand::JogCancel => {
+                if state == State::Jog {
+                    self.stop();
+                }
+            }
+
+            GrblRealtimeCommand::FeedOverride(value) => {
+                self.feed_override = match value {
+                    GrblFeedOverride::Reset => 100,
+                    GrblFeedOverride::Increase10 => (self.feed_override + 10).min(200),
+                    GrblFeedOverride::Decrease10 => self.feed_override.saturating_sub(10).max(10),
+                    GrblFeedOverride::Increase1 => (self.feed_override + 1).min(200),
+                    GrblFeedOverride::Decrease1 => self.feed_override.saturating_sub(1).max(10),
+                };
+                self.override_counter = 0;
+            }
+
+            GrblRealtimeCommand::RapidOverride(value) => {
+                self.rapid_override = match value {
+                    GrblRapidOverride::Full => 100,
+                    GrblRapidOverride::Half => 50,
+                    GrblRapidOverride::Quarter => 25,
+                };
+                self.override_counter = 0;
+            }
+
+            GrblRealtimeCommand::SpeedOverride(value) => {
+                self.speed_override = match value {
+                    GrblSpeedOverride::Reset => 100,
+                    GrblSpeedOverride::Increase10 => (self.speed_override + 10).min(200),
+                    GrblSpeedOverride::Decrease10 => self.speed_override.saturating_sub(10).max(10),
+                    GrblSpeedOverride::Increase1 => (self.speed_override + 1).min(200),
+                    GrblSpeedOverride::Decrease1 => self.speed_override.saturating_sub(1).max(10),
+                };
+                self.override_counter = 0;
+            }
+
+            GrblRealtimeCommand::ToggleSpindleStop => {
+                if state == State::Hold {
+                    self.spindle_stop = !self.spindle_stop;
+                    self.override_counter = 0;
+                }
+            }
+
+            GrblRealtimeCommand::ToggleFloodCoolant => {
+                if state == State::Idle || state == State::Run || state == State::Hold {
+                    self.flood_coolant = !self.flood_coolant;
+                    self.override_counter = 0;
+                }
+            }
+
+            GrblRealtimeCommand::ToggleMistCoolant => {
+                if state == State::Idle || state == State::Run || state == State::Hold {
+                    self.mist_coolant = !self.mist_coolant;
+                    self.override_counter = 0;
+                }
+            }
+        }
+    }
+
+    // Drops all planned motion, leaving the machine where it is
+    fn stop(&mut self) {
+        self.planner.clear();
+        self.pending.clear();
+        self.interpreter.set_position(self.position);
+    }
+
+    fn alarm(&mut self, code: u8, critical: bool) {
+        self.stop();
+        self.hold = false;
+
+        self.alarm = Some(if critical { Alarm::Critical } else { Alarm::Locked });
+        self.send(&format!("ALARM:{}", code));
+
+        if critical {
+            self.send("[MSG:Reset to continue]");
+        }
+    }
+
+    fn reset(&mut self) {
+        // Stopping abruptly loses steps, so the position can not be trusted anymore
+        let aborted = self.alarm.is_none() && !self.hold && !self.door && !self.planner.is_empty();
+
+        self.stop();
+        self.rx.clear();
+        self.completion = None;
+        self.reset_requested = false;
+
+        if aborted {
+            self.send("ALARM:3");
+            self.alarm = Some(Alarm::Locked);
+        } else if self.alarm.is_some() || self.sleep {
+            self.alarm = Some(Alarm::Locked);
+        }
+
+        self.hold = false;
+        self.door = false;
+        self.check = false;
+        self.sleep = false;
+
+        self.feed_override = 100;
+        self.rapid_override = 100;
+        self.speed_override = 100;
+        self.spindle_stop = false;
+        self.flood_coolant = false;
+        self.mist_coolant = false;
+
+        // Coordinate systems are stored persistently, while `G92` and tool length offsets are lost
+        let offsets = Offsets {
+            offset: Point::zero(),
+            tool_length: 0.0,
+            ..self.interpreter.offsets().clone()
+        };
+
+        self.interpreter = Interpreter::with_offsets(offsets);
+        self.interpreter.set_position(self.position);
+
+        self.welcome();
+    }
+
+    fn positive_space(&self, axis: usize) -> bool {
+        return (self.setting(23) as u32) & (1 << axis) != 0;
+    }
+
+    fn within_limits(&self, point: Point) -> bool {
+        if self.setting(20) == 0.0 {
+            return true;
+        }
+
+        return (0..3).all(|axis| {
+            let travel = self.setting(130 + axis as u16);
+            let (min, max) = if self.positive_space(axis) { (0.0, travel) } else { (-travel, 0.0) };
+
+            point[axis] >= min - 1e-6 && point[axis] <= max + 1e-6
+        });
+    }
+
+    fn format_position(&self, position: Point) -> String {
+        return if self.setting(13) != 0.0 {
+            format!("{:.4},{:.4},{:.4}",
+                    position[0] / MILLIMETERS_PER_INCH, position[1] / MILLIMETERS_PER_INCH, position[2] / MILLIMETERS_PER_INCH)
+        } else {
+            format!("{:.3},{:.3},{:.3}", position[0], position[1], position[2])
+        };
+    }
+
+    fn status_report(&mut self) {
+        let state = self.state();
+        let busy = state != State::Idle && state != State::Alarm;
+        let mask = self.setting(10) as u32;
+        let wco = self.interpreter.position() - self.interpreter.work_position();
+
+        let mut fields = vec![match state {
+            State::Idle => "Idle",
+            State::Run => "Run",
+            State::Hold => "Hold:0",
+            State::Jog => "Jog",
+            State::Alarm => "Alarm",
+            State::Door => "Door:1",
+            State::Check => "Check",
+            State::Home => "Home",
+            State::Sleep => "Sleep",
+        }.to_owned()];
+
+        if mask & 1 != 0 {
+            fields.push(format!("MPos:{}", self.format_position(self.position)));
+        } else {
+            fields.push(format!("WPos:{}", self.format_position(self.position - wco)));
+        }
+
+        if mask & 2 != 0 {
+            fields.push(format!("Bf:{},{}", PLANNER_SIZE - self.planner.len(), RX_BUFFER_SIZE - self.rx.len()));
+        }
+
+        let feed = match (state, self.planner.front()) {
+            (State::Run, Some(segment)) | (State::Jog, Some(segment)) | (State::Home, Some(segment)) => self.rate(segment),
+            _ => 0.0,
+        };
+
+        let spindle = if self.spindle_stop { Spindle::Off } else { self.interpreter.state().spindle };
+        let speed = match spindle {
+            Spindle::Off => 0.0,
+            _ => self.interpreter.state().speed * f64::from(self.speed_override) / 100.0,
+        };
+
+        fields.push(format!("FS:{:.0},{:.0}", feed, speed));
+
+        if self.reported_wco != Some(wco) {
+            self.wco_counter = 0;
+        }
+
+        // Offsets and overrides are only reported every now and then, or once they changed
+        if self.wco_counter > 0 {
+            self.wco_counter -= 1;
+        } else {
+            self.wco_counter = if busy { 29 } else { 9 };
+            if self.override_counter == 0 {
+                self.override_counter = 1;
+            }
+
+            self.reported_wco = Some(wco);
+            fields.push(format!("WCO:{}", self.format_position(wco)));
+        }
+
+        if self.override_counter > 0 {
+            self.override_counter -= 1;
+        } else {
+            self.override_counter = if busy { 19 } else { 9 };
+            fields.push(format!("Ov:{},{},{}", self.feed_override, self.rapid_override, self.speed_override));
+
+            let mut accessories = String::new();
+            match spindle {
+                Spindle::Clockwise => accessories.push('S'),
+                Spindle::CounterClockwise => accessories.push('C'),
+                Spindle::Off => {}
+            }
+            if self.flood_coolant {
+                accessories.push('F');
+            }
+            if self.mist_coolant {
+                accessories.push('M');
+            }
+
+            if !accessories.is_empty() {
+                fields.push(format!("A:{}", accessories));
+            }
+        }
+
+        self.send(&format!("<{}>", fields.join("|")));
+    }
+
+    fn parameters(&mut self) {
+        let offsets = self.interpreter.offsets().clone();
+
+        for (system, offset) in CoordinateSystem::ALL.iter().zip(offsets.coordinate_systems.iter()) {
+            let line = format!("[{:?}:{}]", system, self.format_position(*offset));
+            self.send(&line);
+        }
+
+        let lines = vec![
+            format!("[G28:{}]", self.format_position(offsets.home)),
+            format!("[G30:{}]", self.format_position(offsets.secondary_home)),
+            format!("[G92:{}]", self.format_position(offsets.offset)),
+            format!("[TLO:{:.3}]", offsets.tool_length),
+            format!("[PRB:{}:{}]", self.format_position(self.probe.0), self.probe.1 as u8),
+        ];
+        for line in lines {
+            self.send(&line);
+        }
+    }
+
+    fn parser_state(&self) -> String {
+        let state = self.interpreter.state();
+
+        let motion = match state.motion {
+            Motion::Rapid => "G0",
+            Motion::Linear => "G1",
+            Motion::Arc(ArcDirection::Clockwise) => "G2",
+            Motion::Arc(ArcDirection::CounterClockwise) => "G3",
+            Motion::Probe(Probe { toward: true, signal_failure: true }) => "G38.2",
+            Motion::Probe(Probe { toward: true, signal_failure: false }) => "G38.3",
+            Motion::Probe(Probe { toward: false, signal_failure: true }) => "G38.4",
+            Motion::Probe(Probe { toward: false, signal_failure: false }) => "G38.5",
+            Motion::Cancel => "G80",
+        };
+
+        let plane = match state.plane {
+            Plane::XY => "G17",
+            Plane::ZX => "G18",
+            Plane::YZ => "G19",
+        };
+
+        let (units, feed) = match state.units {
+            Units::Inches => ("G20", format!("{:.1}", state.feed)),
+            Units::Millimeters => ("G21", format!("{:.0}", state.feed)),
+        };
+
+        let distance = match state.distance {
+            DistanceMode::Absolute => "G90",
+            DistanceMode::Incremental => "G91",
+        };
+
+        let feed_mode = match state.feed_mode {
+            FeedMode::InverseTime => "G93",
+            FeedMode::UnitsPerMinute => "G94",
+        };
+
+        let spindle = match state.spindle {
+            Spindle::Clockwise => "M3",
+            Spindle::CounterClockwise => "M4",
+            Spindle::Off => "M5",
+        };
+
+        let coolant = match (self.mist_coolant, self.flood_coolant) {
+            (true, true) => "M7 M8",
+            (true, false) => "M7",
+            (false, true) => "M8",
+            (false, false) => "M9",
+        };
+
+        return format!("[GC:{} {:?} {} {} {} {} {} {} T{} F{} S{:.0}]",
+                       motion, state.coordinate_system, plane, units, distance, feed_mode, spindle, coolant,
+                       state.tool, feed, state.speed);
+    }
+}
+
+fn format_setting(code: u16, value: f64) -> String {
+    return match code {
+        0..=10 | 13 | 20..=23 | 26 | 32 => format!("${}={}", code, value as u32),
+        30 | 31 => format!("${}={:.0}", code, value),
+        _ => format!("${}={:.3}", code, value),
+    };
+}
+
+// Grbl's error code for rejecting a line with the given problem
+fn error_code(kind: DiagnosticKind) -> u8 {
+    return match kind {
+        DiagnosticKind::Lex(LexErrorKind::UnexpectedCharacter(_)) => 1,
+        DiagnosticKind::Lex(_) => 2,
+        DiagnosticKind::UnsupportedGCode(_) | DiagnosticKind::UnsupportedMCode(_) | DiagnosticKind::UnsupportedWord(_) => 20,
+        DiagnosticKind::MisplacedBlockDelete | DiagnosticKind::MisplacedChecksum | DiagnosticKind::ChecksumMismatch { .. } => 20,
+        DiagnosticKind::ModalGroupConflict(_) => 21,
+        DiagnosticKind::MissingFeedRate => 22,
+        DiagnosticKind::InvalidValue(_) => 23,
+        DiagnosticKind::AxisCommandConflict => 24,
+        DiagnosticKind::DuplicateWord(_) => 25,
+        DiagnosticKind::MissingAxisWords => 26,
+        DiagnosticKind::MisplacedLineNumber => 27,
+        DiagnosticKind::MissingValue(_) => 28,
+        DiagnosticKind::MachineCoordinatesMotion => 30,
+        DiagnosticKind::UnusedAxisWords => 31,
+        DiagnosticKind::ArcRadiusMismatch { .. } | DiagnosticKind::ArcFullCircleRadius => 33,
+        DiagnosticKind::ArcRadiusTooSmall => 34,
+        DiagnosticKind::MissingArcParameters => 35,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::position::Position;
+
+    use super::*;
+    use super::super::proto::{GrblMachineHoldStatus, GrblMachineState, GrblMessage, GrblPositionStatus, GrblStatusReport};
+
+    fn output(machine: &mut Machine) -> Vec<String> {
+        return String::from_utf8(machine.take_output()).unwrap()
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+    }
+
+    fn unlocked() -> Machine {
+        let mut machine = Machine::new(BTreeMap::new());
+        machine.receive(b"$X\n");
+        machine.take_output();
+        return machine;
+    }
+
+    fn status(machine: &mut Machine) -> GrblStatusReport {
+        machine.receive(b"?");
+        let lines = output(machine);
+        return match GrblMessage::parse(lines.last().unwrap()).unwrap() {
+            GrblMessage::StatusReport(report) => report,
+            message => panic!("Unexpected message: {:?}", message),
+        };
+    }
+
+    #[test]
+    fn test_startup() {
+        let mut machine = Machine::new(BTreeMap::new());
+        assert_eq!(output(&mut machine), vec!["Grbl 1.1h ['$' for help]", "[MSG:'$H'|'$X' to unlock]"]);
+
+        machine.receive(b"G0 X-10\n");
+        assert_eq!(output(&mut machine), vec!["error:9"]);
+
+        machine.receive(b"$X\n");
+        assert_eq!(output(&mut machine), vec!["[MSG:Caution: Unlocked]", "ok"]);
+
+        machine.receive(b"\n");
+        assert_eq!(output(&mut machine), vec!["ok"]);
+    }
+
+    #[test]
+    fn test_motion() {
+        let mut machine = unlocked();
+
+        machine.receive(b"G1 X-5 F300\n");
+        assert_eq!(output(&mut machine), vec!["ok"]);
+
+        machine.step(Duration::from_millis(500));
+        machine.receive(b"?");
+        assert_eq!(output(&mut machine), vec!["<Run|MPos:-2.500,0.000,0.000|FS:300,0|WCO:0.000,0.000,0.000>"]);
+
+        machine.step(Duration::from_secs(1));
+        assert_eq!(status(&mut machine).position, GrblPositionStatus::MachinePosition(Position::from((-5.0, 0.0, 0.0))));
+
+        // Feeds are limited to the maximum rate of the axes involved, which rapids run at
+        machine.receive(b"G1 X-15 F1000\nG0 Y-20\n");
+        machine.step(Duration::from_secs(2));
+        assert_eq!(machine.position, Point::new(-15.0, -500.0 / 60.0 * 0.8, 0.0));
+    }
+
+    #[test]
+    fn test_feed_hold() {
+        let mut machine = unlocked();
+
+        machine.receive(b"G1 X-10 F300\n");
+        machine.receive(b"!");
+        machine.step(Duration::from_secs(1));
+        assert_eq!(status(&mut machine).machine_state, GrblMachineState::Hold(GrblMachineHoldStatus::Complete));
+        assert_eq!(machine.position, Point::zero());
+
+        machine.receive(&[b'~', 0x91, 0x91]);
+        machine.step(Duration::from_millis(500));
+        assert_eq!(machine.position, Point::new(-3.0, 0.0, 0.0));
+
+        let report = status(&mut machine);
+        assert_eq!(report.machine_state, GrblMachineState::Run);
+        assert_eq!(report.feed, Some(360.0));
+    }
+
+    #[test]
+    fn test_planner() {
+        let mut settings = BTreeMap::new();
+        settings.insert(10, 3.0);
+
+        let mut machine = Machine::new(settings);
+        machine.receive(b"$X\n");
+        machine.take_output();
+
+        let mut program = Vec::new();
+        for i in 1..=20 {
+            program.extend_from_slice(format!("G1 X-{} F60\n", i).as_bytes());
+        }
+
+        machine.receive(&program[..RX_BUFFER_SIZE]);
+        let planned = output(&mut machine).len();
+
+        // Lines are acknowledged once planned, the rest waits in the receive buffer
+        machine.receive(&program[RX_BUFFER_SIZE..]);
+        assert_eq!(output(&mut machine).len() + planned, 15);
+
+        let report = status(&mut machine);
+        let buffer = report.buffer.unwrap();
+        assert_eq!(buffer.planner, 0);
+        assert!(buffer.rx < 128);
+
+        machine.step(Duration::from_secs(1));
+        assert_eq!(output(&mut machine), vec!["ok"]);
+    }
+
+    #[test]
+    fn test_receive_buffer_overflow() {
+        let mut machine = unlocked();
+
+        machine.receive(b"G4 P1\n");
+        machine.receive(&[b'('; 200]);
+        assert_eq!(machine.rx.len(), RX_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_soft_limits() {
+        let mut machine = unlocked();
+
+        machine.receive(b"$J=G91 X-500 F1000\n");
+        assert_eq!(output(&mut machine), vec!["error:15"]);
+
+        machine.receive(b"G0 X10\n$X\n");
+        assert_eq!(output(&mut machine), vec!["ALARM:2", "[MSG:Reset to continue]"]);
+
+        machine.receive(&[0x18]);
+        assert_eq!(output(&mut machine), vec!["Grbl 1.1h ['$' for help]", "[MSG:'$H'|'$X' to unlock]"]);
+        assert_eq!(status(&mut machine).machine_state, GrblMachineState::Alarm);
+    }
+
+    #[test]
+    fn test_homing() {
+        let mut machine = Machine::new(BTreeMap::new());
+        machine.take_output();
+
+        machine.receive(b"$H\n");
+        assert_eq!(status(&mut machine).machine_state, GrblMachineState::Home);
+
+        machine.step(Duration::from_secs(1));
+        assert_eq!(output(&mut machine), vec!["ok"]);
+        assert_eq!(machine.position, Point::new(-1.0, -1.0, -1.0));
+    }
+
+    #[test]
+    fn test_reports() {
+        let mut machine = unlocked();
+
+        machine.receive(b"$$\n");
+        let lines = output(&mut machine);
+        assert_eq!(lines.len(), DEFAULT_SETTINGS.len() + 1);
+        assert!(lines.contains(&"$0=10".to_owned()));
+        assert!(lines.contains(&"$30=1000".to_owned()));
+        assert!(lines.contains(&"$130=200.000".to_owned()));
+
+        machine.receive(b"$G\n");
+        assert_eq!(output(&mut machine), vec!["[GC:G0 G54 G17 G21 G90 G94 M5 M9 T0 F0 S0]", "ok"]);
+
+        machine.receive(b"G10 L2 P2 X-5\nG55 G91 M3 S1000 M8 F200\n$G\n$#\n$I\n");
+        let lines = output(&mut machine);
+        assert_eq!(lines[2], "[GC:G0 G55 G17 G21 G91 G94 M3 M8 T0 F200 S1000]");
+        assert_eq!(lines[5], "[G55:-5.000,0.000,0.000]");
+        assert_eq!(lines[14], "[PRB:0.000,0.000,0.000:0]");
+        assert_eq!(lines[16], "[VER:1.1h.20190825:]");
+        assert_eq!(lines[17], "[OPT:VZ,15,128]");
+
+        for line in lines {
+            match GrblMessage::parse(&line).unwrap() {
+                GrblMessage::Other(line) => panic!("Unexpected line: {}", line),
+                _ => {}
+            }
+        }
+
+        machine.receive(b"$10=0\n$99=1\n$1=-1\n");
+        assert_eq!(output(&mut machine), vec!["ok", "error:3", "error:4"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut machine = unlocked();
+
+        machine.receive(b"G1 X-10\nG0 G1 X-1\nG2 X-1\nX-1 X-2\nG0 X-1 Y-1 Z-1 (fine)\n");
+        assert_eq!(output(&mut machine), vec!["error:22", "error:21", "error:35", "error:25", "ok"]);
+
+        machine.receive(format!("G0 X-1 ({})\n", "x".repeat(80)).as_bytes());
+        assert_eq!(output(&mut machine), vec!["error:11"]);
+    }
+}
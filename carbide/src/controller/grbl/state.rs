@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
 use futures::Async;
 use futures::AsyncSink;
 use futures::Sink;
@@ -19,42 +22,201 @@ impl Unit {
     pub fn metricize(&self, pos: Position) -> Position {
         return match self {
             Unit::Millimeter => pos,
-            Unit::Inch => pos * 25.4,
+            Unit::Inch => pos * carbide_gcode::MILLIMETERS_PER_INCH,
+        };
+    }
+
+    pub fn metricize_value(&self, value: f64) -> f64 {
+        return match self {
+            Unit::Millimeter => value,
+            Unit::Inch => value * carbide_gcode::MILLIMETERS_PER_INCH,
         };
     }
 }
 
+// Input of the state tracker
+#[derive(Debug, Clone)]
+pub enum Update {
+    Message(proto::GrblMessage),
+    Connection(controller::Connection),
+}
+
 #[derive(Debug)]
 pub struct State {
     unit: Unit,
 
     wco: Position,
 
+    // Raw settings as reported by the controller
+    settings: HashMap<codes::SettingCode, f64>,
+
+    // Last published state. Values not contained in every status report are carried over from here.
+    state: controller::State,
+
     sender: watch::Sender<controller::State>,
 }
 
 impl State {
     pub fn new() -> (Self, watch::Receiver<controller::State>) {
-        let (sender, receiver) = watch::channel(controller::State {
-            status: controller::MachineStatus::Idle,
-            machine_position: Position::zero(),
-            work_position: Position::zero(),
-        });
+        let (sender, receiver) = watch::channel(controller::State::default());
 
         return (Self {
             unit: Unit::Millimeter,
             wco: Position::zero(),
+            settings: HashMap::new(),
+            state: controller::State::default(),
             sender,
         }, receiver);
     }
 
+    fn limits(&self) -> controller::MachineLimits {
+        // Settings are always reported in millimeters, regardless of the report unit
+        let axes = |x: codes::SettingCode, y: codes::SettingCode, z: codes::SettingCode| -> Option<Position> {
+            return Some(Position {
+                x: *self.settings.get(&x)?,
+                y: *self.settings.get(&y)?,
+                z: *self.settings.get(&z)?,
+            });
+        };
+
+        return controller::MachineLimits {
+            travel: axes(codes::SettingCode::XAxisMaximumTravel,
+                         codes::SettingCode::YAxisMaximumTravel,
+                         codes::SettingCode::ZAxisMaximumTravel),
+            max_rate: axes(codes::SettingCode::XAxisMaximumRate,
+                           codes::SettingCode::YAxisMaximumRate,
+                           codes::SettingCode::ZAxisMaximumRate),
+            acceleration: axes(codes::SettingCode::XAxisAcceleration,
+                               codes::SettingCode::YAxisAcceleration,
+                               codes::SettingCode::ZAxisAcceleration),
+            junction_deviation: self.settings.get(&codes::SettingCode::JunctionDeviation).cloned(),
+            arc_tolerance: self.settings.get(&codes::SettingCode::ArcTolerance).cloned(),
+        };
+    }
+
+    fn settings(&self) -> Vec<controller::Setting> {
+        let mut settings: Vec<controller::Setting> = self.settings.iter()
+            .map(|(code, &value)| controller::Setting {
+                code: code.code(),
+                name: code.name().to_owned(),
+                unit: code.unit().to_owned(),
+                description: code.description().to_owned(),
+                value,
+            })
+            .collect();
+
+        settings.sort_by_key(|setting| setting.code);
+
+        return settings;
+    }
+
+    fn publish(&mut self) {
+        self.sender.broadcast(self.state.clone())
+            .expect("Failed to broadcast state");
+    }
+
     fn handle(&mut self, msg: proto::GrblMessage) {
         match msg {
             proto::GrblMessage::Setting {code, value} => {
                 // Remember configured unit
-                if code == codes::SETTING_CODE_REPORT_IN_INCHES {
+                if code == codes::SettingCode::ReportInInches {
                     self.unit = if (value as usize) == 0 { Unit::Millimeter } else { Unit::Inch };
                 }
+
+                self.settings.insert(code, value);
+
+                // Laser mode is a setting rather than a build option
+                if code == codes::SettingCode::LaserModeEnable {
+                    self.state.capabilities.laser_mode = value != 0.0;
+                }
+
+                self.state.limits = self.limits();
+                self.state.settings = self.settings();
+                self.publish();
+            }
+
+            proto::GrblMessage::Welcome { .. } => {
+                // Grbl forgets everything but its settings and parameters when reset
+                self.state.buffer = None;
+                self.state.line = None;
+                self.state.modal = controller::ModalState::default();
+                self.publish();
+            }
+
+            proto::GrblMessage::Version { version, .. } => {
+                self.state.capabilities.version = Some(version);
+                self.publish();
+            }
+
+            proto::GrblMessage::BuildOptions(options) => {
+                let capabilities = &mut self.state.capabilities;
+
+                capabilities.variable_spindle = options.has('V');
+                capabilities.line_numbers = options.has('N');
+                capabilities.mist_coolant = options.has('M');
+                capabilities.core_xy = options.has('C');
+                capabilities.parking = options.has('P');
+                capabilities.safety_door = options.has('+');
+                capabilities.homing_force_origin = options.has('Z');
+                capabilities.homing_single_axis = options.has('H');
+
+                capabilities.options = options.codes.chars()
+                    .map(|code| codes::BUILD_OPTION_CODES.get(&code)
+                        .map_or_else(|| code.to_string(), |desc| desc.to_string()))
+                    .collect();
+
+                capabilities.planner_blocks = options.planner_blocks;
+                capabilities.rx_buffer = options.rx_buffer;
+
+                self.publish();
+            }
+
+            proto::GrblMessage::Alarm(alarm) => {
+                self.state.alarm = Some(controller::Alarm {
+                    fault: alarm.into(),
+                    timestamp: SystemTime::now(),
+                });
+                self.publish();
+            }
+
+            proto::GrblMessage::Parameter(parameter) => {
+                let parameters = &mut self.state.parameters;
+
+                match parameter {
+                    proto::GrblParameter::CoordinateSystem(index, offset) => {
+                        parameters.coordinate_systems[index] = self.unit.metricize(offset);
+                    }
+                    proto::GrblParameter::Home(position) => {
+                        parameters.home = self.unit.metricize(position);
+                    }
+                    proto::GrblParameter::SecondaryHome(position) => {
+                        parameters.secondary_home = self.unit.metricize(position);
+                    }
+                    proto::GrblParameter::Offset(offset) => {
+                        parameters.offset = self.unit.metricize(offset);
+                    }
+                    proto::GrblParameter::ToolLengthOffset(offset) => {
+                        parameters.tool_length_offset = self.unit.metricize_value(offset);
+                    }
+                    proto::GrblParameter::Probe { position, success } => {
+                        parameters.probe = Some(controller::Probe {
+                            position: self.unit.metricize(position),
+                            success,
+                        });
+                    }
+                }
+
+                self.publish();
+            }
+
+            proto::GrblMessage::ParserState(modal) => {
+                // Feed rates are reported in the report unit like everything else
+                let feed = self.unit.metricize_value(modal.feed);
+
+                self.state.modal = modal.into();
+                self.state.modal.feed = feed;
+
+                self.publish();
             }
 
             proto::GrblMessage::StatusReport(status) => {
@@ -64,21 +226,52 @@ impl State {
 
                 let (mpos, wpos) = match status.position {
                     proto::GrblPositionStatus::MachinePosition(mpos) => {
+                        let mpos = self.unit.metricize(mpos);
                         (mpos, mpos - self.wco)
                     }
                     proto::GrblPositionStatus::WorkPosition(wpos) => {
+                        let wpos = self.unit.metricize(wpos);
                         (wpos + self.wco, wpos)
                     }
                 };
 
-                let state = controller::State {
-                    status: status.machine_state.into(),
-                    machine_position: mpos,
-                    work_position: wpos,
-                };
+                let state = &mut self.state;
+
+                state.status = status.machine_state.clone().into();
+
+                // The alarm is over once the machine got unlocked or reset
+                if status.machine_state != proto::GrblMachineState::Alarm {
+                    state.alarm = None;
+                }
+
+                state.machine_position = mpos;
+                state.work_position = wpos;
+                state.buffer = status.buffer.map(controller::BufferState::from);
+                state.line = status.line;
+
+                if let Some(feed) = status.feed {
+                    state.feed = self.unit.metricize_value(feed);
+                }
+
+                if let Some(speed) = status.speed {
+                    state.speed = speed;
+                }
+
+                // Pins are only reported while any of them is triggered
+                state.input_pins = status.input_pins
+                    .map(controller::InputPins::from)
+                    .unwrap_or_default();
+
+                if let Some(overrides) = status.overrides {
+                    state.overrides = overrides.into();
+
+                    // Accessory state is only reported along with overrides and left out if everything is off
+                    state.accessories = status.accessory
+                        .map(controller::Accessories::from)
+                        .unwrap_or_default();
+                }
 
-                self.sender.broadcast(state)
-                    .expect("Failed to broadcast state");
+                self.publish();
             }
 
             _ => {}
@@ -87,11 +280,19 @@ impl State {
 }
 
 impl Sink for State {
-    type SinkItem = proto::GrblMessage;
+    type SinkItem = Update;
     type SinkError = ();
 
     fn start_send(&mut self, item: Self::SinkItem) -> Result<AsyncSink<Self::SinkItem>, Self::SinkError> {
-        self.handle(item);
+        match item {
+            Update::Message(msg) => self.handle(msg),
+
+            Update::Connection(connection) => {
+                self.state.connection = connection;
+                self.publish();
+            }
+        }
+
         return Ok(AsyncSink::Ready);
     }
 
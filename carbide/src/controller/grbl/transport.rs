@@ -0,0 +1,237 @@
+use std::fmt;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use failure::Error;
+use futures::Future;
+use futures::future;
+use serde_derive::Deserialize;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
+use tokio_serial as serial;
+
+use super::recording::ReplayConfig;
+
+// Byte stream to a controller
+pub trait Port: AsyncRead + AsyncWrite + Send {}
+
+impl<T> Port for T
+    where T: AsyncRead + AsyncWrite + Send {}
+
+// Way of reaching a controller, which is opened again on every reconnect
+pub trait Transport: fmt::Display + Send + Sync {
+    fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send>;
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+impl Default for FlowControl {
+    fn default() -> Self {
+        return FlowControl::None;
+    }
+}
+
+impl From<FlowControl> for serial::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        return match flow_control {
+            FlowControl::None => serial::FlowControl::None,
+            FlowControl::Software => serial::FlowControl::Software,
+            FlowControl::Hardware => serial::FlowControl::Hardware,
+        };
+    }
+}
+
+fn default_baud_rate() -> u32 {
+    return 115200;
+}
+
+// Controller attached to a serial port
+#[derive(Debug, Clone, Deserialize)]
+pub struct SerialConfig {
+    pub path: String,
+
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+
+    #[serde(default)]
+    pub flow_control: FlowControl,
+
+    // Keeps other programs from opening the port while connected
+    #[serde(default)]
+    pub exclusive: bool,
+}
+
+impl fmt::Display for SerialConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.path);
+    }
+}
+
+impl Transport for SerialConfig {
+    fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send> {
+        let settings = serial::SerialPortSettings {
+            baud_rate: self.baud_rate,
+            data_bits: serial::DataBits::Eight,
+            flow_control: self.flow_control.into(),
+            parity: serial::Parity::None,
+            stop_bits: serial::StopBits::One,
+            timeout: Duration::from_millis(1),
+        };
+
+        let port = serial::Serial::from_path(&self.path, &settings)
+            .and_then(|mut port| {
+                port.set_exclusive(self.exclusive)?;
+                return Ok(port);
+            })
+            .map(|port| {
+                log::info!("GRBL: Port {} opened", self.path);
+                return Box::new(port) as Box<Port>;
+            })
+            .map_err(Error::from);
+
+        return Box::new(future::result(port));
+    }
+}
+
+// Controller reachable over a raw TCP connection, like ESP32 boards running grblHAL or FluidNC
+#[derive(Debug, Clone, Deserialize)]
+pub struct TcpConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for TcpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}:{}", self.host, self.port);
+    }
+}
+
+impl Transport for TcpConfig {
+    fn open(&self) -> Box<Future<Item=Box<Port>, Error=Error> + Send> {
+        let address = match (self.host.as_str(), self.port).to_socket_addrs()
+            .and_then(|mut addresses| addresses.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Host has no address"))) {
+            Ok(address) => address,
+            Err(err) => return Box::new(future::err(Error::from(err))),
+        };
+
+        return Box::new(TcpStream::connect(&address)
+            .and_then(move |stream| {
+                // Realtime commands are single bytes which must not wait for more to come
+                stream.set_nodelay(true)?;
+
+                log::info!("GRBL: Connected to {}", address);
+                return Ok(Box::new(stream) as Box<Port>);
+            })
+            .map_err(Error::from));
+    }
+}
+
+// Any kind of transport, told apart by the fields given
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TransportConfig {
+    Serial(SerialConfig),
+    Tcp(TcpConfig),
+    Replay(ReplayConfig),
+}
+
+impl TransportConfig {
+    pub fn transport(&self) -> Box<Transport> {
+        return match self {
+            TransportConfig::Serial(config) => Box::new(config.clone()),
+            TransportConfig::Tcp(config) => Box::new(config.clone()),
+            TransportConfig::Replay(config) => Box::new(config.clone()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let controller = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"Grbl 1.1f ['$' for help]\r\n").unwrap();
+        });
+
+        let transport = TcpConfig {
+            host: address.ip().to_string(),
+            port: address.port(),
+        };
+
+        let (_, banner) = tokio::runtime::current_thread::block_on_all(transport.open()
+            .and_then(|port| tokio::io::read_to_end(port, Vec::new())
+                .map_err(Error::from)))
+            .unwrap();
+
+        assert_eq!(banner, b"Grbl 1.1f ['$' for help]\r\n");
+
+        controller.join().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_refused() {
+        // Grab a free port and release it again
+        let address = TcpListener::bind("127.0.0.1:0").unwrap()
+            .local_addr().unwrap();
+
+        let transport = TcpConfig {
+            host: address.ip().to_string(),
+            port: address.port(),
+        };
+
+        assert!(tokio::runtime::current_thread::block_on_all(transport.open()).is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config: TransportConfig = serde_yaml::from_str("path: /dev/ttyUSB0").unwrap();
+        match config {
+            TransportConfig::Serial(config) => {
+                assert_eq!(config.path, "/dev/ttyUSB0");
+                assert_eq!(config.baud_rate, 115200);
+                assert!(!config.exclusive);
+            }
+            _ => panic!("Expected serial config"),
+        }
+
+        let config: TransportConfig = serde_yaml::from_str("{path: /dev/ttyACM0, baud_rate: 250000, flow_control: hardware, exclusive: true}").unwrap();
+        match config {
+            TransportConfig::Serial(config) => {
+                assert_eq!(config.baud_rate, 250000);
+                assert!(config.exclusive);
+            }
+            _ => panic!("Expected serial config"),
+        }
+
+        let config: TransportConfig = serde_yaml::from_str("{host: fluidnc.local, port: 23}").unwrap();
+        match config {
+            TransportConfig::Tcp(config) => assert_eq!(config.to_string(), "fluidnc.local:23"),
+            _ => panic!("Expected TCP config"),
+        }
+
+        let config: TransportConfig = serde_yaml::from_str("{replay: session.txt, speed: 2.0}").unwrap();
+        match config {
+            TransportConfig::Replay(config) => assert_eq!(config.to_string(), "replay of session.txt"),
+            _ => panic!("Expected replay config"),
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::time::SystemTime;
+
 use failure::Error;
 use futures::Future;
 use futures::Stream;
@@ -9,26 +12,143 @@ use crate::server;
 
 pub mod grbl;
 
+// Error or alarm reported by the controller
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fault {
+    // Controller specific code, `None` for faults raised by carbide itself
+    pub code: Option<u8>,
+
+    pub name: String,
+    pub description: String,
+}
+
+impl Fault {
+    pub fn new(name: &str) -> Self {
+        return Self {
+            code: None,
+            name: name.to_owned(),
+            description: String::new(),
+        };
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self.code {
+            Some(code) => write!(f, "{} ({})", self.name, code),
+            None => write!(f, "{}", self.name),
+        };
+    }
+}
+
+// Alarm raised by the controller and the time it was raised at
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub fault: Fault,
+    pub timestamp: SystemTime,
+}
+
 #[derive(Debug, Clone)]
 pub enum Response {
     Ok,
-    Error(String),
+    Error(Fault),
 }
 
 pub type Canceled = oneshot::Canceled;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RealtimeCommand {
+    FeedHold,
+    CycleStart,
+
+    // Resets the controller and discards all lines not yet sent to it
+    SoftReset,
+
+    // Holds like an opened safety door, retracting the spindle if the controller parks
+    SafetyDoor,
+    JogCancel,
+
+    // Spindle stop only works on hold, coolant toggles while idle, running or on hold
+    ToggleSpindleStop,
+    ToggleFloodCoolant,
+    ToggleMistCoolant,
+}
+
+// Rapid overrides only come in fixed steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RapidOverride {
+    Full,
+    Half,
+    Quarter,
+}
+
 pub trait Sender {
     fn send_line(&self, line: &str) -> Box<Future<Item=Response, Error=Canceled> + Send>;
+
+    fn send_realtime(&self, command: RealtimeCommand);
 }
 
-pub trait Controller: Send {
-    // TODO: Allow to get raw stream of controller output for terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
 
+// Raw traffic between carbide and the controller
+#[derive(Debug, Clone)]
+pub struct ConsoleMessage {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    pub text: String,
+}
+
+impl ConsoleMessage {
+    pub fn new(direction: Direction, text: String) -> Self {
+        return Self {
+            direction,
+            timestamp: SystemTime::now(),
+            text,
+        };
+    }
+}
+
+pub trait Controller: Send {
     fn description(&self) -> (server::ControllerType, &str);
 
     fn sender(&self) -> Box<Sender + Send>;
 
     fn state(&self) -> Box<Stream<Item=State, Error=()> + Send>;
+
+    fn current_state(&self) -> State;
+
+    fn console(&self) -> Box<Stream<Item=ConsoleMessage, Error=()> + Send>;
+
+    fn parameters(&self) -> Parameters {
+        return self.current_state().parameters;
+    }
+
+    // Sets the offset of the work coordinate system G54 to G59 by index, given in millimeters
+    fn set_coordinate_system(&self, index: usize, offset: Position) -> Box<Future<Item=Response, Error=Canceled> + Send>;
+
+    fn settings(&self) -> Vec<Setting> {
+        return self.current_state().settings;
+    }
+
+    // Writes a setting and fails unless reading it back afterwards yields the written value
+    fn write_setting(&self, code: u8, value: f64) -> Box<Future<Item=Response, Error=Canceled> + Send>;
+
+    // Releases the lock on the machine after an alarm without homing it
+    fn unlock(&self) -> Box<Future<Item=Response, Error=Canceled> + Send>;
+
+    // Runs the homing cycle, which completes once the machine is homed
+    fn home(&self) -> Box<Future<Item=Response, Error=Canceled> + Send>;
+
+    // Overrides are given in percent and take effect right away, even while a job is running
+    fn set_feed_override(&self, percent: f64);
+
+    fn set_rapid_override(&self, rapids: RapidOverride);
+
+    fn set_speed_override(&self, percent: f64);
 }
 
 #[derive(Debug, Clone)]
@@ -56,12 +176,321 @@ pub enum MachineStatus {
     Check,
     Home,
     Sleep,
+
+    // State the controller reported but carbide does not know about
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MachineLimits {
+    // Maximum travel per axis, `None` until reported by the controller
+    pub travel: Option<Position>,
+
+    // Maximum rate per axis in mm/min and acceleration per axis in mm/sec^2
+    pub max_rate: Option<Position>,
+    pub acceleration: Option<Position>,
+
+    pub junction_deviation: Option<f64>,
+    pub arc_tolerance: Option<f64>,
+}
+
+impl MachineLimits {
+    // Motion model used for estimating run times, `None` until all required limits are known
+    pub fn model(&self) -> Option<carbide_gcode::MachineModel> {
+        let defaults = carbide_gcode::MachineModel::default();
+
+        return Some(carbide_gcode::MachineModel {
+            max_rate: self.max_rate?.into(),
+            acceleration: self.acceleration?.into(),
+            junction_deviation: self.junction_deviation.unwrap_or(defaults.junction_deviation),
+            arc_tolerance: self.arc_tolerance.unwrap_or(defaults.arc_tolerance),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferState {
+    // Available blocks in the planner and bytes in the receive buffer
+    pub planner: usize,
+    pub rx: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InputPins {
+    pub x_limit: bool,
+    pub y_limit: bool,
+    pub z_limit: bool,
+    pub probe: bool,
+    pub door: bool,
+    pub hold: bool,
+    pub soft_reset: bool,
+    pub cycle_start: bool,
+}
+
+// Override values in percent
+#[derive(Debug, Clone)]
+pub struct Overrides {
+    pub feed: f64,
+    pub rapids: f64,
+    pub speed: f64,
+}
+
+impl Default for Overrides {
+    fn default() -> Self {
+        return Self {
+            feed: 100.0,
+            rapids: 100.0,
+            speed: 100.0,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpindleState {
+    Off,
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug, Clone)]
+pub struct Accessories {
+    pub spindle: SpindleState,
+    pub flood_coolant: bool,
+    pub mist_coolant: bool,
+}
+
+impl Default for Accessories {
+    fn default() -> Self {
+        return Self {
+            spindle: SpindleState::Off,
+            flood_coolant: false,
+            mist_coolant: false,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionMode {
+    Rapid,
+    Linear,
+    ClockwiseArc,
+    CounterClockwiseArc,
+    ProbeToward,
+    ProbeTowardNoError,
+    ProbeAway,
+    ProbeAwayNoError,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Plane {
+    XY,
+    ZX,
+    YZ,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    Inches,
+    Millimeters,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMode {
+    Absolute,
+    Incremental,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedMode {
+    InverseTime,
+    UnitsPerMinute,
+}
+
+// Modal state of the controller's g-code parser, which applies to the next line sent
+#[derive(Debug, Clone)]
+pub struct ModalState {
+    pub motion: MotionMode,
+
+    // Index of the active work coordinate system, G54 being 0
+    pub coordinate_system: usize,
+
+    pub plane: Plane,
+    pub units: Units,
+    pub distance: DistanceMode,
+    pub feed_mode: FeedMode,
+
+    pub spindle: SpindleState,
+    pub flood_coolant: bool,
+    pub mist_coolant: bool,
+
+    pub tool: u32,
+
+    // Programmed feed rate in mm/min and spindle speed in RPM
+    pub feed: f64,
+    pub speed: f64,
+}
+
+impl Default for ModalState {
+    fn default() -> Self {
+        return Self {
+            motion: MotionMode::Rapid,
+            coordinate_system: 0,
+            plane: Plane::XY,
+            units: Units::Millimeters,
+            distance: DistanceMode::Absolute,
+            feed_mode: FeedMode::UnitsPerMinute,
+            spindle: SpindleState::Off,
+            flood_coolant: false,
+            mist_coolant: false,
+            tool: 0,
+            feed: 0.0,
+            speed: 0.0,
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub position: Position,
+    pub success: bool,
+}
+
+// Parameters stored by the controller in machine coordinates and millimeters
+#[derive(Debug, Clone)]
+pub struct Parameters {
+    // Offsets of the work coordinate systems G54 to G59
+    pub coordinate_systems: [Position; 6],
+
+    // Positions stored by G28.1 and G30.1
+    pub home: Position,
+    pub secondary_home: Position,
+
+    // Offset set by G92
+    pub offset: Position,
+
+    pub tool_length_offset: f64,
+
+    // Result of the last probing cycle
+    pub probe: Option<Probe>,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        return Self {
+            coordinate_systems: [Position::zero(); 6],
+            home: Position::zero(),
+            secondary_home: Position::zero(),
+            offset: Position::zero(),
+            tool_length_offset: 0.0,
+            probe: None,
+        };
+    }
+}
+
+// Setting stored in the controller
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub code: u8,
+    pub name: String,
+    pub unit: String,
+    pub description: String,
+    pub value: f64,
+}
+
+// Features of the controller as far as it reported them
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub version: Option<String>,
+
+    pub variable_spindle: bool,
+    pub laser_mode: bool,
+    pub line_numbers: bool,
+    pub mist_coolant: bool,
+    pub core_xy: bool,
+    pub parking: bool,
+    pub safety_door: bool,
+    pub homing_force_origin: bool,
+    pub homing_single_axis: bool,
+
+    // Descriptions of all enabled build options
+    pub options: Vec<String>,
+
+    // Size of the motion planner in blocks and of the receive buffer in bytes
+    pub planner_blocks: Option<usize>,
+    pub rx_buffer: Option<usize>,
+}
+
+// Progress of establishing communication with the controller
+#[derive(Debug, Clone, PartialEq)]
+pub enum Connection {
+    Connecting,
+    Ready,
+
+    // The controller did not identify itself, holding the reason why
+    Failed(String),
+
+    // The connection got lost or could not be opened and is retried, holding the reason why
+    Disconnected(String),
 }
 
 #[derive(Debug, Clone)]
 pub struct State {
+    pub connection: Connection,
+
     pub status: MachineStatus,
 
     pub machine_position: Position,
     pub work_position: Position,
+
+    pub limits: MachineLimits,
+
+    // All settings reported by the controller ordered by code
+    pub settings: Vec<Setting>,
+
+    pub buffer: Option<BufferState>,
+
+    // Line number of the block currently executed, if the program uses line numbers
+    pub line: Option<usize>,
+
+    // Current feed rate in mm/min and spindle speed in RPM
+    pub feed: f64,
+    pub speed: f64,
+
+    pub input_pins: InputPins,
+    pub overrides: Overrides,
+    pub accessories: Accessories,
+
+    // Alarm raised by the controller, `None` if it is not known why the machine is locked
+    pub alarm: Option<Alarm>,
+
+    pub modal: ModalState,
+
+    pub parameters: Parameters,
+
+    pub capabilities: Capabilities,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        return Self {
+            connection: Connection::Connecting,
+            status: MachineStatus::Idle,
+            machine_position: Position::zero(),
+            work_position: Position::zero(),
+            limits: MachineLimits::default(),
+            settings: Vec::new(),
+            buffer: None,
+            line: None,
+            feed: 0.0,
+            speed: 0.0,
+            input_pins: InputPins::default(),
+            overrides: Overrides::default(),
+            accessories: Accessories::default(),
+            alarm: None,
+            modal: ModalState::default(),
+            parameters: Parameters::default(),
+            capabilities: Capabilities::default(),
+        };
+    }
 }
\ No newline at end of file
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use carbide_gcode::{BoundingBox, Diagnostic, Interpreter, MachineModel};
+use failure::Error;
+
+#[derive(Debug)]
+pub enum LibraryError {
+    InvalidName,
+    NotFound,
+
+    // The program contains errors and was rejected
+    Invalid(Vec<Diagnostic>),
+
+    Io(io::Error),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            LibraryError::InvalidName => write!(f, "Invalid file name"),
+            LibraryError::NotFound => write!(f, "No such file"),
+            LibraryError::Invalid(diagnostics) => write!(f, "Invalid program: {} errors", diagnostics.len()),
+            LibraryError::Io(err) => write!(f, "I/O error: {}", err),
+        };
+    }
+}
+
+impl error::Error for LibraryError {}
+
+impl From<io::Error> for LibraryError {
+    fn from(err: io::Error) -> Self {
+        return match err.kind() {
+            io::ErrorKind::NotFound => LibraryError::NotFound,
+            _ => LibraryError::Io(err),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub size: u64,
+    pub lines: usize,
+
+    // Bounding box in work coordinates
+    pub bounds: Option<BoundingBox>,
+
+    // Estimated run time in seconds, `None` if the motion limits of the machine are unknown
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+// Metadata is expensive to compute for large files, so it is kept until the file or the machine model changes
+struct Cached {
+    modified: SystemTime,
+    model: Option<MachineModel>,
+    metadata: Metadata,
+}
+
+pub struct Library {
+    path: PathBuf,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+impl Library {
+    pub fn new<P>(path: P) -> Result<Self, Error>
+        where P: AsRef<Path> {
+        fs::create_dir_all(path.as_ref())?;
+
+        return Ok(Self {
+            path: path.as_ref().to_owned(),
+            cache: Mutex::new(HashMap::new()),
+        });
+    }
+
+    // Only plain file names are accepted to keep requests from escaping the library directory
+    fn file(&self, name: &str) -> Result<PathBuf, LibraryError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_');
+
+        if !valid {
+            return Err(LibraryError::InvalidName);
+        }
+
+        return Ok(self.path.join(name));
+    }
+
+    pub fn list(&self, model: Option<&MachineModel>) -> Result<Vec<Entry>, LibraryError> {
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            // Skip hidden and temporary files
+            if self.file(&name).is_err() || !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            entries.push(Entry {
+                metadata: self.metadata(&name, model)?,
+                name,
+            });
+        }
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        return Ok(entries);
+    }
+
+    pub fn metadata(&self, name: &str, model: Option<&MachineModel>) -> Result<Metadata, LibraryError> {
+        let file = self.file(name)?;
+        let modified = fs::metadata(&file)?.modified()?;
+
+        if let Some(cached) = self.cache.lock().unwrap().get(name) {
+            if cached.modified == modified && cached.model.as_ref() == model {
+                return Ok(cached.metadata.clone());
+            }
+        }
+
+        let source = fs::read_to_string(&file)?;
+        let metadata = analyze(&source, model);
+
+        self.cache.lock().unwrap().insert(name.to_owned(), Cached {
+            modified,
+            model: model.cloned(),
+            metadata: metadata.clone(),
+        });
+
+        return Ok(metadata);
+    }
+
+    pub fn read(&self, name: &str) -> Result<String, LibraryError> {
+        return Ok(fs::read_to_string(self.file(name)?)?);
+    }
+
+    // Stores the program after making sure it is free of errors
+    pub fn upload(&self, name: &str, source: &str, model: Option<&MachineModel>) -> Result<Metadata, LibraryError> {
+        let file = self.file(name)?;
+
+        let program = carbide_gcode::parse(source);
+        let (_, diagnostics) = Interpreter::new().run(&program);
+
+        let errors: Vec<Diagnostic> = program.diagnostics.iter()
+            .chain(diagnostics.iter())
+            .filter(|diagnostic| diagnostic.is_error())
+            .cloned()
+            .collect();
+        if !errors.is_empty() {
+            return Err(LibraryError::Invalid(errors));
+        }
+
+        // Write to a hidden file first so a partially written file never shows up in the library
+        let temp = self.path.join(format!(".{}.upload", name));
+        fs::write(&temp, source)?;
+        fs::rename(&temp, &file)?;
+
+        return self.metadata(name, model);
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), LibraryError> {
+        fs::remove_file(self.file(name)?)?;
+        self.cache.lock().unwrap().remove(name);
+
+        return Ok(());
+    }
+}
+
+fn analyze(source: &str, model: Option<&MachineModel>) -> Metadata {
+    let program = carbide_gcode::parse(source);
+    let (moves, _) = Interpreter::new().run(&program);
+
+    return Metadata {
+        size: source.len() as u64,
+        lines: source.lines().count(),
+        bounds: BoundingBox::of(moves.iter()),
+        duration: model.map(|model| carbide_gcode::estimate(&moves, model).total),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_library() {
+        let path = std::env::temp_dir().join(format!("carbide-library-{}", std::process::id()));
+        let library = Library::new(&path).unwrap();
+
+        let metadata = library.upload("square.nc", "G0 X0 Y0\nG1 X10 F100\nY10\nX0\nY0\n", None).unwrap();
+        assert_eq!(metadata.lines, 5);
+        assert_eq!(metadata.bounds.map(|b| b.max), Some(carbide_gcode::Point::new(10.0, 10.0, 0.0)));
+        assert_eq!(metadata.duration, None);
+
+        match library.upload("broken.nc", "G0 X0\nG2 X10 F100\n", None) {
+            Err(LibraryError::Invalid(errors)) => assert_eq!(errors[0].line, 2),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        match library.upload("../escape.nc", "G0 X0", None) {
+            Err(LibraryError::InvalidName) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let entries = library.list(Some(&MachineModel::default())).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "square.nc");
+        assert!(entries[0].metadata.duration.unwrap() > 0.0);
+
+        assert_eq!(library.read("square.nc").unwrap().lines().count(), 5);
+
+        library.delete("square.nc").unwrap();
+        match library.read("square.nc") {
+            Err(LibraryError::NotFound) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,3 @@
+pub mod library;
+pub mod preflight;
+pub mod runner;
@@ -0,0 +1,243 @@
+use carbide_gcode::{BoundingBox, CoordinateSystem, Diagnostic, Estimate, Interpreter, ModalState, Offsets, Point, Program};
+
+use crate::controller;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn letter(&self) -> char {
+        return match self {
+            Axis::X => 'X',
+            Axis::Y => 'Y',
+            Axis::Z => 'Z',
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    // Line of the first move leaving the envelope
+    pub line: usize,
+
+    pub axis: Axis,
+
+    // Machine position reached by the move and the limit it exceeds
+    pub position: f64,
+    pub limit: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Preflight {
+    // Bounding box of all moves in machine coordinates
+    pub bounds: Option<BoundingBox>,
+
+    // Envelope the moves are checked against or `None` if the machine travel is unknown
+    pub envelope: Option<BoundingBox>,
+
+    pub violations: Vec<Violation>,
+    pub diagnostics: Vec<Diagnostic>,
+
+    // Estimated run time or `None` if the motion limits of the machine are unknown
+    pub estimate: Option<Estimate>,
+}
+
+impl Preflight {
+    pub fn check(program: &Program, state: &controller::State) -> Self {
+        let parameters = &state.parameters;
+        let mut offsets = Offsets {
+            home: parameters.home.into(),
+            secondary_home: parameters.secondary_home.into(),
+            offset: parameters.offset.into(),
+            tool_length: parameters.tool_length_offset,
+            ..Offsets::default()
+        };
+        for (offset, &system) in offsets.coordinate_systems.iter_mut().zip(parameters.coordinate_systems.iter()) {
+            *offset = system.into();
+        }
+
+        // The program starts out in the work coordinate system the controller is in
+        let modal = ModalState {
+            coordinate_system: CoordinateSystem::ALL.get(state.modal.coordinate_system).cloned()
+                .unwrap_or(CoordinateSystem::G54),
+            ..ModalState::default()
+        };
+
+        let mut interpreter = Interpreter::with_offsets(offsets);
+        interpreter.set_state(modal);
+        interpreter.set_position(state.machine_position.into());
+
+        let (moves, diagnostics) = interpreter.run(program);
+
+        let mut diagnostics: Vec<Diagnostic> = program.diagnostics.iter()
+            .cloned()
+            .chain(diagnostics)
+            .collect();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+        // Grbl places the machine origin at the home position and the work area in negative space. Forcing the origin
+        // to the home position turns the work area of axes homing towards their minimum into positive space.
+        let homing_direction = state.settings.iter()
+            .find(|setting| setting.code == 23)
+            .map_or(0, |setting| setting.value as u32);
+        let envelope = state.limits.travel.map(|travel| {
+            let travel = Point::from(travel);
+            let mut envelope = BoundingBox {
+                min: -travel,
+                max: Point::zero(),
+            };
+
+            for axis in 0..3 {
+                if state.capabilities.homing_force_origin && homing_direction & (1 << axis) != 0 {
+                    envelope.min[axis] = 0.0;
+                    envelope.max[axis] = travel[axis];
+                }
+            }
+
+            return envelope;
+        });
+
+        let mut violations = Vec::new();
+
+        if let Some(envelope) = envelope {
+            // Only the first violation per axis and direction is reported
+            let mut reported = [[false; 2]; 3];
+
+            for m in moves.iter() {
+                let bounds = m.bounds();
+
+                for (i, axis) in Axis::ALL.iter().enumerate() {
+                    if bounds.min[i] < envelope.min[i] && !reported[i][0] {
+                        reported[i][0] = true;
+                        violations.push(Violation {
+                            line: m.line,
+                            axis: *axis,
+                            position: bounds.min[i],
+                            limit: envelope.min[i],
+                        });
+                    }
+
+                    if bounds.max[i] > envelope.max[i] && !reported[i][1] {
+                        reported[i][1] = true;
+                        violations.push(Violation {
+                            line: m.line,
+                            axis: *axis,
+                            position: bounds.max[i],
+                            limit: envelope.max[i],
+                        });
+                    }
+                }
+            }
+        }
+
+        let estimate = state.limits.model()
+            .map(|model| carbide_gcode::estimate(&moves, &model));
+
+        return Self {
+            bounds: BoundingBox::of(moves.iter()),
+            envelope,
+            violations,
+            diagnostics,
+            estimate,
+        };
+    }
+
+    pub fn is_ok(&self) -> bool {
+        return self.violations.is_empty() && !self.diagnostics.iter().any(Diagnostic::is_error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    #[test]
+    fn test_preflight() {
+        let state = controller::State {
+            status: controller::MachineStatus::Idle,
+            machine_position: Position { x: -100.0, y: -100.0, z: -10.0 },
+            work_position: Position { x: 0.0, y: 0.0, z: 0.0 },
+            limits: controller::MachineLimits {
+                travel: Some(Position { x: 200.0, y: 200.0, z: 50.0 }),
+                ..controller::MachineLimits::default()
+            },
+            parameters: controller::Parameters {
+                coordinate_systems: [
+                    Position { x: -100.0, y: -100.0, z: -10.0 },
+                    Position { x: -20.0, y: -100.0, z: -10.0 },
+                    Position::zero(), Position::zero(), Position::zero(), Position::zero(),
+                ],
+                ..controller::Parameters::default()
+            },
+            ..controller::State::default()
+        };
+
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X50 Y50\nG1 Z-5 F100\nG0 Z5"), &state);
+        assert!(preflight.is_ok());
+        assert!(preflight.estimate.is_none());
+        assert_eq!(preflight.bounds.map(|b| b.min), Some(Point::new(-100.0, -100.0, -15.0)));
+
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X50\nG0 X150\nG0 Z20\nG0 X-120"), &state);
+        assert!(!preflight.is_ok());
+        assert_eq!(preflight.violations.len(), 3);
+        assert_eq!((preflight.violations[0].line, preflight.violations[0].axis), (2, Axis::X));
+        assert_eq!((preflight.violations[1].line, preflight.violations[1].axis), (3, Axis::Z));
+        assert_eq!((preflight.violations[2].line, preflight.violations[2].axis), (4, Axis::X));
+        assert_eq!(preflight.violations[2].limit, -200.0);
+
+        // Each work coordinate system has its own offset, also when switching within the program
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X50\nG55\nG0 X50"), &state);
+        assert!(!preflight.is_ok());
+        assert_eq!(preflight.violations.len(), 1);
+        assert_eq!((preflight.violations[0].line, preflight.violations[0].axis), (3, Axis::X));
+
+        let modal = controller::ModalState {
+            coordinate_system: 1,
+            ..controller::ModalState::default()
+        };
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X-5"), &controller::State { modal, ..state.clone() });
+        assert!(preflight.is_ok());
+        assert_eq!(preflight.bounds.map(|b| b.max.x), Some(-25.0));
+
+        // Axes homing towards their minimum work in positive space once the origin is forced to the home position
+        let forced = controller::State {
+            machine_position: Position { x: 100.0, y: 100.0, z: -10.0 },
+            settings: vec![controller::Setting {
+                code: 23,
+                name: String::new(),
+                unit: String::new(),
+                description: String::new(),
+                value: 3.0,
+            }],
+            capabilities: controller::Capabilities {
+                homing_force_origin: true,
+                ..controller::Capabilities::default()
+            },
+            parameters: controller::Parameters {
+                coordinate_systems: [Position { x: 100.0, y: 100.0, z: -10.0 }; 6],
+                ..controller::Parameters::default()
+            },
+            ..state.clone()
+        };
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X50 Y50 Z5"), &forced);
+        assert!(preflight.is_ok());
+        assert_eq!(preflight.envelope.map(|e| (e.min, e.max)),
+                   Some((Point::new(0.0, 0.0, -50.0), Point::new(200.0, 200.0, 0.0))));
+
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X-150"), &forced);
+        assert_eq!(preflight.violations.len(), 1);
+        assert_eq!(preflight.violations[0].limit, 0.0);
+
+        let state = controller::State { limits: controller::MachineLimits::default(), ..state };
+        let preflight = Preflight::check(&carbide_gcode::parse("G0 X500"), &state);
+        assert!(preflight.is_ok());
+        assert!(preflight.envelope.is_none());
+    }
+}
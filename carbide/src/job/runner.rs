@@ -0,0 +1,350 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use carbide_gcode::{Estimate, Lexer, TokenKind};
+use futures::Async;
+use futures::Future;
+use futures::Stream;
+use futures::stream;
+use futures::sync::mpsc;
+use futures::try_ready;
+use tokio::sync::watch;
+use tokio::timer::Timeout;
+
+use crate::controller;
+
+// Number of lines queued up ahead of the acknowledged ones. The character counting buffer in the controller decides
+// how many of them are actually handed to the machine.
+const WINDOW: usize = 32;
+
+// Time to wait for the machine to come to a stop before resetting it on abort
+const HOLD_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Running,
+    Paused,
+    Completed,
+    Aborted,
+    Failed { line: usize, error: controller::Fault },
+
+    // Lost the controller, holding the source line last acknowledged by it
+    Interrupted { line: Option<usize> },
+}
+
+impl Status {
+    pub fn is_finished(&self) -> bool {
+        return match self {
+            Status::Running | Status::Paused => false,
+            Status::Completed | Status::Aborted | Status::Failed { .. } | Status::Interrupted { .. } => true,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub status: Status,
+
+    // Number of lines to stream, sent to the controller and acknowledged by it
+    pub lines: usize,
+    pub sent: usize,
+    pub acknowledged: usize,
+
+    // Source line of the most recently acknowledged line
+    pub current_line: Option<usize>,
+
+    // Estimated times in seconds, `None` if the motion limits of the machine are unknown
+    pub total_time: Option<f64>,
+    pub remaining_time: Option<f64>,
+}
+
+impl Progress {
+    pub fn percentage(&self) -> f64 {
+        if self.lines == 0 {
+            return 100.0;
+        }
+
+        return 100.0 * self.acknowledged as f64 / self.lines as f64;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Control {
+    Pause,
+    Resume,
+    Abort,
+}
+
+pub struct Job {
+    name: String,
+
+    control: mpsc::UnboundedSender<Control>,
+    progress: watch::Receiver<Progress>,
+}
+
+impl Job {
+    pub fn start(name: &str,
+                 program: &str,
+                 estimate: Option<Estimate>,
+                 controller: &controller::Controller) -> (Self, impl Future<Item=(), Error=()>) {
+        let lines: Vec<(usize, String)> = program.lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, strip_comments(line)))
+            .filter(|(_, line)| !line.is_empty() && line != "%")
+            .collect();
+
+        let progress = Progress {
+            status: Status::Running,
+            lines: lines.len(),
+            sent: 0,
+            acknowledged: 0,
+            current_line: None,
+            total_time: estimate.as_ref().map(|estimate| estimate.total),
+            remaining_time: estimate.as_ref().map(|estimate| estimate.total),
+        };
+
+        let (control_sender, control_receiver) = mpsc::unbounded();
+        let (progress_sender, progress_receiver) = watch::channel(progress.clone());
+
+        let runner = Runner {
+            lines: lines.into_iter(),
+            pending: VecDeque::new(),
+            sender: controller.sender(),
+            state: Some(controller.state()),
+            control: control_receiver.fuse(),
+            estimate,
+            progress: progress.clone(),
+            published: progress,
+            watch: progress_sender,
+            aborting: None,
+        };
+
+        return (Self {
+            name: name.to_owned(),
+            control: control_sender,
+            progress: progress_receiver,
+        }, runner);
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn progress(&self) -> Progress {
+        return (*self.progress.get_ref()).clone();
+    }
+
+    pub fn updates(&self) -> impl Stream<Item=Progress, Error=()> {
+        return self.progress.clone()
+            .map_err(|_| ());
+    }
+
+    // Control requests are ignored if the job has already finished
+    pub fn pause(&self) {
+        let _ = self.control.unbounded_send(Control::Pause);
+    }
+
+    pub fn resume(&self) {
+        let _ = self.control.unbounded_send(Control::Resume);
+    }
+
+    pub fn abort(&self) {
+        let _ = self.control.unbounded_send(Control::Abort);
+    }
+}
+
+// Removes comments to save space in the controller's line buffer
+fn strip_comments(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut position = 0;
+
+    for token in Lexer::new(line) {
+        match token {
+            Ok(ref token) => if let TokenKind::Comment { .. } = token.kind {
+                stripped.push_str(&line[position..token.span.start]);
+                position = token.span.end;
+            },
+
+            // Leave it to the controller to complain about the line
+            Err(_) => return line.trim().to_owned(),
+        }
+    }
+
+    stripped.push_str(&line[position..]);
+
+    return stripped.trim().to_owned();
+}
+
+struct Runner {
+    // Lines not yet sent and lines waiting for the controller's response
+    lines: std::vec::IntoIter<(usize, String)>,
+    pending: VecDeque<(usize, Box<Future<Item=controller::Response, Error=controller::Canceled> + Send>)>,
+
+    sender: Box<controller::Sender + Send>,
+    state: Option<Box<Stream<Item=controller::State, Error=()> + Send>>,
+
+    control: stream::Fuse<mpsc::UnboundedReceiver<Control>>,
+
+    estimate: Option<Estimate>,
+
+    progress: Progress,
+    published: Progress,
+    watch: watch::Sender<Progress>,
+
+    // Waits for the machine to stop moving after the job got aborted
+    aborting: Option<Box<Future<Item=(), Error=()> + Send>>,
+}
+
+impl Runner {
+    fn stop(&mut self, status: Status) {
+        self.progress.status = status;
+
+        self.lines = Vec::new().into_iter();
+        self.pending.clear();
+
+        self.sender.send_realtime(controller::RealtimeCommand::FeedHold);
+
+        let state = self.state.take()
+            .expect("Job stopped twice");
+
+        let stopped = state
+            .skip_while(|state| Ok(match state.status {
+                controller::MachineStatus::Run |
+                controller::MachineStatus::Jog |
+                controller::MachineStatus::Hold(controller::HoldStatus::InProgress) => true,
+                _ => false,
+            }))
+            .into_future()
+            .map(|_| ())
+            .map_err(|_| ());
+
+        self.aborting = Some(Box::new(Timeout::new(stopped, HOLD_TIMEOUT)
+            .then(|_| Ok(()))));
+    }
+
+    // Gives up on the job without stopping the machine, which can not be talked to anymore
+    fn interrupt(&mut self) {
+        log::warn!("Job interrupted after line {:?}", self.progress.current_line);
+
+        self.progress.status = Status::Interrupted { line: self.progress.current_line };
+
+        self.lines = Vec::new().into_iter();
+        self.pending.clear();
+    }
+
+    fn publish(&mut self) {
+        if self.progress != self.published {
+            self.published = self.progress.clone();
+            let _ = self.watch.broadcast(self.progress.clone());
+        }
+    }
+
+    fn run(&mut self) -> Result<Async<()>, ()> {
+        if let Some(ref mut aborting) = self.aborting {
+            try_ready!(aborting.poll());
+
+            // Resetting the machine after it stopped moving keeps the machine position
+            self.sender.send_realtime(controller::RealtimeCommand::SoftReset);
+
+            return Ok(Async::Ready(()));
+        }
+
+        while let Async::Ready(Some(control)) = self.control.poll()? {
+            match control {
+                Control::Pause => if self.progress.status == Status::Running {
+                    self.sender.send_realtime(controller::RealtimeCommand::FeedHold);
+                    self.progress.status = Status::Paused;
+                },
+
+                Control::Resume => if self.progress.status == Status::Paused {
+                    self.sender.send_realtime(controller::RealtimeCommand::CycleStart);
+                    self.progress.status = Status::Running;
+                },
+
+                Control::Abort => {
+                    self.stop(Status::Aborted);
+                    return self.run();
+                }
+            }
+        }
+
+        loop {
+            while self.pending.len() < WINDOW {
+                let (line, text) = match self.lines.next() {
+                    Some(line) => line,
+                    None => break,
+                };
+
+                self.pending.push_back((line, self.sender.send_line(&text)));
+                self.progress.sent += 1;
+            }
+
+            // Responses arrive in the same order the lines were sent
+            let (line, response) = match self.pending.front_mut() {
+                Some((line, response)) => (*line, response.poll()),
+                None => break,
+            };
+
+            let response = match response {
+                Ok(Async::Ready(response)) => response,
+                Ok(Async::NotReady) => break,
+
+                // Lines are dropped unanswered if the connection to the controller got lost
+                Err(_) => {
+                    self.interrupt();
+                    return Ok(Async::Ready(()));
+                }
+            };
+
+            self.pending.pop_front();
+
+            match response {
+                controller::Response::Ok => {
+                    self.progress.acknowledged += 1;
+                    self.progress.current_line = Some(line);
+                    self.progress.remaining_time = self.estimate.as_ref()
+                        .map(|estimate| estimate.remaining(line + 1));
+                }
+
+                controller::Response::Error(error) => {
+                    log::warn!("Job failed in line {}: {}", line, error);
+
+                    self.stop(Status::Failed { line, error });
+                    return self.run();
+                }
+            }
+        }
+
+        if self.pending.is_empty() && self.lines.len() == 0 {
+            self.progress.status = Status::Completed;
+            self.progress.remaining_time = self.progress.total_time.map(|_| 0.0);
+            return Ok(Async::Ready(()));
+        }
+
+        return Ok(Async::NotReady);
+    }
+}
+
+impl Future for Runner {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        let result = self.run();
+        self.publish();
+
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(strip_comments("G0 X1 (rapid) Y2 ; move"), "G0 X1  Y2");
+        assert_eq!(strip_comments("  (only a comment)  "), "");
+        assert_eq!(strip_comments("G1 X1 (unterminated"), "G1 X1 (unterminated");
+    }
+}
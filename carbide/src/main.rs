@@ -15,6 +15,7 @@ use crate::controller::Controller;
 use std::io::BufReader;
 
 mod controller;
+mod job;
 mod server;
 mod config;
 mod position;
@@ -28,11 +29,20 @@ fn main() -> Result<(), Error> {
 
     let mut runtime = tokio::runtime::Runtime::new()?;
 
-    let (controller, driver) = match config.controller {
-        config::ControllerConfig::GRBL(config) => controller::grbl::GrblController::new(&config)?,
+    // The drivers of the real and the simulated controller are of different types
+    let (controller, driver): (_, Box<Future<Item=(), Error=Error> + Send>) = match config.controller {
+        config::ControllerConfig::GRBL(config) => {
+            let (controller, driver) = controller::grbl::GrblController::new(&config)?;
+            (controller, Box::new(driver))
+        }
+        config::ControllerConfig::Simulator(config) => {
+            let (controller, driver) = controller::grbl::GrblController::simulated(&config)?;
+            (controller, Box::new(driver))
+        }
     };
 
-    let driver = driver.map_err(|err| panic!(err));
+    // Keep serving the API after the controller failed so clients can learn why
+    let driver = driver.map_err(|err| log::error!("Controller failed: {}", err));
 
     let controller = Arc::new(Mutex::new(controller));
 
@@ -50,7 +60,9 @@ fn main() -> Result<(), Error> {
         });
     runtime.spawn(stdin);
 
-    let server = server::serve(&config.server, controller.clone());
+    let library = Arc::new(job::library::Library::new(&config.jobs.path)?);
+
+    let server = server::serve(&config.server, controller.clone(), library);
     runtime.spawn(server);
 
     runtime.block_on_all(driver);
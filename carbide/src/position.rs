@@ -90,3 +90,19 @@ impl Into<(f64, f64, f64)> for Position {
         return (self.x, self.y, self.z);
     }
 }
+
+impl From<carbide_gcode::Point> for Position {
+    fn from(p: carbide_gcode::Point) -> Self {
+        return Self {
+            x: p.x,
+            y: p.y,
+            z: p.z,
+        };
+    }
+}
+
+impl From<Position> for carbide_gcode::Point {
+    fn from(p: Position) -> Self {
+        return carbide_gcode::Point::new(p.x, p.y, p.z);
+    }
+}
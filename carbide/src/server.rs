@@ -1,17 +1,34 @@
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use bytes::Buf;
 use failure::Error;
 use futures::Future;
+use futures::future;
 use futures::sink::Sink;
+use futures::sync::mpsc;
+use futures::stream;
 use futures::stream::Stream;
-use serde_derive::Serialize;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use warp::{self, Filter, Rejection, Reply};
+use warp::http::StatusCode;
+use warp::http::header::{HeaderValue, CONTENT_TYPE};
 
 use crate::config::ServerConfig;
 use crate::controller;
+use crate::job::library::{self, Library, LibraryError};
+use crate::job::preflight::Preflight;
+use crate::job::runner::{self, Job};
 use crate::position::Position;
 
+// Upper limit for uploaded programs
+const MAX_PROGRAM_SIZE: u64 = 64 * 1024 * 1024;
+
+// Upper limit for imported settings backups
+const MAX_SETTINGS_SIZE: u64 = 64 * 1024;
+
 #[derive(Debug, Clone, Serialize)]
 pub enum ControllerType {
     Grbl,
@@ -24,6 +41,33 @@ pub struct Info {
     description: String,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub enum ConnectionStatus {
+    Connecting,
+    Ready,
+    Failed,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Connection {
+    pub status: ConnectionStatus,
+
+    // Reason the connection failed or got lost
+    pub error: Option<String>,
+}
+
+impl From<controller::Connection> for Connection {
+    fn from(connection: controller::Connection) -> Self {
+        return match connection {
+            controller::Connection::Connecting => Connection { status: ConnectionStatus::Connecting, error: None },
+            controller::Connection::Ready => Connection { status: ConnectionStatus::Ready, error: None },
+            controller::Connection::Failed(error) => Connection { status: ConnectionStatus::Failed, error: Some(error) },
+            controller::Connection::Disconnected(error) => Connection { status: ConnectionStatus::Disconnected, error: Some(error) },
+        };
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub enum MachineStatus {
     Idle,
@@ -39,6 +83,7 @@ pub enum MachineStatus {
     Check,
     Home,
     Sleep,
+    Unknown(String),
 }
 
 impl From<controller::MachineStatus> for MachineStatus {
@@ -61,28 +106,672 @@ impl From<controller::MachineStatus> for MachineStatus {
             controller::MachineStatus::Check => MachineStatus::Check,
             controller::MachineStatus::Home => MachineStatus::Home,
             controller::MachineStatus::Sleep => MachineStatus::Sleep,
+            controller::MachineStatus::Unknown(state) => MachineStatus::Unknown(state),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BufferState {
+    pub planner: usize,
+    pub rx: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputPins {
+    pub x_limit: bool,
+    pub y_limit: bool,
+    pub z_limit: bool,
+    pub probe: bool,
+    pub door: bool,
+    pub hold: bool,
+    pub soft_reset: bool,
+    pub cycle_start: bool,
+}
+
+impl From<controller::InputPins> for InputPins {
+    fn from(pins: controller::InputPins) -> Self {
+        return InputPins {
+            x_limit: pins.x_limit,
+            y_limit: pins.y_limit,
+            z_limit: pins.z_limit,
+            probe: pins.probe,
+            door: pins.door,
+            hold: pins.hold,
+            soft_reset: pins.soft_reset,
+            cycle_start: pins.cycle_start,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Overrides {
+    pub feed: f64,
+    pub rapids: f64,
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum SpindleState {
+    Off,
+    Clockwise,
+    CounterClockwise,
+}
+
+impl From<controller::SpindleState> for SpindleState {
+    fn from(state: controller::SpindleState) -> Self {
+        return match state {
+            controller::SpindleState::Off => SpindleState::Off,
+            controller::SpindleState::Clockwise => SpindleState::Clockwise,
+            controller::SpindleState::CounterClockwise => SpindleState::CounterClockwise,
+        };
+    }
+}
+
+// Modal state reported as the g-code words selecting each mode
+#[derive(Debug, Clone, Serialize)]
+pub struct ModalState {
+    pub motion: &'static str,
+    pub coordinate_system: &'static str,
+    pub plane: &'static str,
+    pub units: &'static str,
+    pub distance: &'static str,
+    pub feed_mode: &'static str,
+
+    pub spindle: SpindleState,
+    pub flood_coolant: bool,
+    pub mist_coolant: bool,
+
+    pub tool: u32,
+
+    // Feed rate in mm/min and spindle speed in RPM
+    pub feed: f64,
+    pub speed: f64,
+}
+
+impl From<controller::ModalState> for ModalState {
+    fn from(state: controller::ModalState) -> Self {
+        return ModalState {
+            motion: match state.motion {
+                controller::MotionMode::Rapid => "G0",
+                controller::MotionMode::Linear => "G1",
+                controller::MotionMode::ClockwiseArc => "G2",
+                controller::MotionMode::CounterClockwiseArc => "G3",
+                controller::MotionMode::ProbeToward => "G38.2",
+                controller::MotionMode::ProbeTowardNoError => "G38.3",
+                controller::MotionMode::ProbeAway => "G38.4",
+                controller::MotionMode::ProbeAwayNoError => "G38.5",
+                controller::MotionMode::None => "G80",
+            },
+            coordinate_system: ["G54", "G55", "G56", "G57", "G58", "G59"].get(state.coordinate_system)
+                .cloned()
+                .unwrap_or("G54"),
+            plane: match state.plane {
+                controller::Plane::XY => "G17",
+                controller::Plane::ZX => "G18",
+                controller::Plane::YZ => "G19",
+            },
+            units: match state.units {
+                controller::Units::Inches => "G20",
+                controller::Units::Millimeters => "G21",
+            },
+            distance: match state.distance {
+                controller::DistanceMode::Absolute => "G90",
+                controller::DistanceMode::Incremental => "G91",
+            },
+            feed_mode: match state.feed_mode {
+                controller::FeedMode::InverseTime => "G93",
+                controller::FeedMode::UnitsPerMinute => "G94",
+            },
+            spindle: state.spindle.into(),
+            flood_coolant: state.flood_coolant,
+            mist_coolant: state.mist_coolant,
+            tool: state.tool,
+            feed: state.feed,
+            speed: state.speed,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub version: Option<String>,
+
+    pub variable_spindle: bool,
+    pub laser_mode: bool,
+    pub line_numbers: bool,
+    pub mist_coolant: bool,
+    pub core_xy: bool,
+    pub parking: bool,
+    pub safety_door: bool,
+    pub homing_force_origin: bool,
+    pub homing_single_axis: bool,
+
+    pub options: Vec<String>,
+
+    pub planner_blocks: Option<usize>,
+    pub rx_buffer: Option<usize>,
+}
+
+impl From<controller::Capabilities> for Capabilities {
+    fn from(capabilities: controller::Capabilities) -> Self {
+        return Capabilities {
+            version: capabilities.version,
+            variable_spindle: capabilities.variable_spindle,
+            laser_mode: capabilities.laser_mode,
+            line_numbers: capabilities.line_numbers,
+            mist_coolant: capabilities.mist_coolant,
+            core_xy: capabilities.core_xy,
+            parking: capabilities.parking,
+            safety_door: capabilities.safety_door,
+            homing_force_origin: capabilities.homing_force_origin,
+            homing_single_axis: capabilities.homing_single_axis,
+            options: capabilities.options,
+            planner_blocks: capabilities.planner_blocks,
+            rx_buffer: capabilities.rx_buffer,
         };
     }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ControllerState {
+    pub connection: Connection,
+
     pub status: MachineStatus,
 
     pub machine_position: (f64, f64, f64),
     pub work_position: (f64, f64, f64),
+
+    pub buffer: Option<BufferState>,
+    pub line: Option<usize>,
+
+    // Feed rate in mm/min and spindle speed in RPM
+    pub feed: f64,
+    pub speed: f64,
+
+    pub input_pins: InputPins,
+    pub overrides: Overrides,
+
+    pub spindle: SpindleState,
+    pub flood_coolant: bool,
+    pub mist_coolant: bool,
+
+    pub alarm: Option<Alarm>,
+
+    pub modal: ModalState,
+
+    pub capabilities: Capabilities,
 }
 
 impl From<controller::State> for ControllerState {
     fn from(state: controller::State) -> Self {
         return ControllerState {
+            connection: state.connection.into(),
             status: state.status.into(),
             machine_position: state.machine_position.into(),
             work_position: state.work_position.into(),
+            buffer: state.buffer.map(|buffer| BufferState {
+                planner: buffer.planner,
+                rx: buffer.rx,
+            }),
+            line: state.line,
+            feed: state.feed,
+            speed: state.speed,
+            input_pins: state.input_pins.into(),
+            overrides: Overrides {
+                feed: state.overrides.feed,
+                rapids: state.overrides.rapids,
+                speed: state.overrides.speed,
+            },
+            spindle: state.accessories.spindle.into(),
+            flood_coolant: state.accessories.flood_coolant,
+            mist_coolant: state.accessories.mist_coolant,
+            alarm: state.alarm.map(Alarm::from),
+            modal: state.modal.into(),
+            capabilities: state.capabilities.into(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Bounds {
+    pub min: (f64, f64, f64),
+    pub max: (f64, f64, f64),
+}
+
+impl From<carbide_gcode::BoundingBox> for Bounds {
+    fn from(bounds: carbide_gcode::BoundingBox) -> Self {
+        return Bounds {
+            min: bounds.min.into(),
+            max: bounds.max.into(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(source: &str, diagnostic: &carbide_gcode::Diagnostic) -> Self {
+        let (line, column) = diagnostic.span.location(source);
+
+        return Diagnostic {
+            severity: match diagnostic.severity {
+                carbide_gcode::Severity::Warning => Severity::Warning,
+                carbide_gcode::Severity::Error => Severity::Error,
+            },
+            line,
+            column,
+            message: diagnostic.kind.to_string(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitViolation {
+    pub line: usize,
+    pub axis: char,
+    pub position: f64,
+    pub limit: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreflightReport {
+    pub ok: bool,
+
+    pub bounds: Option<Bounds>,
+    pub envelope: Option<Bounds>,
+
+    pub violations: Vec<LimitViolation>,
+    pub diagnostics: Vec<Diagnostic>,
+
+    // Estimated run time in seconds
+    pub duration: Option<f64>,
+}
+
+impl PreflightReport {
+    pub fn new(source: &str, preflight: &Preflight) -> Self {
+        return PreflightReport {
+            ok: preflight.is_ok(),
+            bounds: preflight.bounds.map(Bounds::from),
+            envelope: preflight.envelope.map(Bounds::from),
+            violations: preflight.violations.iter()
+                .map(|violation| LimitViolation {
+                    line: violation.line,
+                    axis: violation.axis.letter(),
+                    position: violation.position,
+                    limit: violation.limit,
+                })
+                .collect(),
+            diagnostics: preflight.diagnostics.iter()
+                .map(|diagnostic| Diagnostic::new(source, diagnostic))
+                .collect(),
+            duration: preflight.estimate.as_ref().map(|estimate| estimate.total),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Fault {
+    pub code: Option<u8>,
+    pub name: String,
+    pub description: String,
+}
+
+impl From<controller::Fault> for Fault {
+    fn from(fault: controller::Fault) -> Self {
+        return Fault {
+            code: fault.code,
+            name: fault.name,
+            description: fault.description,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alarm {
+    pub code: Option<u8>,
+    pub name: String,
+    pub description: String,
+
+    // Milliseconds since the epoch
+    pub timestamp: u64,
+}
+
+impl From<controller::Alarm> for Alarm {
+    fn from(alarm: controller::Alarm) -> Self {
+        return Alarm {
+            code: alarm.fault.code,
+            name: alarm.fault.name,
+            description: alarm.fault.description,
+            timestamp: timestamp(alarm.timestamp),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorMessage {
+    pub message: String,
+}
+
+impl ErrorMessage {
+    pub fn new(message: &str) -> Self {
+        return ErrorMessage {
+            message: message.to_owned(),
+        };
+    }
+}
+
+// Replies with differently typed bodies must share one type, so the body is serialized right away
+type JsonReply = warp::http::Response<Vec<u8>>;
+
+fn json_reply<T>(body: &T, status: StatusCode) -> JsonReply
+    where T: Serialize {
+    let (body, status) = match serde_json::to_vec(body) {
+        Ok(body) => (body, status),
+        Err(err) => {
+            log::error!("Failed to serialize reply: {}", err);
+            (Vec::new(), StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+
+    let mut reply = warp::http::Response::new(body);
+    *reply.status_mut() = status;
+    reply.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    return reply;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum JobStatus {
+    Running,
+    Paused,
+    Completed,
+    Aborted,
+    Failed,
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobError {
+    pub line: usize,
+    pub code: Option<u8>,
+    pub message: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobProgress {
+    pub name: String,
+
+    pub status: JobStatus,
+    pub error: Option<JobError>,
+
+    pub lines: usize,
+    pub sent: usize,
+    pub acknowledged: usize,
+    pub percentage: f64,
+    pub current_line: Option<usize>,
+
+    // Estimated times in seconds
+    pub total_time: Option<f64>,
+    pub remaining_time: Option<f64>,
+}
+
+impl JobProgress {
+    pub fn new(name: &str, progress: &runner::Progress) -> Self {
+        let (status, error) = match progress.status {
+            runner::Status::Running => (JobStatus::Running, None),
+            runner::Status::Paused => (JobStatus::Paused, None),
+            runner::Status::Completed => (JobStatus::Completed, None),
+            runner::Status::Aborted => (JobStatus::Aborted, None),
+            runner::Status::Interrupted { .. } => (JobStatus::Interrupted, None),
+            runner::Status::Failed { line, ref error } => (JobStatus::Failed, Some(JobError {
+                line,
+                code: error.code,
+                message: error.name.clone(),
+                description: error.description.clone(),
+            })),
+        };
+
+        return JobProgress {
+            name: name.to_owned(),
+            status,
+            error,
+            lines: progress.lines,
+            sent: progress.sent,
+            acknowledged: progress.acknowledged,
+            percentage: progress.percentage(),
+            current_line: progress.current_line,
+            total_time: progress.total_time,
+            remaining_time: progress.remaining_time,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvalidProgram {
+    pub message: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileInfo {
+    pub name: String,
+
+    pub size: u64,
+    pub lines: usize,
+
+    // Bounding box in work coordinates
+    pub bounds: Option<Bounds>,
+
+    // Estimated run time in seconds
+    pub duration: Option<f64>,
+}
+
+impl FileInfo {
+    pub fn new(name: &str, metadata: &library::Metadata) -> Self {
+        return FileInfo {
+            name: name.to_owned(),
+            size: metadata.size,
+            lines: metadata.lines,
+            bounds: metadata.bounds.map(Bounds::from),
+            duration: metadata.duration,
+        };
+    }
+}
+
+fn library_error(err: LibraryError) -> JsonReply {
+    let status = match err {
+        LibraryError::InvalidName => StatusCode::BAD_REQUEST,
+        LibraryError::NotFound => StatusCode::NOT_FOUND,
+        LibraryError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        LibraryError::Io(ref err) => {
+            log::error!("Job library failed: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+
+    return json_reply(&ErrorMessage::new(&err.to_string()), status);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum ConsoleDirection {
+    Sent,
+    Received,
+
+    // Response to a line sent by the client
+    Response,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsoleMessage {
+    pub direction: ConsoleDirection,
+
+    // Milliseconds since the epoch
+    pub timestamp: u64,
+
+    pub text: String,
+}
+
+fn timestamp(time: SystemTime) -> u64 {
+    return time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() * 1000 + u64::from(duration.subsec_millis()))
+        .unwrap_or(0);
+}
+
+impl From<controller::ConsoleMessage> for ConsoleMessage {
+    fn from(message: controller::ConsoleMessage) -> Self {
+        return ConsoleMessage {
+            direction: match message.direction {
+                controller::Direction::Sent => ConsoleDirection::Sent,
+                controller::Direction::Received => ConsoleDirection::Received,
+            },
+            timestamp: timestamp(message.timestamp),
+            text: message.text,
+        };
+    }
+}
+
+impl ConsoleMessage {
+    pub fn response(response: Result<controller::Response, controller::Canceled>) -> Self {
+        let text = match response {
+            Ok(controller::Response::Ok) => "ok".to_owned(),
+            Ok(controller::Response::Error(error)) => format!("error: {}", error),
+            Err(_) => "canceled".to_owned(),
+        };
+
+        return ConsoleMessage {
+            direction: ConsoleDirection::Response,
+            timestamp: timestamp(SystemTime::now()),
+            text,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Probe {
+    pub position: (f64, f64, f64),
+    pub success: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Parameters {
+    // Offsets of G54 to G59
+    pub coordinate_systems: Vec<(f64, f64, f64)>,
+
+    pub home: (f64, f64, f64),
+    pub secondary_home: (f64, f64, f64),
+    pub offset: (f64, f64, f64),
+    pub tool_length_offset: f64,
+
+    pub probe: Option<Probe>,
+}
+
+impl From<controller::Parameters> for Parameters {
+    fn from(parameters: controller::Parameters) -> Self {
+        return Parameters {
+            coordinate_systems: parameters.coordinate_systems.iter()
+                .map(|&offset| offset.into())
+                .collect(),
+            home: parameters.home.into(),
+            secondary_home: parameters.secondary_home.into(),
+            offset: parameters.offset.into(),
+            tool_length_offset: parameters.tool_length_offset,
+            probe: parameters.probe.map(|probe| Probe {
+                position: probe.position.into(),
+                success: probe.success,
+            }),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Setting {
+    pub code: u8,
+    pub name: String,
+    pub unit: String,
+    pub description: String,
+    pub value: f64,
+}
+
+impl From<controller::Setting> for Setting {
+    fn from(setting: controller::Setting) -> Self {
+        return Setting {
+            code: setting.code,
+            name: setting.name,
+            unit: setting.unit,
+            description: setting.description,
+            value: setting.value,
         };
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct SettingValue {
+    pub value: f64,
+}
+
+// Entry of an exported settings backup. The name is informational and ignored on import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingBackup {
+    pub code: u8,
+    #[serde(default)]
+    pub name: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingFailure {
+    pub code: u8,
+    pub error: Fault,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ImportReport {
+    pub written: Vec<u8>,
+    pub unchanged: Vec<u8>,
+    pub failed: Vec<SettingFailure>,
+}
+
+// Realtime command, which the controller acts on right away instead of queueing it behind other commands
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RealtimeRequest {
+    FeedHold,
+    Resume,
+    SafetyDoor,
+    JogCancel,
+    SpindleStop,
+    FloodCoolant,
+    MistCoolant,
+
+    FeedOverride { percent: f64 },
+
+    // Either 100, 50 or 25
+    RapidOverride { percent: u8 },
+
+    SpeedOverride { percent: f64 },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Offset {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
 fn info(controller: Arc<Mutex<controller::Controller>>) -> impl warp::Reply {
     let controller = controller.lock().unwrap();
 
@@ -114,9 +803,506 @@ fn state(controller: Arc<Mutex<controller::Controller>>, ws: warp::ws::Ws2) -> i
     });
 }
 
+fn preflight(controller: Arc<Mutex<controller::Controller>>, body: warp::body::FullBody) -> impl warp::Reply {
+    let source = String::from_utf8_lossy(body.bytes());
+    let program = carbide_gcode::parse(&source);
+
+    let state = controller.lock().unwrap().current_state();
+    let preflight = Preflight::check(&program, &state);
+
+    return warp::reply::json(&PreflightReport::new(&source, &preflight));
+}
+
+fn run_job(controller: Arc<Mutex<controller::Controller>>,
+           jobs: Arc<Mutex<Option<Job>>>,
+           name: &str,
+           source: &str) -> JsonReply {
+    let mut job = jobs.lock().unwrap();
+
+    if let Some(ref job) = *job {
+        if !job.progress().status.is_finished() {
+            return json_reply(&ErrorMessage::new("Another job is running"), StatusCode::CONFLICT);
+        }
+    }
+
+    let controller = controller.lock().unwrap();
+    let state = controller.current_state();
+
+    if state.connection != controller::Connection::Ready {
+        return json_reply(&ErrorMessage::new("Controller is not connected"), StatusCode::CONFLICT);
+    }
+
+    // The controller would reject every line while locked
+    if let controller::MachineStatus::Alarm = state.status {
+        let message = match state.alarm {
+            Some(alarm) => format!("Machine is locked by an alarm: {}", alarm.fault),
+            None => "Machine is locked by an alarm".to_owned(),
+        };
+
+        return json_reply(&ErrorMessage::new(&message), StatusCode::CONFLICT);
+    }
+
+    // Refuse to start jobs which would fail or hit the machine limits on the way
+    let program = carbide_gcode::parse(source);
+    let preflight = Preflight::check(&program, &state);
+    if !preflight.is_ok() {
+        return json_reply(&PreflightReport::new(source, &preflight), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let (started, runner) = Job::start(name, source, preflight.estimate, &*controller);
+    tokio::spawn(runner);
+
+    let progress = JobProgress::new(started.name(), &started.progress());
+    *job = Some(started);
+
+    return json_reply(&progress, StatusCode::OK);
+}
+
+fn start_job(controller: Arc<Mutex<controller::Controller>>,
+             jobs: Arc<Mutex<Option<Job>>>,
+             body: warp::body::FullBody) -> impl warp::Reply {
+    let source = String::from_utf8_lossy(body.bytes());
+
+    return run_job(controller, jobs, "upload", &source);
+}
+
+fn console(controller: Arc<Mutex<controller::Controller>>, ws: warp::ws::Ws2) -> impl warp::Reply {
+    return ws.on_upgrade(move |socket| {
+        let (sink, stream) = socket.split();
+
+        let (sender, traffic) = {
+            let controller = controller.lock().unwrap();
+            (controller.sender(), controller.console())
+        };
+
+        // Send lines received from the client one after another and report back each response
+        let (responses, responses_receiver) = mpsc::unbounded();
+        let requests = stream
+            .map_err(|err| log::warn!("Socket failed: {}", err))
+            .filter_map(|message| message.to_str().ok().map(str::to_owned))
+            // A pasted block comes in a single message, but each line is acknowledged on its own
+            .map(|message| futures::stream::iter_ok::<_, ()>(message.split(|c| c == '\r' || c == '\n')
+                .map(str::trim_end)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect::<Vec<_>>()))
+            .flatten()
+            .for_each(move |line| {
+                let responses = responses.clone();
+
+                return sender.send_line(&line)
+                    .then(move |response| {
+                        let _ = responses.unbounded_send(ConsoleMessage::response(response));
+                        return Ok(());
+                    });
+            });
+
+        let messages = traffic
+            .map(ConsoleMessage::from)
+            .select(responses_receiver)
+            .map(|message| {
+                let message = serde_json::to_string(&message).unwrap();
+
+                return warp::ws::Message::text(message);
+            });
+
+        let writer = sink
+            .sink_map_err(|err| log::warn!("Socket closed: {}", err) )
+            .send_all(messages)
+            .map(|_| ());
+
+        return writer.select(requests)
+            .map(|_| ())
+            .map_err(|_| ());
+    });
+}
+
+fn parameters(controller: Arc<Mutex<controller::Controller>>) -> impl warp::Reply {
+    let parameters: Parameters = controller.lock().unwrap().parameters().into();
+
+    return warp::reply::json(&parameters);
+}
+
+fn set_coordinate_system(system: String,
+                         controller: Arc<Mutex<controller::Controller>>,
+                         jobs: Arc<Mutex<Option<Job>>>,
+                         offset: Offset) -> impl Future<Item=JsonReply, Error=Rejection> {
+    let index = match system.as_str() {
+        "G54" => 0,
+        "G55" => 1,
+        "G56" => 2,
+        "G57" => 3,
+        "G58" => 4,
+        "G59" => 5,
+        _ => return future::Either::A(future::err(warp::reject::not_found())),
+    };
+
+    // The line would end up between the lines of the job
+    let running = jobs.lock().unwrap().as_ref()
+        .map_or(false, |job| !job.progress().status.is_finished());
+    if running {
+        return future::Either::A(future::ok(json_reply(&ErrorMessage::new("A job is running"), StatusCode::CONFLICT)));
+    }
+
+    let response = controller.lock().unwrap()
+        .set_coordinate_system(index, Position { x: offset.x, y: offset.y, z: offset.z });
+
+    return future::Either::B(response.then(|response| Ok(response_reply(response))));
+}
+
+fn settings(controller: Arc<Mutex<controller::Controller>>) -> impl warp::Reply {
+    let settings: Vec<Setting> = controller.lock().unwrap().settings().into_iter()
+        .map(Setting::from)
+        .collect();
+
+    return warp::reply::json(&settings);
+}
+
+fn write_setting(code: u8,
+                 controller: Arc<Mutex<controller::Controller>>,
+                 setting: SettingValue) -> impl Future<Item=JsonReply, Error=Rejection> {
+    let response = controller.lock().unwrap()
+        .write_setting(code, setting.value);
+
+    return response.then(|response| Ok(response_reply(response)));
+}
+
+fn export_settings(format: String, controller: Arc<Mutex<controller::Controller>>) -> Result<impl warp::Reply, Rejection> {
+    let backup: Vec<SettingBackup> = controller.lock().unwrap().settings().into_iter()
+        .map(|setting| SettingBackup {
+            code: setting.code,
+            name: setting.name,
+            value: setting.value,
+        })
+        .collect();
+
+    let (content, content_type) = match format.as_str() {
+        "json" => (serde_json::to_string_pretty(&backup).unwrap(), "application/json"),
+        "yaml" => (serde_yaml::to_string(&backup).unwrap(), "application/x-yaml"),
+        _ => return Err(warp::reject::not_found()),
+    };
+
+    return Ok(warp::reply::with_header(content, "content-type", content_type));
+}
+
+fn import_settings(format: String,
+                   controller: Arc<Mutex<controller::Controller>>,
+                   body: warp::body::FullBody) -> impl Future<Item=JsonReply, Error=Rejection> {
+    let backup: Result<Vec<SettingBackup>, String> = match format.as_str() {
+        "json" => serde_json::from_slice(body.bytes()).map_err(|err| err.to_string()),
+        "yaml" => serde_yaml::from_slice(body.bytes()).map_err(|err| err.to_string()),
+        _ => return future::Either::A(future::err(warp::reject::not_found())),
+    };
+
+    let backup = match backup {
+        Ok(backup) => backup,
+        Err(err) => return future::Either::A(future::ok(json_reply(&ErrorMessage::new(&format!("Invalid backup: {}", err)),
+                                                                   StatusCode::BAD_REQUEST))),
+    };
+
+    // Skip settings already having the desired value to spare the controller's EEPROM
+    let current = controller.lock().unwrap().settings();
+    let (unchanged, changed): (Vec<SettingBackup>, Vec<SettingBackup>) = backup.into_iter()
+        .partition(|entry| current.iter()
+            .any(|setting| setting.code == entry.code && (setting.value - entry.value).abs() < 0.001));
+
+    let report = ImportReport {
+        unchanged: unchanged.iter().map(|entry| entry.code).collect(),
+        ..ImportReport::default()
+    };
+
+    // Write one setting after the other, each confirmed by reading it back
+    let import = stream::iter_ok::<_, Rejection>(changed)
+        .fold(report, move |mut report, entry| {
+            return controller.lock().unwrap()
+                .write_setting(entry.code, entry.value)
+                .then(move |response| {
+                    match response {
+                        Ok(controller::Response::Ok) => report.written.push(entry.code),
+                        Ok(controller::Response::Error(error)) => report.failed.push(SettingFailure {
+                            code: entry.code,
+                            error: error.into(),
+                        }),
+                        Err(_) => report.failed.push(SettingFailure {
+                            code: entry.code,
+                            error: controller::Fault::new("Command discarded by the controller").into(),
+                        }),
+                    }
+
+                    return Ok::<_, Rejection>(report);
+                });
+        });
+
+    return future::Either::B(import.map(|report| {
+        let status = if report.failed.is_empty() { StatusCode::OK } else { StatusCode::UNPROCESSABLE_ENTITY };
+        return json_reply(&report, status);
+    }));
+}
+
+fn response_reply(response: Result<controller::Response, controller::Canceled>) -> JsonReply {
+    return match response {
+        Ok(controller::Response::Ok) => json_reply(&(), StatusCode::OK),
+        Ok(controller::Response::Error(error)) => json_reply(&Fault::from(error), StatusCode::UNPROCESSABLE_ENTITY),
+        Err(_) => json_reply(&ErrorMessage::new("Command discarded by the controller"), StatusCode::SERVICE_UNAVAILABLE),
+    };
+}
+
+fn send_realtime(controller: &Mutex<controller::Controller>,
+                 jobs: &Mutex<Option<Job>>,
+                 request: RealtimeRequest) -> Result<(), ErrorMessage> {
+    // The job keeps track of being paused, so holding and resuming go through it while it runs
+    if let Some(ref job) = *jobs.lock().unwrap() {
+        if !job.progress().status.is_finished() {
+            match request {
+                RealtimeRequest::FeedHold => {
+                    job.pause();
+                    return Ok(());
+                }
+                RealtimeRequest::Resume => {
+                    job.resume();
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let controller = controller.lock().unwrap();
+
+    let command = match request {
+        RealtimeRequest::FeedHold => controller::RealtimeCommand::FeedHold,
+        RealtimeRequest::Resume => controller::RealtimeCommand::CycleStart,
+        RealtimeRequest::SafetyDoor => controller::RealtimeCommand::SafetyDoor,
+        RealtimeRequest::JogCancel => controller::RealtimeCommand::JogCancel,
+        RealtimeRequest::SpindleStop => controller::RealtimeCommand::ToggleSpindleStop,
+        RealtimeRequest::FloodCoolant => controller::RealtimeCommand::ToggleFloodCoolant,
+        RealtimeRequest::MistCoolant => controller::RealtimeCommand::ToggleMistCoolant,
+
+        RealtimeRequest::FeedOverride { percent } | RealtimeRequest::SpeedOverride { percent } if !percent.is_finite() => {
+            return Err(ErrorMessage::new("Invalid override"));
+        }
+
+        RealtimeRequest::FeedOverride { percent } => {
+            controller.set_feed_override(percent);
+            return Ok(());
+        }
+
+        RealtimeRequest::RapidOverride { percent } => {
+            controller.set_rapid_override(match percent {
+                100 => controller::RapidOverride::Full,
+                50 => controller::RapidOverride::Half,
+                25 => controller::RapidOverride::Quarter,
+                _ => return Err(ErrorMessage::new("Rapid override must be 100, 50 or 25 percent")),
+            });
+            return Ok(());
+        }
+
+        RealtimeRequest::SpeedOverride { percent } => {
+            controller.set_speed_override(percent);
+            return Ok(());
+        }
+    };
+
+    controller.sender().send_realtime(command);
+    return Ok(());
+}
+
+fn realtime(controller: Arc<Mutex<controller::Controller>>,
+            jobs: Arc<Mutex<Option<Job>>>,
+            request: RealtimeRequest) -> JsonReply {
+    return match send_realtime(&controller, &jobs, request) {
+        Ok(()) => json_reply(&(), StatusCode::OK),
+        Err(error) => json_reply(&error, StatusCode::UNPROCESSABLE_ENTITY),
+    };
+}
+
+fn realtime_socket(controller: Arc<Mutex<controller::Controller>>,
+                   jobs: Arc<Mutex<Option<Job>>>,
+                   ws: warp::ws::Ws2) -> impl warp::Reply {
+    return ws.on_upgrade(move |socket| {
+        let (sink, stream) = socket.split();
+
+        // Every message is a request on its own, only failed ones are answered
+        let errors = stream
+            .map_err(|err| log::warn!("Socket failed: {}", err))
+            .filter_map(|message| message.to_str().ok().map(str::to_owned))
+            .filter_map(move |request| {
+                return serde_json::from_str(&request)
+                    .map_err(|err| ErrorMessage::new(&format!("Invalid request: {}", err)))
+                    .and_then(|request| send_realtime(&controller, &jobs, request))
+                    .err();
+            })
+            .map(|error| {
+                let error = serde_json::to_string(&error).unwrap();
+
+                return warp::ws::Message::text(error);
+            });
+
+        return sink
+            .sink_map_err(|err| log::warn!("Socket closed: {}", err) )
+            .send_all(errors)
+            .map(|_| ());
+    });
+}
+
+fn control_machine(action: String,
+                   controller: Arc<Mutex<controller::Controller>>,
+                   jobs: Arc<Mutex<Option<Job>>>) -> impl Future<Item=JsonReply, Error=Rejection> {
+    let running = jobs.lock().unwrap().as_ref()
+        .map_or(false, |job| !job.progress().status.is_finished());
+
+    let controller = controller.lock().unwrap();
+
+    let response = match action.as_str() {
+        // Resetting is always allowed as it is the way to stop the machine in a hurry
+        "reset" => {
+            controller.sender().send_realtime(controller::RealtimeCommand::SoftReset);
+            return future::Either::A(future::ok(json_reply(&(), StatusCode::OK)));
+        }
+
+        "unlock" | "home" if running => {
+            return future::Either::A(future::ok(json_reply(&ErrorMessage::new("A job is running"), StatusCode::CONFLICT)));
+        }
+
+        "unlock" => controller.unlock(),
+        "home" => controller.home(),
+
+        _ => return future::Either::A(future::err(warp::reject::not_found())),
+    };
+
+    return future::Either::B(response.then(|response| Ok(response_reply(response))));
+}
+
+fn list_files(controller: Arc<Mutex<controller::Controller>>, library: Arc<Library>) -> JsonReply {
+    let model = controller.lock().unwrap().current_state().limits.model();
+
+    return match library.list(model.as_ref()) {
+        Ok(entries) => {
+            let files: Vec<FileInfo> = entries.iter()
+                .map(|entry| FileInfo::new(&entry.name, &entry.metadata))
+                .collect();
+
+            json_reply(&files, StatusCode::OK)
+        }
+        Err(err) => library_error(err),
+    };
+}
+
+fn upload_file(name: String,
+               controller: Arc<Mutex<controller::Controller>>,
+               library: Arc<Library>,
+               body: warp::body::FullBody) -> JsonReply {
+    let source = match std::str::from_utf8(body.bytes()) {
+        Ok(source) => source,
+        Err(err) => return json_reply(&ErrorMessage::new(&format!("Program is not valid UTF-8: {}", err)),
+                                      StatusCode::BAD_REQUEST),
+    };
+
+    let model = controller.lock().unwrap().current_state().limits.model();
+
+    return match library.upload(&name, source, model.as_ref()) {
+        Ok(metadata) => json_reply(&FileInfo::new(&name, &metadata), StatusCode::CREATED),
+        Err(LibraryError::Invalid(diagnostics)) => json_reply(&InvalidProgram {
+            message: "Program contains errors".to_owned(),
+            diagnostics: diagnostics.iter()
+                .map(|diagnostic| Diagnostic::new(source, diagnostic))
+                .collect(),
+        }, StatusCode::UNPROCESSABLE_ENTITY),
+        Err(err) => library_error(err),
+    };
+}
+
+fn download_file(name: String, library: Arc<Library>) -> impl warp::Reply {
+    return match library.read(&name) {
+        Ok(source) => warp::reply::with_status(source, StatusCode::OK),
+        Err(err) => {
+            let status = match err {
+                LibraryError::InvalidName => StatusCode::BAD_REQUEST,
+                LibraryError::NotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+
+            warp::reply::with_status(err.to_string(), status)
+        }
+    };
+}
+
+fn delete_file(name: String, library: Arc<Library>) -> JsonReply {
+    return match library.delete(&name) {
+        Ok(()) => json_reply(&(), StatusCode::OK),
+        Err(err) => library_error(err),
+    };
+}
+
+fn run_file(name: String,
+            controller: Arc<Mutex<controller::Controller>>,
+            jobs: Arc<Mutex<Option<Job>>>,
+            library: Arc<Library>) -> impl warp::Reply {
+    return match library.read(&name) {
+        Ok(source) => run_job(controller, jobs, &name, &source),
+        Err(err) => library_error(err),
+    };
+}
+
+fn job_status(jobs: Arc<Mutex<Option<Job>>>) -> JsonReply {
+    return match *jobs.lock().unwrap() {
+        Some(ref job) => json_reply(&JobProgress::new(job.name(), &job.progress()), StatusCode::OK),
+        None => json_reply(&ErrorMessage::new("No job"), StatusCode::NOT_FOUND),
+    };
+}
+
+fn control_job(action: String, jobs: Arc<Mutex<Option<Job>>>) -> JsonReply {
+    let jobs = jobs.lock().unwrap();
+
+    let job = match *jobs {
+        Some(ref job) => job,
+        None => return json_reply(&ErrorMessage::new("No job"), StatusCode::NOT_FOUND),
+    };
+
+    match action.as_str() {
+        "pause" => job.pause(),
+        "resume" => job.resume(),
+        "abort" => job.abort(),
+        _ => return json_reply(&ErrorMessage::new("Unknown action"), StatusCode::NOT_FOUND),
+    }
+
+    return json_reply(&JobProgress::new(job.name(), &job.progress()), StatusCode::OK);
+}
+
+fn job_progress(jobs: Arc<Mutex<Option<Job>>>, ws: warp::ws::Ws2) -> impl warp::Reply {
+    return ws.on_upgrade(move |socket| {
+        let (sink, _) = socket.split();
+
+        // Send the current progress followed by all updates until the job finishes
+        let progress = jobs.lock().unwrap().as_ref()
+            .map(|job| {
+                let name = job.name().to_owned();
+
+                return stream::once(Ok(job.progress()))
+                    .chain(job.updates())
+                    .map(move |progress| {
+                        let progress = JobProgress::new(&name, &progress);
+                        let progress = serde_json::to_string(&progress).unwrap();
+
+                        return warp::ws::Message::text(progress);
+                    });
+            });
+
+        return sink
+            .sink_map_err(|err| log::warn!("Socket closed: {}", err) )
+            .send_all(stream::iter_ok::<_, ()>(progress).flatten())
+            .map(|_| ());
+    });
+}
+
 pub fn serve(config: &ServerConfig,
-             controller: Arc<Mutex<controller::Controller>>) -> impl Future<Item=(), Error=()> {
+             controller: Arc<Mutex<controller::Controller>>,
+             library: Arc<Library>) -> impl Future<Item=(), Error=()> {
     let controller = warp::any().map(move || controller.clone());
+    let library = warp::any().map(move || library.clone());
+
+    let jobs = Arc::new(Mutex::new(None));
+    let jobs = warp::any().map(move || jobs.clone());
 
     let info = warp::get2()
         .and(warp::path("info"))
@@ -128,8 +1314,166 @@ pub fn serve(config: &ServerConfig,
         .and(warp::ws2())
         .map(state);
 
+    let console = warp::path("console")
+        .and(controller.clone())
+        .and(warp::ws2())
+        .map(console);
+
+    let preflight = warp::post2()
+        .and(warp::path("preflight"))
+        .and(controller.clone())
+        .and(warp::body::content_length_limit(MAX_PROGRAM_SIZE))
+        .and(warp::body::concat())
+        .map(preflight);
+
+    let job_start = warp::post2()
+        .and(warp::path("job"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(jobs.clone())
+        .and(warp::body::content_length_limit(MAX_PROGRAM_SIZE))
+        .and(warp::body::concat())
+        .map(start_job);
+
+    let job_status = warp::get2()
+        .and(warp::path("job"))
+        .and(warp::path::end())
+        .and(jobs.clone())
+        .map(job_status);
+
+    let job_progress = warp::path("job")
+        .and(warp::path("progress"))
+        .and(jobs.clone())
+        .and(warp::ws2())
+        .map(job_progress);
+
+    let job_control = warp::post2()
+        .and(warp::path("job"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(jobs.clone())
+        .map(control_job);
+
+    let parameters_get = warp::get2()
+        .and(warp::path("parameters"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .map(parameters);
+
+    let parameters_set = warp::put2()
+        .and(warp::path("parameters"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(jobs.clone())
+        .and(warp::body::json())
+        .and_then(set_coordinate_system);
+
+    let settings_list = warp::get2()
+        .and(warp::path("settings"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .map(settings);
+
+    let settings_write = warp::put2()
+        .and(warp::path("settings"))
+        .and(warp::path::param::<u8>())
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(warp::body::json())
+        .and_then(write_setting);
+
+    let settings_export = warp::get2()
+        .and(warp::path("settings"))
+        .and(warp::path("export"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and_then(export_settings);
+
+    let settings_import = warp::post2()
+        .and(warp::path("settings"))
+        .and(warp::path("import"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(warp::body::content_length_limit(MAX_SETTINGS_SIZE))
+        .and(warp::body::concat())
+        .and_then(import_settings);
+
+    let machine_realtime = warp::post2()
+        .and(warp::path("machine"))
+        .and(warp::path("realtime"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(jobs.clone())
+        .and(warp::body::json())
+        .map(realtime);
+
+    let machine_realtime_socket = warp::path("machine")
+        .and(warp::path("realtime"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(jobs.clone())
+        .and(warp::ws2())
+        .map(realtime_socket);
+
+    let machine_control = warp::post2()
+        .and(warp::path("machine"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(jobs.clone())
+        .and_then(control_machine);
+
+    let files_list = warp::get2()
+        .and(warp::path("files"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(library.clone())
+        .map(list_files);
+
+    let file_upload = warp::post2()
+        .and(warp::path("files"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(library.clone())
+        .and(warp::body::content_length_limit(MAX_PROGRAM_SIZE))
+        .and(warp::body::concat())
+        .map(upload_file);
+
+    let file_download = warp::get2()
+        .and(warp::path("files"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(library.clone())
+        .map(download_file);
+
+    let file_delete = warp::delete2()
+        .and(warp::path("files"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(library.clone())
+        .map(delete_file);
+
+    let file_run = warp::post2()
+        .and(warp::path("files"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("run"))
+        .and(warp::path::end())
+        .and(controller.clone())
+        .and(jobs.clone())
+        .and(library.clone())
+        .map(run_file);
+
     let api = warp::path("api")
-        .and(info.or(state))
+        .and(info.or(state).or(console).or(preflight)
+            .or(job_start).or(job_status).or(job_progress).or(job_control)
+            .or(parameters_get).or(parameters_set)
+            .or(settings_list).or(settings_write).or(settings_export).or(settings_import)
+            .or(machine_realtime).or(machine_realtime_socket).or(machine_control)
+            .or(files_list).or(file_upload).or(file_download).or(file_delete).or(file_run))
         .with(warp::log("carbide::server::api"));
 
     let fs = warp::fs::dir("web")
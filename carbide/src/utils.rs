@@ -59,4 +59,47 @@ pub mod stream {
             }
         }
     }
+    pub mod subscribers {
+        use std::sync::{Arc, Mutex};
+
+        use super::*;
+
+        // Fans out items to a changing set of subscribers. Other than `Broadcast`, subscribers can come and go at any
+        // time and publishing never blocks.
+        pub struct Subscribers<I>
+            where I: Clone {
+            senders: Arc<Mutex<Vec<mpsc::UnboundedSender<I>>>>,
+        }
+
+        impl <I> Clone for Subscribers<I>
+            where I: Clone {
+            fn clone(&self) -> Self {
+                return Self {
+                    senders: self.senders.clone(),
+                };
+            }
+        }
+
+        impl <I> Subscribers<I>
+            where I: Clone {
+
+            pub fn new() -> Self {
+                return Self {
+                    senders: Arc::new(Mutex::new(Vec::new())),
+                };
+            }
+
+            pub fn subscribe(&self) -> mpsc::UnboundedReceiver<I> {
+                let (tx, rx) = mpsc::unbounded();
+                self.senders.lock().unwrap().push(tx);
+                return rx;
+            }
+
+            pub fn publish(&self, item: I) {
+                // Drop subscribers which went away
+                self.senders.lock().unwrap()
+                    .retain(|tx| tx.unbounded_send(item.clone()).is_ok());
+            }
+        }
+    }
 }